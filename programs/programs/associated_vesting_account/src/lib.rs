@@ -1,63 +1,31 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
-    declare_id, entrypoint,
+    clock::Clock,
     entrypoint::ProgramResult,
-    instruction::{AccountMeta, Instruction},
     msg,
     program::{invoke, invoke_signed},
-    program_error::PrintProgramError,
+    program_error::{PrintProgramError, ProgramError},
+    program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
-    system_instruction,
+    system_instruction, system_program,
+    sysvar::Sysvar,
 };
 
-use vesting::*;
-
-use thiserror::Error;
-
-declare_id!("");
-entrypoint!(ava_entrypoint);
-
-pub(crate) fn get_associated_vesting_address_and_bump_seed(
-    wallet_address: &Pubkey,
-    mint: &Pubkey,
-    program_id: &Pubkey,
-    vesting_program: &Pubkey,
-) -> (Pubkey, u8) {
-    get_associated_vesting_address_and_bump_seed_internal(
-        wallet_address,
-        mint,
-        program_id,
-        vesting_program,
-    )
-}
-
-pub fn get_associated_vesting_address(wallet_address: &Pubkey, mint: &Pubkey) -> Pubkey {
-    get_associated_vesting_address_with_program_id(wallet_address, mint)
-}
+use vesting::{
+    error::ErrorCode,
+    event::{EventData, VestingEvent},
+    state::VestingState,
+};
 
-pub fn get_associated_vesting_address_with_program_id(
-    wallet_address: &Pubkey,
-    mint: &Pubkey,
-) -> Pubkey {
-    get_associated_vesting_address_and_bump_seed(wallet_address, mint, vesting::id(), id()).0
-}
+// The address derivation lives in the vesting program so that it can verify
+// associated vesting accounts without depending on this crate.
+pub use vesting::associated::{
+    check_id, get_associated_vesting_address, get_associated_vesting_address_and_bump_seed, id, ID,
+};
 
-pub fn get_associated_vesting_address_and_bump_seed_internal(
-    wallet_address: &Pubkey,
-    mint: &Pubkey,
-    program_id: &Pubkey,
-    vesting_program: &Pubkey,
-) -> (Pubkey, u8) {
-    Pubkey::find_program_address(
-        &[
-            wallet_address.as_ref(),
-            vesting_program.as_ref(),
-            mint.as_ref(),
-        ],
-        program_id,
-    )
-}
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(ava_entrypoint);
 
 pub fn create_pda_account<'a>(
     payer: &AccountInfo<'a>,
@@ -136,16 +104,14 @@ pub struct Processor;
 
 impl Processor {
     pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
-        let ix = if input.is_empty() {
-            AssociatedVestingIx::Create
-        } else {
-            AssociatedVestingIx::try_from_slice(input).map_err(|_| InvalidInstruction)?
+        let ix = match data {
+            [] | [0] => AssociatedVestingIx::Create,
+            _ => return Err(ErrorCode::InvalidInstruction.into()),
         };
         match ix {
             AssociatedVestingIx::Create => {
                 Self::process_create_associated_vesting_account(program_id, accounts)?
             }
-            _ => return Err(ProgramError::InvalidArgument),
         }
 
         Ok(())
@@ -160,42 +126,63 @@ impl Processor {
         let payer = next_account_info(accounts_iter)?;
         let wallet = next_account_info(accounts_iter)?;
         let mint = next_account_info(accounts_iter)?;
-        let vesting_account = next_account_info(accounts_iter)?;
+        let associated_vesting_account = next_account_info(accounts_iter)?;
         let vesting_program = next_account_info(accounts_iter)?;
         let system_program = next_account_info(accounts_iter)?;
 
         let rent = Rent::get()?;
 
-        let (associated_vesting_address, bump_seed) = get_associated_vest_and_bump_seed_internal(
+        if *vesting_program.key != vesting::id() {
+            msg!("Incorrect vesting program ID");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Vesting vaults may hold tokens of either token program.
+        if *mint.owner != spl_token::id() && *mint.owner != spl_token_2022::id() {
+            msg!("Mint must be owned by the token program or Token-2022");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (associated_vesting_address, bump_seed) = get_associated_vesting_address_and_bump_seed(
             wallet.key,
             mint.key,
+            vesting_program.key,
             program_id,
-            vesting_program,
         );
 
-        if associated_vesting_address != *associated_vesting_address.key {
-            return Err(InvalidSeeds);
+        if associated_vesting_address != *associated_vesting_account.key {
+            return Err(ProgramError::InvalidSeeds);
         }
 
         if *associated_vesting_account.owner != system_program::id() {
-            return Err(IllegalOwner);
+            return Err(ProgramError::IllegalOwner);
         }
 
         let associated_vesting_account_signer_seeds: &[&[_]] = &[
-            wallet.as_ref(),
-            vesting_program.as_ref(),
-            mint.as_ref(),
+            wallet.key.as_ref(),
+            vesting_program.key.as_ref(),
+            mint.key.as_ref(),
             &[bump_seed],
         ];
 
         create_pda_account(
             payer,
             &rent,
-            vesting::state::VestingState::LEN,
+            VestingState::LEN,
             vesting_program.key,
             system_program,
             associated_vesting_account,
             associated_vesting_account_signer_seeds,
         )?;
+
+        VestingEvent::AssociatedAccountCreated(EventData {
+            account: *associated_vesting_account.key,
+            amount: 0,
+            timestamp: Clock::get()?.unix_timestamp as u64,
+            outstanding: 0,
+        })
+        .emit();
+
+        Ok(())
     }
 }
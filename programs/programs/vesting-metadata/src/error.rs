@@ -15,6 +15,16 @@ pub enum ErrorCode {
     InvalidInstruction,
     #[error("Remaining Balance")]
     RemainingBalance,
+    #[error("Unsupported Version")]
+    UnsupportedVersion,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Invalid Program Address")]
+    InvalidProgramAddress,
+    #[error("Plan In Use")]
+    PlanInUse,
+    #[error("Plan Superseded")]
+    PlanSuperseded,
 }
 
 impl From<ErrorCode> for ProgramError {
@@ -37,6 +47,15 @@ impl PrintProgramError for ErrorCode {
         match self {
             ErrorCode::InvalidInstruction => msg!("Invalid Instruction."),
             ErrorCode::RemainingBalance => msg!("Account has remaining balance."),
+            ErrorCode::UnsupportedVersion => msg!("The account layout version is not supported."),
+            ErrorCode::Unauthorized => msg!("The signer is not the metadata authority."),
+            ErrorCode::InvalidProgramAddress => {
+                msg!("The metadata account is not derived from its authority and vault.")
+            }
+            ErrorCode::PlanInUse => {
+                msg!("The terms of a plan only change with a new version.")
+            }
+            ErrorCode::PlanSuperseded => msg!("A newer version of the plan exists."),
         }
     }
 }
@@ -0,0 +1,135 @@
+//! Events logged with `sol_log_data` whenever a metadata account changes.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program::{log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+
+use std::convert::TryInto;
+
+use crate::state::{METADATA_DISCRIMINATOR, PK_LEN};
+
+const CREATED: u8 = 0;
+const UPDATED: u8 = 1;
+const DELETED: u8 = 2;
+
+const EV_DISC: usize = 0;
+const EV_TAG: usize = EV_DISC + 1;
+const EV_ACCOUNT: usize = EV_TAG + 1;
+const EV_AUTH: usize = EV_ACCOUNT + PK_LEN;
+const EV_TS: usize = EV_AUTH + PK_LEN;
+const EV_LEN: usize = EV_TS + 8;
+
+/// Prefix of the log lines written by `sol_log_data`.
+pub const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EventData {
+    /// The metadata account that changed.
+    pub account: Pubkey,
+    /// Authority of the metadata account.
+    pub authority: Pubkey,
+    /// Cluster time of the change.
+    pub timestamp: u64,
+}
+
+/// Events start with `METADATA_DISCRIMINATOR` followed by a one byte tag and
+/// the `EventData` fields.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetadataEvent {
+    Created(EventData),
+    Updated(EventData),
+    Deleted(EventData),
+}
+
+impl MetadataEvent {
+    pub fn data(&self) -> &EventData {
+        match self {
+            Self::Created(data) | Self::Updated(data) | Self::Deleted(data) => data,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(EV_LEN);
+        buf.push(METADATA_DISCRIMINATOR);
+        buf.push(match self {
+            Self::Created(_) => CREATED,
+            Self::Updated(_) => UPDATED,
+            Self::Deleted(_) => DELETED,
+        });
+
+        let data = self.data();
+        buf.extend_from_slice(data.account.as_ref());
+        buf.extend_from_slice(data.authority.as_ref());
+        buf.extend_from_slice(&data.timestamp.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != EV_LEN || data[EV_DISC] != METADATA_DISCRIMINATOR {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let account = Pubkey::new_from_array(data[EV_ACCOUNT..EV_AUTH].try_into().unwrap());
+        let authority = Pubkey::new_from_array(data[EV_AUTH..EV_TS].try_into().unwrap());
+        let timestamp = u64::from_le_bytes(data[EV_TS..].try_into().unwrap());
+        let event_data = EventData {
+            account,
+            authority,
+            timestamp,
+        };
+
+        Ok(match data[EV_TAG] {
+            CREATED => Self::Created(event_data),
+            UPDATED => Self::Updated(event_data),
+            DELETED => Self::Deleted(event_data),
+            _ => return Err(ProgramError::InvalidAccountData),
+        })
+    }
+
+    /// Logs the event as a single `sol_log_data` field.
+    pub fn emit(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+
+    /// Decodes a single log message. Anything but a metadata event, including
+    /// data logged by other programs, gives `None`.
+    pub fn from_log(log: &str) -> Option<Self> {
+        let field = log.strip_prefix(PROGRAM_DATA_PREFIX)?;
+        let data = STANDARD.decode(field.trim()).ok()?;
+        Self::unpack(&data).ok()
+    }
+
+    /// Decodes every metadata event in the log messages of a transaction, in
+    /// the order they were logged.
+    pub fn from_logs<S: AsRef<str>>(logs: &[S]) -> Vec<Self> {
+        logs.iter()
+            .filter_map(|log| Self::from_log(log.as_ref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_logs() {
+        let data = EventData {
+            account: Pubkey::new_unique(),
+            authority: Pubkey::new_unique(),
+            timestamp: 1_706_702_400,
+        };
+        let created = MetadataEvent::Created(data);
+        let deleted = MetadataEvent::Deleted(data);
+        let mut unknown = created.pack();
+        unknown[EV_TAG] = 0xff;
+
+        let logs = vec![
+            format!("{}{}", PROGRAM_DATA_PREFIX, STANDARD.encode(created.pack())),
+            format!("{}{}", PROGRAM_DATA_PREFIX, STANDARD.encode(unknown)),
+            "Program log: Entrypoint: Vesting Metadata".to_string(),
+            format!("{}{}", PROGRAM_DATA_PREFIX, STANDARD.encode(deleted.pack())),
+        ];
+
+        assert_eq!(MetadataEvent::from_logs(&logs), vec![created, deleted]);
+    }
+}
@@ -3,16 +3,23 @@ use solana_program::{
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_program,
 };
 
 use std::convert::TryInto;
 use std::mem::size_of;
 
-use crate::{error::ErrorCode::InvalidInstruction, state::PK_LEN};
+use crate::{
+    error::ErrorCode::InvalidInstruction,
+    state::{RewardBasis, PK_LEN},
+};
 
 const CREATE: u8 = 0;
 const UPDATE: u8 = 1;
 const DELETE: u8 = 2;
+const MIGRATE: u8 = 3;
+const COMMIT: u8 = 4;
+const RELEASE: u8 = 5;
 
 const IX_AUTH: usize = 0;
 const IX_VAULT: usize = PK_LEN;
@@ -21,22 +28,37 @@ const IX_APR: usize = IX_DURA + 8;
 const IX_WTL: usize = IX_APR + 8;
 const IX_FEE: usize = IX_WTL + 8;
 const IX_LIFE: usize = IX_FEE + 8;
+const IX_BASIS: usize = IX_LIFE + 8;
+const IX_SWEEP: usize = IX_BASIS + 1;
+const IX_GRACE: usize = IX_SWEEP + 1;
+const IX_TREAS: usize = IX_GRACE + 8;
 
 pub struct IxCtx {
     /// Authority must match the authority for the provided pool and vault
     pub authority: Pubkey,
-    /// The token account for staked tokens
+    /// The token account for staked tokens, also the vault rewards are paid
+    /// from
     pub vault: Pubkey,
     /// the amount of time required to elapse before rewards are fully realised
     pub duration: u64,
     /// the percentage of interest generated over 12 months
     pub apr: u64,
-    ///
+    /// the time a withdrawal request must wait before it can be completed
     pub withdrawal_timelock: u64,
-    ///
+    /// the fee charged for withdrawing before the end of the duration, in
+    /// basis points
     pub early_withdrawal_fee: u64,
-    ///
+    /// how long the metadata remains valid for, zero for no limit
     pub lifetime: u64,
+    /// the balance `apr` is paid on
+    pub reward_basis: RewardBasis,
+    /// whether unclaimed vested balances may be swept back to grantors once
+    /// the lifetime has passed
+    pub sweepable: bool,
+    /// the time after expiry before sweeping is possible
+    pub sweep_grace_period: u64,
+    /// the token account early withdrawal fees are paid into
+    pub treasury: Pubkey,
 }
 
 impl IxCtx {
@@ -49,6 +71,10 @@ impl IxCtx {
         buf.extend_from_slice(&self.withdrawal_timelock.to_le_bytes());
         buf.extend_from_slice(&self.early_withdrawal_fee.to_le_bytes());
         buf.extend_from_slice(&self.lifetime.to_le_bytes());
+        buf.push(self.reward_basis as u8);
+        buf.push(self.sweepable as u8);
+        buf.extend_from_slice(&self.sweep_grace_period.to_le_bytes());
+        buf.extend_from_slice(self.treasury.as_ref());
         buf
     }
 
@@ -90,11 +116,34 @@ impl IxCtx {
             .ok_or(InvalidInstruction)?;
 
         let lifetime = data
-            .get(IX_LIFE..)
+            .get(IX_LIFE..IX_BASIS)
             .and_then(|s| s.try_into().ok())
             .map(u64::from_le_bytes)
             .ok_or(InvalidInstruction)?;
 
+        let reward_basis = data
+            .get(IX_BASIS)
+            .and_then(|basis| RewardBasis::from_u8(*basis))
+            .ok_or(InvalidInstruction)?;
+
+        let sweepable = match data.get(IX_SWEEP) {
+            Some(0) => false,
+            Some(1) => true,
+            _ => return Err(InvalidInstruction.into()),
+        };
+
+        let sweep_grace_period = data
+            .get(IX_GRACE..IX_TREAS)
+            .and_then(|s| s.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(InvalidInstruction)?;
+
+        let treasury = data
+            .get(IX_TREAS..)
+            .and_then(|s| s.try_into().ok())
+            .map(Pubkey::new_from_array)
+            .ok_or(InvalidInstruction)?;
+
         Ok(Self {
             authority,
             vault,
@@ -103,17 +152,74 @@ impl IxCtx {
             withdrawal_timelock,
             early_withdrawal_fee,
             lifetime,
+            reward_basis,
+            sweepable,
+            sweep_grace_period,
+            treasury,
         })
     }
 }
 
 pub enum MetadataInstruction {
-    /// Accounts expected for all instructions:
-    /// `[s]` Authority
+    /// Creates the metadata account of the authority for `vault`, at the
+    /// address returned by `state::find_metadata_address`. The authority
+    /// pays its rent.
+    ///
+    /// Accounts expected:
+    /// `[s,w]` Authority
     /// `[w]` Metadata account
+    /// `[]` System Program
     Create(IxCtx),
+    /// Creates the next version of a plan with new terms, at the address
+    /// returned by `state::find_plan_version_address`. The current version
+    /// keeps its terms for the grants signed under it and only records its
+    /// successor, after which it takes no new grants. The authority and vault
+    /// cannot change, they make up the address. The authority pays the rent.
+    ///
+    /// Without the New Version the terms are replaced in place. Only
+    /// `lifetime` may change that way, and not at all for sweepable plans.
+    /// Every other change needs a new version.
+    ///
+    /// Accounts expected:
+    /// `[s,w]` Authority
+    /// `[w]` Metadata account
+    /// `[w]` New Version, optional
+    /// `[]` System Program, with the New Version
     Update(IxCtx),
-    Delete(IxCtx),
+    /// Zeroes a metadata account and refunds its lamports to the authority.
+    /// Fails with `RemainingBalance` while vestings reference the plan and
+    /// with `PlanSuperseded` for versions that have a successor, so versions
+    /// are deleted from the latest down. Deleting a later version makes the
+    /// Previous Version the latest again, its address is reused by the next
+    /// `Update`.
+    ///
+    /// Accounts expected:
+    /// `[s,w]` Authority
+    /// `[w]` Metadata account
+    /// `[w]` Previous Version, for versions created by `Update`
+    Delete,
+    /// Rewrites a metadata account in the current `MetadataState` layout,
+    /// resizing it and topping up its rent from the payer.
+    ///
+    /// Accounts expected:
+    /// `[s,w]` Payer
+    /// `[w]` Metadata account
+    /// `[]` System Program
+    Migrate,
+    /// Counts `vestings` new vesting accounts on the plan and adds `amount`
+    /// to its total commitment. Only the vesting program can sign for the
+    /// registrar, see `state::find_registrar_address`.
+    ///
+    /// Accounts expected:
+    /// `[s]` Registrar
+    /// `[w]` Metadata account
+    Commit { vestings: u64, amount: u64 },
+    /// Takes `vestings` closed vesting accounts and `amount` off the
+    /// counters of the plan, the reverse of `Commit`. Releasing more than
+    /// was committed fails.
+    ///
+    /// Accounts expected are the same as `Commit`.
+    Release { vestings: u64, amount: u64 },
 }
 
 impl MetadataInstruction {
@@ -129,11 +235,18 @@ impl MetadataInstruction {
                 buf.push(UPDATE);
                 buf.extend_from_slice(&ixctx.pack());
             }
-            Self::Delete(ixctx) => {
-                buf.push(DELETE);
-                buf.extend_from_slice(&ixctx.pack());
+            Self::Delete => buf.push(DELETE),
+            Self::Migrate => buf.push(MIGRATE),
+            Self::Commit { vestings, amount } => {
+                buf.push(COMMIT);
+                buf.extend_from_slice(&vestings.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Release { vestings, amount } => {
+                buf.push(RELEASE);
+                buf.extend_from_slice(&vestings.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
             }
-            _ => unreachable!(),
         }
 
         buf
@@ -143,14 +256,32 @@ impl MetadataInstruction {
         let (tag, rest) = data.split_first().ok_or(InvalidInstruction)?;
 
         Ok(match *tag {
-            CREATE => Self::Create(IxCtx::unpack(&rest)?),
-            UPDATE => Self::Update(IxCtx::unpack(&rest)?),
-            DELETE => Self::Delete(IxCtx::unpack(&rest)?),
-            _ => unreachable!(),
+            CREATE => Self::Create(IxCtx::unpack(rest)?),
+            UPDATE => Self::Update(IxCtx::unpack(rest)?),
+            DELETE => Self::Delete,
+            MIGRATE => Self::Migrate,
+            COMMIT => {
+                let (vestings, amount) = unpack_counts(rest)?;
+                Self::Commit { vestings, amount }
+            }
+            RELEASE => {
+                let (vestings, amount) = unpack_counts(rest)?;
+                Self::Release { vestings, amount }
+            }
+            _ => return Err(InvalidInstruction.into()),
         })
     }
 }
 
+fn unpack_counts(data: &[u8]) -> Result<(u64, u64), ProgramError> {
+    if data.len() != 16 {
+        return Err(InvalidInstruction.into());
+    }
+    let vestings = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let amount = u64::from_le_bytes(data[8..].try_into().unwrap());
+    Ok((vestings, amount))
+}
+
 pub struct EndpointCtx<'a> {
     program_id: &'a Pubkey,
     tx_auth: &'a Pubkey,
@@ -162,6 +293,10 @@ pub struct EndpointCtx<'a> {
     withdrawal_timelock: u64,
     early_withdrawal_fee: u64,
     lifetime: u64,
+    reward_basis: RewardBasis,
+    sweepable: bool,
+    sweep_grace_period: u64,
+    treasury: &'a Pubkey,
 }
 
 /// Endpoints
@@ -171,6 +306,7 @@ pub fn create(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
     let accounts = vec![
         AccountMeta::new(*ctx.tx_auth, true),
         AccountMeta::new(*ctx.metadata, false),
+        AccountMeta::new_readonly(system_program::id(), false),
     ];
 
     let data = MetadataInstruction::Create(IxCtx {
@@ -181,6 +317,10 @@ pub fn create(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
         withdrawal_timelock: ctx.withdrawal_timelock,
         early_withdrawal_fee: ctx.early_withdrawal_fee,
         lifetime: ctx.lifetime,
+        reward_basis: ctx.reward_basis,
+        sweepable: ctx.sweepable,
+        sweep_grace_period: ctx.sweep_grace_period,
+        treasury: *ctx.treasury,
     })
     .pack();
 
@@ -191,13 +331,17 @@ pub fn create(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
     })
 }
 
-pub fn update(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
+pub fn update(ctx: EndpointCtx, new_version: Option<&Pubkey>) -> Result<Instruction, ProgramError> {
     msg!("Vesting Metadata: Update");
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*ctx.tx_auth, true),
         AccountMeta::new(*ctx.metadata, false),
     ];
+    if let Some(new_version) = new_version {
+        accounts.push(AccountMeta::new(*new_version, false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
 
     let data = MetadataInstruction::Update(IxCtx {
         authority: *ctx.authority,
@@ -207,6 +351,10 @@ pub fn update(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
         withdrawal_timelock: ctx.withdrawal_timelock,
         early_withdrawal_fee: ctx.early_withdrawal_fee,
         lifetime: ctx.lifetime,
+        reward_basis: ctx.reward_basis,
+        sweepable: ctx.sweepable,
+        sweep_grace_period: ctx.sweep_grace_period,
+        treasury: *ctx.treasury,
     })
     .pack();
 
@@ -217,27 +365,91 @@ pub fn update(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
     })
 }
 
-pub fn delete(ctx: EndpointCtx) -> Result<Instruction, ProgramError> {
+pub fn delete(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    metadata: &Pubkey,
+    previous_version: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
     msg!("Vesting Metadata: Delete");
 
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*metadata, false),
+    ];
+    if let Some(previous_version) = previous_version {
+        accounts.push(AccountMeta::new(*previous_version, false));
+    }
+
+    let data = MetadataInstruction::Delete.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn migrate(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    metadata: &Pubkey,
+    system_program: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting Metadata: Migrate");
+
     let accounts = vec![
-        AccountMeta::new(*ctx.tx_auth, true),
-        AccountMeta::new(*ctx.metadata, false),
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new_readonly(*system_program, false),
     ];
 
-    let data = MetadataInstruction::Delete(IxCtx {
-        authority: *ctx.authority,
-        vault: *ctx.vault,
-        duration: ctx.duration,
-        apr: ctx.apr,
-        withdrawal_timelock: ctx.withdrawal_timelock,
-        early_withdrawal_fee: ctx.early_withdrawal_fee,
-        lifetime: ctx.lifetime,
+    let data = MetadataInstruction::Migrate.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
     })
-    .pack();
+}
+
+pub fn commit(
+    program_id: &Pubkey,
+    registrar: &Pubkey,
+    metadata: &Pubkey,
+    vestings: u64,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new_readonly(*registrar, true),
+        AccountMeta::new(*metadata, false),
+    ];
+
+    let data = MetadataInstruction::Commit { vestings, amount }.pack();
 
     Ok(Instruction {
-        program_id: *ctx.program_id,
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn release(
+    program_id: &Pubkey,
+    registrar: &Pubkey,
+    metadata: &Pubkey,
+    vestings: u64,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new_readonly(*registrar, true),
+        AccountMeta::new(*metadata, false),
+    ];
+
+    let data = MetadataInstruction::Release { vestings, amount }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
         accounts,
         data,
     })
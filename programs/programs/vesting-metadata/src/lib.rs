@@ -1,6 +1,16 @@
 pub mod error;
+pub mod event;
 pub mod instruction;
 pub mod processor;
 pub mod state;
 
+#[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
+
+solana_program::declare_id!("SCYGyVRR45ytWfuQGJXkY1RtkXTX1GDA6SaxuyW5ZKG");
+
+/// The vesting program, the only one that may `Commit` and `Release` grants
+/// of a plan.
+pub mod vesting_program {
+    solana_program::declare_id!("SCYQKBoArPUXrAykFDX2XVp21kfrVe7mxoVmgrVsKLv");
+}
@@ -1,17 +1,26 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction,
+    rent::Rent,
+    system_instruction::{self, create_account},
+    sysvar::Sysvar,
 };
 
 use crate::{
     error::ErrorCode,
+    event::{EventData, MetadataEvent},
     instruction::{IxCtx, MetadataInstruction},
-    state::MetadataState,
+    state::{
+        find_metadata_address, find_plan_version_address, find_registrar_address, MetadataState,
+        METADATA_VERSION,
+    },
+    vesting_program,
 };
 
 pub struct Processor;
@@ -27,8 +36,13 @@ impl Processor {
             MetadataInstruction::Update(ix_ctx) => {
                 Self::process_update(program_id, accounts, ix_ctx)?
             }
-            MetadataInstruction::Delete(ix_ctx) => {
-                Self::process_delete(program_id, accounts, ix_ctx)?
+            MetadataInstruction::Delete => Self::process_delete(program_id, accounts)?,
+            MetadataInstruction::Migrate => Self::process_migrate(program_id, accounts)?,
+            MetadataInstruction::Commit { vestings, amount } => {
+                Self::process_count(program_id, accounts, vestings, amount, true)?
+            }
+            MetadataInstruction::Release { vestings, amount } => {
+                Self::process_count(program_id, accounts, vestings, amount, false)?
             }
         }
 
@@ -36,7 +50,7 @@ impl Processor {
     }
 
     fn process_create(
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
         accounts: &[AccountInfo],
         ix_ctx: IxCtx,
     ) -> Result<(), ProgramError> {
@@ -44,8 +58,41 @@ impl Processor {
 
         let authority = next_account_info(accounts_iter)?;
         let metadata_account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if ix_ctx.authority != *authority.key {
+            msg!("Authority does not match the signer");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let (metadata_address, bump) =
+            find_metadata_address(program_id, authority.key, &ix_ctx.vault);
+        if metadata_address != *metadata_account.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        invoke_signed(
+            &create_account(
+                authority.key,
+                metadata_account.key,
+                Rent::get()?.minimum_balance(MetadataState::LEN),
+                MetadataState::LEN as u64,
+                program_id,
+            ),
+            &[
+                authority.clone(),
+                metadata_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[authority.key.as_ref(), ix_ctx.vault.as_ref(), &[bump]]],
+        )?;
 
         let mut metadata_data = metadata_account.data.borrow_mut();
+        let current_ts = Clock::get()?.unix_timestamp as u64;
 
         let metadata = MetadataState {
             is_initialized: true,
@@ -56,14 +103,33 @@ impl Processor {
             withdrawal_timelock: ix_ctx.withdrawal_timelock,
             early_withdrawal_fee: ix_ctx.early_withdrawal_fee,
             lifetime: ix_ctx.lifetime,
+            reward_basis: ix_ctx.reward_basis,
+            created_ts: current_ts,
+            sweepable: ix_ctx.sweepable,
+            sweep_grace_period: ix_ctx.sweep_grace_period,
+            active_vestings: 0,
+            total_committed: 0,
+            root: Pubkey::default(),
+            revision: 0,
+            previous: Pubkey::default(),
+            successor: Pubkey::default(),
+            treasury: ix_ctx.treasury,
         };
 
         metadata.pack_into_slice(&mut metadata_data);
+
+        MetadataEvent::Created(EventData {
+            account: *metadata_account.key,
+            authority: *authority.key,
+            timestamp: current_ts,
+        })
+        .emit();
+
         Ok(())
     }
 
     fn process_update(
-        _program_id: &Pubkey,
+        program_id: &Pubkey,
         accounts: &[AccountInfo],
         ix_ctx: IxCtx,
     ) -> Result<(), ProgramError> {
@@ -72,50 +138,674 @@ impl Processor {
         let authority = next_account_info(accounts_iter)?;
         let metadata_account = next_account_info(accounts_iter)?;
 
-        let mut metadata_data = metadata_account.data.borrow_mut();
+        let mut current = authorize(
+            program_id,
+            authority,
+            metadata_account,
+            &metadata_account.data.borrow(),
+        )?;
+        if current.is_superseded() {
+            return Err(ErrorCode::PlanSuperseded.into());
+        }
+        if ix_ctx.authority != current.authority || ix_ctx.vault != current.vault {
+            msg!("Authority and vault are part of the metadata address and cannot change");
+            return Err(ProgramError::InvalidArgument);
+        }
 
         let metadata = MetadataState {
-            is_initialized: true,
-            authority: *authority.key,
-            vault: ix_ctx.vault,
             duration: ix_ctx.duration,
             apr: ix_ctx.apr,
             withdrawal_timelock: ix_ctx.withdrawal_timelock,
             early_withdrawal_fee: ix_ctx.early_withdrawal_fee,
             lifetime: ix_ctx.lifetime,
+            reward_basis: ix_ctx.reward_basis,
+            sweepable: ix_ctx.sweepable,
+            sweep_grace_period: ix_ctx.sweep_grace_period,
+            treasury: ix_ctx.treasury,
+            // The lifetime keeps counting from the original creation.
+            ..current.clone()
         };
 
-        metadata.pack_into_slice(&mut metadata_data);
+        let updated = match accounts_iter.next() {
+            // Without a new version the grants on the plan read the new terms,
+            // counted or not.
+            None => {
+                if !keeps_terms(&current, &metadata) {
+                    return Err(ErrorCode::PlanInUse.into());
+                }
+                metadata.pack_into_slice(&mut metadata_account.data.borrow_mut());
+                *metadata_account.key
+            }
+            Some(new_version) => {
+                let system_program = next_account_info(accounts_iter)?;
+
+                let revision = current
+                    .revision
+                    .checked_add(1)
+                    .ok_or(ProgramError::ArithmeticOverflow)?;
+                let (version_address, bump) =
+                    find_plan_version_address(program_id, authority.key, &current.vault, revision);
+                if version_address != *new_version.key {
+                    return Err(ErrorCode::InvalidProgramAddress.into());
+                }
+
+                invoke_signed(
+                    &create_account(
+                        authority.key,
+                        new_version.key,
+                        Rent::get()?.minimum_balance(MetadataState::LEN),
+                        MetadataState::LEN as u64,
+                        program_id,
+                    ),
+                    &[
+                        authority.clone(),
+                        new_version.clone(),
+                        system_program.clone(),
+                    ],
+                    &[&[
+                        authority.key.as_ref(),
+                        current.vault.as_ref(),
+                        &revision.to_le_bytes(),
+                        &[bump],
+                    ]],
+                )?;
+
+                MetadataState {
+                    active_vestings: 0,
+                    total_committed: 0,
+                    root: current.plan_root(metadata_account.key),
+                    revision,
+                    previous: *metadata_account.key,
+                    ..metadata
+                }
+                .pack_into_slice(&mut new_version.data.borrow_mut());
+
+                current.successor = *new_version.key;
+                current.pack_into_slice(&mut metadata_account.data.borrow_mut());
+                *new_version.key
+            }
+        };
+
+        MetadataEvent::Updated(EventData {
+            account: updated,
+            authority: *authority.key,
+            timestamp: Clock::get()?.unix_timestamp as u64,
+        })
+        .emit();
+
         Ok(())
     }
 
-    fn process_delete(
-        _program_id: &Pubkey,
-        _accounts: &[AccountInfo],
-        _ix_ctx: IxCtx,
-    ) -> Result<(), ProgramError> {
-        msg!("Ok");
-        /*
+    fn process_migrate(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let payer = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+
+        if !payer.is_signer {
+            msg!("Payer must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *metadata_account.owner != *program_id {
+            msg!("Metadata program must own the metadata account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut metadata = {
+            let data = metadata_account.data.borrow();
+            match MetadataState::version(&data) {
+                Some(METADATA_VERSION) => {
+                    msg!("Metadata account is already up to date");
+                    return Ok(());
+                }
+                Some(version) => match MetadataState::versioned_len(version) {
+                    Some(len) if data.len() >= len => {
+                        MetadataState::unpack_versioned(version, &data[..len])?
+                    }
+                    _ => return Err(ErrorCode::UnsupportedVersion.into()),
+                },
+                None => return Err(ErrorCode::UnsupportedVersion.into()),
+            }
+        };
+
+        if metadata.created_ts == 0 {
+            metadata.created_ts = Clock::get()?.unix_timestamp as u64;
+        }
+        // Fees were paid back into the vault before plans had a treasury.
+        if metadata.treasury == Pubkey::default() {
+            metadata.treasury = metadata.vault;
+        }
+
+        metadata_account.realloc(MetadataState::LEN, true)?;
+
+        let required_lamports = Rent::get()?
+            .minimum_balance(MetadataState::LEN)
+            .saturating_sub(metadata_account.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, metadata_account.key, required_lamports),
+                &[
+                    payer.clone(),
+                    metadata_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        MetadataState::pack(metadata, &mut metadata_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_delete(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
         let accounts_iter = &mut accounts.iter();
 
         let authority = next_account_info(accounts_iter)?;
         let metadata_account = next_account_info(accounts_iter)?;
 
-        let metadata = metadata_account.data.borrow_mut();
-            &[0u8; MetadataState::LEN];
+        let metadata = authorize(
+            program_id,
+            authority,
+            metadata_account,
+            &metadata_account.data.borrow(),
+        )?;
+        if metadata.active_vestings != 0 {
+            return Err(ErrorCode::RemainingBalance.into());
+        }
+        if metadata.is_superseded() {
+            return Err(ErrorCode::PlanSuperseded.into());
+        }
+
+        // The previous version becomes the latest again, so that the next
+        // update recreates this address instead of skipping over it.
+        if metadata.revision > 0 {
+            let previous_account = next_account_info(accounts_iter)?;
+            if *previous_account.key != metadata.previous {
+                msg!("Previous version does not match the metadata account");
+                return Err(ProgramError::InvalidArgument);
+            }
+            let mut previous = authorize(
+                program_id,
+                authority,
+                previous_account,
+                &previous_account.data.borrow(),
+            )?;
+            previous.successor = Pubkey::default();
+            previous.pack_into_slice(&mut previous_account.data.borrow_mut());
+        }
 
-        system_instruction::transfer(
-            from_pubkey: metadata_account,
-            to_pubkey: authority,
-            lamports: metadata_account.lamports,
-        );
+        let lamports = metadata_account.lamports();
+        **authority.lamports.borrow_mut() = authority
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        **metadata_account.lamports.borrow_mut() = 0;
+        metadata_account.data.borrow_mut().fill(0);
+
+        MetadataEvent::Deleted(EventData {
+            account: *metadata_account.key,
+            authority: *authority.key,
+            timestamp: Clock::get()?.unix_timestamp as u64,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_count(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        vestings: u64,
+        amount: u64,
+        commit: bool,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let registrar = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+
+        if !registrar.is_signer {
+            msg!("Registrar must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if find_registrar_address(&vesting_program::id()).0 != *registrar.key {
+            msg!("Only the vesting program can count grants");
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        if *metadata_account.owner != *program_id {
+            msg!("Metadata program must own the metadata account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        if metadata_account.lamports != 0 {
-            return Err();
+        let mut metadata_data = metadata_account.data.borrow_mut();
+        if MetadataState::version(&metadata_data) != Some(METADATA_VERSION) {
+            msg!("Metadata account must be migrated first");
+            return Err(ErrorCode::UnsupportedVersion.into());
+        }
+        let mut metadata = MetadataState::unpack(&metadata_data)?;
+
+        if commit {
+            metadata.active_vestings = metadata
+                .active_vestings
+                .checked_add(vestings)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            metadata.total_committed = metadata
+                .total_committed
+                .checked_add(amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
         } else {
-            Ok(())
+            metadata.active_vestings = metadata
+                .active_vestings
+                .checked_sub(vestings)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            metadata.total_committed = metadata
+                .total_committed
+                .checked_sub(amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
         }
-        */
+
+        metadata.pack_into_slice(&mut metadata_data);
+
         Ok(())
     }
 }
+
+// Whether `updated` leaves the terms existing grants read unchanged. The
+// lifetime only decides when new grants stop, unless the plan sweeps.
+fn keeps_terms(current: &MetadataState, updated: &MetadataState) -> bool {
+    let lifetime_only = MetadataState {
+        lifetime: current.lifetime,
+        ..updated.clone()
+    } == *current;
+    lifetime_only && (!current.sweepable || updated.lifetime == current.lifetime)
+}
+
+// Reads a metadata account in the current layout and checks that its
+// authority signed.
+fn authorize(
+    program_id: &Pubkey,
+    authority: &AccountInfo,
+    metadata_account: &AccountInfo,
+    metadata_data: &[u8],
+) -> Result<MetadataState, ProgramError> {
+    if !authority.is_signer {
+        msg!("Authority must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if *metadata_account.owner != *program_id {
+        msg!("Metadata program must own the metadata account");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    if MetadataState::version(metadata_data) != Some(METADATA_VERSION) {
+        msg!("Metadata account must be migrated first");
+        return Err(ErrorCode::UnsupportedVersion.into());
+    }
+
+    let metadata = MetadataState::unpack(metadata_data)?;
+    if metadata.authority != *authority.key {
+        return Err(ErrorCode::Unauthorized.into());
+    }
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::RewardBasis;
+    use solana_program::{
+        entrypoint::SUCCESS,
+        program_stubs::{self, SyscallStubs},
+    };
+    use std::sync::Once;
+
+    // Sysvars for the processor, other programs are not run so created
+    // accounts only get their data.
+    struct TestSyscalls;
+
+    impl SyscallStubs for TestSyscalls {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                unix_timestamp: 1_700_000_000,
+                ..Clock::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            SUCCESS
+        }
+    }
+
+    fn set_syscalls() {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscalls));
+        });
+    }
+
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+    }
+
+    impl TestAccount {
+        fn plan(key: Pubkey, plan: &MetadataState) -> Self {
+            let mut data = vec![0; MetadataState::LEN];
+            plan.pack_into_slice(&mut data);
+            Self {
+                key,
+                owner: crate::id(),
+                lamports: 1_000,
+                data,
+            }
+        }
+
+        // An address the processor creates a plan version at.
+        fn blank(key: Pubkey) -> Self {
+            Self {
+                key,
+                owner: crate::id(),
+                lamports: 0,
+                data: vec![0; MetadataState::LEN],
+            }
+        }
+
+        fn state(&self) -> MetadataState {
+            MetadataState::unpack(&self.data).unwrap()
+        }
+    }
+
+    // Runs `process` with the accounts at `keys` in that order, the first
+    // one signing.
+    fn run(
+        accounts: &mut [TestAccount],
+        keys: &[Pubkey],
+        process: impl FnOnce(&[AccountInfo]) -> ProgramResult,
+    ) -> ProgramResult {
+        let infos: Vec<AccountInfo> = accounts
+            .iter_mut()
+            .map(|account| {
+                AccountInfo::new(
+                    &account.key,
+                    false,
+                    true,
+                    &mut account.lamports,
+                    &mut account.data,
+                    &account.owner,
+                    false,
+                    0,
+                )
+            })
+            .collect();
+        let ordered: Vec<AccountInfo> = keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| {
+                let mut info = infos
+                    .iter()
+                    .find(|info| info.key == key)
+                    .unwrap_or_else(|| panic!("missing account {}", key))
+                    .clone();
+                info.is_signer = index == 0;
+                info
+            })
+            .collect();
+        process(&ordered)
+    }
+
+    #[test]
+    fn test_keeps_terms() {
+        let current = MetadataState {
+            is_initialized: true,
+            duration: 31_536_000,
+            apr: 500,
+            lifetime: 63_072_000,
+            active_vestings: 1,
+            ..MetadataState::default()
+        };
+        let longer = MetadataState {
+            lifetime: 94_608_000,
+            ..current.clone()
+        };
+        let higher_apr = MetadataState {
+            apr: 600,
+            ..current.clone()
+        };
+
+        assert!(keeps_terms(&current, &current));
+        assert!(keeps_terms(&current, &longer));
+        assert!(!keeps_terms(&current, &higher_apr));
+
+        // Sweeping starts from the expiry, so the lifetime is a term too.
+        let sweepable = MetadataState {
+            sweepable: true,
+            ..current
+        };
+        assert!(!keeps_terms(
+            &sweepable,
+            &MetadataState {
+                lifetime: 94_608_000,
+                ..sweepable.clone()
+            }
+        ));
+    }
+
+    #[test]
+    fn test_count() {
+        let program_id = crate::id();
+        let registrar_key = find_registrar_address(&vesting_program::id()).0;
+        let metadata_key = Pubkey::new_unique();
+        let system = Pubkey::default();
+        let mut data = vec![0u8; MetadataState::LEN];
+        MetadataState {
+            is_initialized: true,
+            ..MetadataState::default()
+        }
+        .pack_into_slice(&mut data);
+
+        let mut count = |vestings, amount, commit| {
+            let (mut registrar_lamports, mut lamports) = (0, 0);
+            let mut registrar_data = [];
+            let accounts = [
+                AccountInfo::new(
+                    &registrar_key,
+                    true,
+                    false,
+                    &mut registrar_lamports,
+                    &mut registrar_data,
+                    &system,
+                    false,
+                    0,
+                ),
+                AccountInfo::new(
+                    &metadata_key,
+                    false,
+                    true,
+                    &mut lamports,
+                    &mut data,
+                    &program_id,
+                    false,
+                    0,
+                ),
+            ];
+            Processor::process_count(&program_id, &accounts, vestings, amount, commit)?;
+            let metadata = MetadataState::unpack(&accounts[1].data.borrow())?;
+            Ok((metadata.active_vestings, metadata.total_committed))
+        };
+
+        assert_eq!(count(2, 300, true), Ok((2, 300)));
+        assert_eq!(count(1, 100, false), Ok((1, 200)));
+        // Releasing more than was committed means the counters are off.
+        assert_eq!(count(2, 100, false), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(count(1, 300, false), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(count(1, 200, false), Ok((0, 0)));
+    }
+
+    #[test]
+    fn test_update_and_delete_versions() {
+        set_syscalls();
+        let program_id = crate::id();
+        let authority = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let version =
+            |revision| find_plan_version_address(&program_id, &authority, &vault, revision).0;
+        let (root_key, first_key, second_key) = (version(0), version(1), version(2));
+        let system = Pubkey::default();
+        let ctx = |apr| IxCtx {
+            authority,
+            vault,
+            duration: 31_536_000,
+            apr,
+            withdrawal_timelock: 0,
+            early_withdrawal_fee: 0,
+            lifetime: 0,
+            reward_basis: RewardBasis::default(),
+            sweepable: false,
+            sweep_grace_period: 0,
+            treasury: Pubkey::default(),
+        };
+        let root = MetadataState {
+            is_initialized: true,
+            authority,
+            vault,
+            duration: 31_536_000,
+            apr: 500,
+            created_ts: 1_600_000_000,
+            active_vestings: 1,
+            total_committed: 1_000,
+            ..MetadataState::default()
+        };
+
+        let mut accounts = vec![
+            TestAccount {
+                key: authority,
+                owner: system,
+                lamports: 0,
+                data: vec![],
+            },
+            TestAccount {
+                key: system,
+                owner: system,
+                lamports: 0,
+                data: vec![],
+            },
+            TestAccount::plan(root_key, &root),
+            TestAccount::blank(first_key),
+            TestAccount::blank(second_key),
+        ];
+        let update = |accounts: &mut [TestAccount], keys: &[Pubkey], apr| {
+            run(accounts, keys, |infos| {
+                Processor::process_update(&program_id, infos, ctx(apr))
+            })
+        };
+        let delete = |accounts: &mut [TestAccount], keys: &[Pubkey]| {
+            run(accounts, keys, |infos| {
+                Processor::process_delete(&program_id, infos)
+            })
+        };
+
+        // Grants read the terms in place, so those only change with a new
+        // version.
+        assert_eq!(
+            update(&mut accounts, &[authority, root_key], 600),
+            Err(ErrorCode::PlanInUse.into())
+        );
+        assert_eq!(
+            update(
+                &mut accounts,
+                &[authority, root_key, second_key, system],
+                600
+            ),
+            Err(ErrorCode::InvalidProgramAddress.into())
+        );
+        assert_eq!(
+            update(
+                &mut accounts,
+                &[authority, root_key, first_key, system],
+                600
+            ),
+            Ok(())
+        );
+        let first = accounts[3].state();
+        assert_eq!(accounts[2].state().successor, first_key);
+        assert_eq!(
+            (first.root, first.revision, first.previous, first.successor),
+            (root_key, 1, root_key, Pubkey::default())
+        );
+        assert_eq!((first.apr, first.created_ts), (600, root.created_ts));
+        assert_eq!((first.active_vestings, first.total_committed), (0, 0));
+        assert_eq!(
+            update(
+                &mut accounts,
+                &[authority, root_key, second_key, system],
+                700
+            ),
+            Err(ErrorCode::PlanSuperseded.into())
+        );
+
+        assert_eq!(
+            update(
+                &mut accounts,
+                &[authority, first_key, second_key, system],
+                700
+            ),
+            Ok(())
+        );
+        let second = accounts[4].state();
+        assert_eq!(accounts[3].state().successor, second_key);
+        assert_eq!(
+            (second.root, second.revision, second.previous, second.apr),
+            (root_key, 2, first_key, 700)
+        );
+
+        // Versions are deleted from the latest down, each one handing the
+        // plan back to the one before it.
+        assert_eq!(
+            delete(&mut accounts, &[authority, first_key, root_key]),
+            Err(ErrorCode::PlanSuperseded.into())
+        );
+        assert_eq!(
+            delete(&mut accounts, &[authority, second_key, root_key]),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            delete(&mut accounts, &[authority, second_key, first_key]),
+            Ok(())
+        );
+        assert!(accounts[4].data.iter().all(|byte| *byte == 0));
+        assert!(!accounts[3].state().is_superseded());
+
+        // The next update creates the deleted address again.
+        assert_eq!(
+            update(
+                &mut accounts,
+                &[authority, first_key, second_key, system],
+                800
+            ),
+            Ok(())
+        );
+        assert_eq!(accounts[4].state().apr, 800);
+        assert_eq!(accounts[3].state().successor, second_key);
+
+        // Even a version no grant counts yet keeps its terms.
+        assert_eq!(
+            update(&mut accounts, &[authority, second_key], 900),
+            Err(ErrorCode::PlanInUse.into())
+        );
+
+        assert_eq!(
+            delete(&mut accounts, &[authority, root_key]),
+            Err(ErrorCode::RemainingBalance.into())
+        );
+    }
+}
@@ -6,15 +6,59 @@ use solana_program::{
 
 pub(crate) const PK_LEN: usize = 32;
 
-const IS_INIT: usize = 0;
-const AUTH: usize = 1;
+const DISC: usize = 0;
+const VERSION: usize = DISC + 1;
+const IS_INIT: usize = VERSION + 1;
+const AUTH: usize = IS_INIT + 1;
 const VAULT: usize = AUTH + PK_LEN;
 const DURA: usize = VAULT + PK_LEN;
 const APR: usize = DURA + 8;
 const WTL: usize = APR + 8;
 const FEE: usize = WTL + 8;
 const LIFE: usize = FEE + 8;
+const BASIS: usize = LIFE + 8;
+const C_TS: usize = BASIS + 1;
+const SWEEP: usize = C_TS + 8;
+const GRACE: usize = SWEEP + 1;
+const ACTIVE: usize = GRACE + 8;
+const COMMITTED: usize = ACTIVE + 8;
+const ROOT: usize = COMMITTED + 8;
+const REV: usize = ROOT + PK_LEN;
+const PREV: usize = REV + 8;
+const SUCC: usize = PREV + PK_LEN;
+const TREAS: usize = SUCC + PK_LEN;
 
+/// First byte of every `MetadataState`.
+pub const METADATA_DISCRIMINATOR: u8 = b'M';
+/// The `MetadataState` layout written by this program. Older accounts are
+/// brought up to date with `Migrate`.
+pub const METADATA_VERSION: u8 = 7;
+
+/// Seed of the account the vesting program signs `Commit` and `Release`
+/// with.
+pub const REGISTRAR_SEED: &[u8] = b"registrar";
+
+/// The balance of a vesting account that `apr` is paid on.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RewardBasis {
+    /// Everything still in the vesting account, vested or not.
+    #[default]
+    Outstanding = 0,
+    /// Only what has not vested yet.
+    Unvested = 1,
+}
+
+impl RewardBasis {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Outstanding),
+            1 => Some(Self::Unvested),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct MetadataState {
     pub is_initialized: bool,
     pub authority: Pubkey,
@@ -24,6 +68,31 @@ pub struct MetadataState {
     pub withdrawal_timelock: u64,
     pub early_withdrawal_fee: u64,
     pub lifetime: u64,
+    pub reward_basis: RewardBasis,
+    /// The unix timestamp `lifetime` counts from. Accounts migrated from
+    /// before it count from their migration.
+    pub created_ts: u64,
+    /// Whether the plan opted in to `Sweep` returning unclaimed vested
+    /// balances to grantors once it has expired.
+    pub sweepable: bool,
+    /// How long after expiry `Sweep` becomes possible.
+    pub sweep_grace_period: u64,
+    /// Number of open vesting accounts on this plan.
+    pub active_vestings: u64,
+    /// Sum of the start balances of those vesting accounts.
+    pub total_committed: u64,
+    /// The first version of the plan, default on the first version itself,
+    /// see `plan_root`.
+    pub root: Pubkey,
+    /// How many times the plan was updated before this version.
+    pub revision: u64,
+    /// The version this one replaced, default on the first version.
+    pub previous: Pubkey,
+    /// The version that replaced this one, default while it is the latest.
+    pub successor: Pubkey,
+    /// The token account early withdrawal fees are paid into. Accounts
+    /// migrated from before it pay them into `vault`.
+    pub treasury: Pubkey,
 }
 
 impl IsInitialized for MetadataState {
@@ -32,12 +101,128 @@ impl IsInitialized for MetadataState {
     }
 }
 
+impl MetadataState {
+    /// When the plan stops accepting new grants, `None` if `lifetime` is
+    /// zero and it never does.
+    pub fn expiry_ts(&self) -> Option<u64> {
+        match self.lifetime {
+            0 => None,
+            lifetime => Some(self.created_ts.saturating_add(lifetime)),
+        }
+    }
+
+    /// When `Sweep` becomes possible for grants of this plan, `None` if the
+    /// plan did not opt in or never expires.
+    pub fn sweep_ts(&self) -> Option<u64> {
+        if !self.sweepable {
+            return None;
+        }
+        self.expiry_ts()
+            .map(|expiry_ts| expiry_ts.saturating_add(self.sweep_grace_period))
+    }
+
+    /// The first version of the plan stored at `address`.
+    pub fn plan_root(&self, address: &Pubkey) -> Pubkey {
+        if self.root == Pubkey::default() {
+            *address
+        } else {
+            self.root
+        }
+    }
+
+    /// Whether an `Update` replaced this version of the plan.
+    pub fn is_superseded(&self) -> bool {
+        self.successor != Pubkey::default()
+    }
+
+    /// Account size of each layout version. Version 0 lacks the
+    /// discriminator and version bytes, versions 0 and 1 lack `reward_basis`,
+    /// versions up to 2 the expiry fields, versions up to 3
+    /// `active_vestings`, versions up to 4 `total_committed`, versions up to
+    /// 5 the plan version links and versions up to 6 `treasury`. The layouts
+    /// are otherwise the same.
+    pub fn versioned_len(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(BASIS - IS_INIT),
+            1 => Some(BASIS),
+            2 => Some(C_TS),
+            3 => Some(ACTIVE),
+            4 => Some(COMMITTED),
+            5 => Some(ROOT),
+            6 => Some(TREAS),
+            METADATA_VERSION => Some(Self::LEN),
+            _ => None,
+        }
+    }
+
+    /// The layout version of a serialized `MetadataState`. Unversioned
+    /// accounts start with their `is_initialized` flag and are version 0.
+    pub fn version(src: &[u8]) -> Option<u8> {
+        match src.first()? {
+            0 | 1 => Some(0),
+            &METADATA_DISCRIMINATOR => src.get(VERSION).copied(),
+            _ => None,
+        }
+    }
+
+    /// Reads an account of any layout version, see `versioned_len`. Fields
+    /// the layout lacks take their default.
+    pub fn unpack_versioned(version: u8, src: &[u8]) -> Result<Self, ProgramError> {
+        if Self::versioned_len(version) != Some(src.len()) {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let start = if version == 0 { IS_INIT } else { DISC };
+        let mut buf = [0u8; Self::LEN];
+        buf[start..start + src.len()].copy_from_slice(src);
+        buf[DISC] = METADATA_DISCRIMINATOR;
+        buf[VERSION] = METADATA_VERSION;
+        Self::unpack_from_slice(&buf)
+    }
+}
+
+/// Returns the address and bump of the metadata account of `authority` for
+/// `vault`, as created by `Create`.
+pub fn find_metadata_address(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    vault: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[authority.as_ref(), vault.as_ref()], program_id)
+}
+
+/// Returns the address and bump of the version of the plan of `authority`
+/// for `vault` that `revision` updates created. Revision 0 is the account
+/// created by `Create`, see `find_metadata_address`.
+pub fn find_plan_version_address(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    vault: &Pubkey,
+    revision: u64,
+) -> (Pubkey, u8) {
+    if revision == 0 {
+        return find_metadata_address(program_id, authority, vault);
+    }
+    Pubkey::find_program_address(
+        &[authority.as_ref(), vault.as_ref(), &revision.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Returns the address and bump of the account `vesting_program_id` signs
+/// `Commit` and `Release` with.
+pub fn find_registrar_address(vesting_program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REGISTRAR_SEED], vesting_program_id)
+}
+
 impl Sealed for MetadataState {}
 
 impl Pack for MetadataState {
-    const LEN: usize = 111;
+    const LEN: usize = TREAS + PK_LEN;
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[DISC] = METADATA_DISCRIMINATOR;
+        dst[VERSION] = METADATA_VERSION;
         dst[IS_INIT] = self.is_initialized as u8;
         dst[AUTH..VAULT].copy_from_slice(self.authority.as_ref());
         dst[VAULT..DURA].copy_from_slice(self.vault.as_ref());
@@ -45,14 +230,33 @@ impl Pack for MetadataState {
         dst[APR..WTL].copy_from_slice(&self.apr.to_le_bytes());
         dst[WTL..FEE].copy_from_slice(&self.withdrawal_timelock.to_le_bytes());
         dst[FEE..LIFE].copy_from_slice(&self.early_withdrawal_fee.to_le_bytes());
-        dst[LIFE..].copy_from_slice(&self.lifetime.to_le_bytes());
+        dst[LIFE..BASIS].copy_from_slice(&self.lifetime.to_le_bytes());
+        dst[BASIS] = self.reward_basis as u8;
+        dst[C_TS..SWEEP].copy_from_slice(&self.created_ts.to_le_bytes());
+        dst[SWEEP] = self.sweepable as u8;
+        dst[GRACE..ACTIVE].copy_from_slice(&self.sweep_grace_period.to_le_bytes());
+        dst[ACTIVE..COMMITTED].copy_from_slice(&self.active_vestings.to_le_bytes());
+        dst[COMMITTED..ROOT].copy_from_slice(&self.total_committed.to_le_bytes());
+        dst[ROOT..REV].copy_from_slice(self.root.as_ref());
+        dst[REV..PREV].copy_from_slice(&self.revision.to_le_bytes());
+        dst[PREV..SUCC].copy_from_slice(self.previous.as_ref());
+        dst[SUCC..TREAS].copy_from_slice(self.successor.as_ref());
+        dst[TREAS..].copy_from_slice(self.treasury.as_ref());
     }
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        // Accounts are all zeroes until `Create` writes them.
+        if src.iter().all(|byte| *byte == 0) {
+            return Ok(Self::default());
+        }
+        if src[DISC] != METADATA_DISCRIMINATOR || src[VERSION] != METADATA_VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
         let is_initialized = match src[IS_INIT] {
             0 => false,
             1 => true,
-            _ => unreachable!(),
+            _ => return Err(ProgramError::InvalidAccountData),
         };
 
         let authority = Pubkey::new_from_array(src[AUTH..VAULT].try_into().unwrap());
@@ -61,7 +265,23 @@ impl Pack for MetadataState {
         let apr = u64::from_le_bytes(src[APR..WTL].try_into().unwrap());
         let withdrawal_timelock = u64::from_le_bytes(src[WTL..FEE].try_into().unwrap());
         let early_withdrawal_fee = u64::from_le_bytes(src[FEE..LIFE].try_into().unwrap());
-        let lifetime = u64::from_le_bytes(src[LIFE..].try_into().unwrap());
+        let lifetime = u64::from_le_bytes(src[LIFE..BASIS].try_into().unwrap());
+        let reward_basis =
+            RewardBasis::from_u8(src[BASIS]).ok_or(ProgramError::InvalidAccountData)?;
+        let created_ts = u64::from_le_bytes(src[C_TS..SWEEP].try_into().unwrap());
+        let sweepable = match src[SWEEP] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let sweep_grace_period = u64::from_le_bytes(src[GRACE..ACTIVE].try_into().unwrap());
+        let active_vestings = u64::from_le_bytes(src[ACTIVE..COMMITTED].try_into().unwrap());
+        let total_committed = u64::from_le_bytes(src[COMMITTED..ROOT].try_into().unwrap());
+        let root = Pubkey::new_from_array(src[ROOT..REV].try_into().unwrap());
+        let revision = u64::from_le_bytes(src[REV..PREV].try_into().unwrap());
+        let previous = Pubkey::new_from_array(src[PREV..SUCC].try_into().unwrap());
+        let successor = Pubkey::new_from_array(src[SUCC..TREAS].try_into().unwrap());
+        let treasury = Pubkey::new_from_array(src[TREAS..].try_into().unwrap());
 
         Ok(Self {
             is_initialized,
@@ -72,6 +292,207 @@ impl Pack for MetadataState {
             withdrawal_timelock,
             early_withdrawal_fee,
             lifetime,
+            reward_basis,
+            created_ts,
+            sweepable,
+            sweep_grace_period,
+            active_vestings,
+            total_committed,
+            root,
+            revision,
+            previous,
+            successor,
+            treasury,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata_state() -> MetadataState {
+        MetadataState {
+            is_initialized: true,
+            authority: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            duration: 31_536_000,
+            apr: 500,
+            withdrawal_timelock: 86_400,
+            early_withdrawal_fee: 250,
+            lifetime: 63_072_000,
+            reward_basis: RewardBasis::Unvested,
+            created_ts: 1_700_000_000,
+            sweepable: true,
+            sweep_grace_period: 2_592_000,
+            active_vestings: 3,
+            total_committed: 1_500_000,
+            root: Pubkey::new_unique(),
+            revision: 2,
+            previous: Pubkey::new_unique(),
+            successor: Pubkey::default(),
+            treasury: Pubkey::new_unique(),
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let state = metadata_state();
+        let mut buf = vec![0u8; MetadataState::LEN];
+        MetadataState::pack(state.clone(), &mut buf).unwrap();
+
+        assert_eq!(buf[DISC], METADATA_DISCRIMINATOR);
+        assert_eq!(MetadataState::version(&buf), Some(METADATA_VERSION));
+        assert_eq!(MetadataState::unpack(&buf).unwrap(), state);
+
+        buf[VERSION] = METADATA_VERSION + 1;
+        assert_eq!(
+            MetadataState::unpack(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn test_unpack_versioned() {
+        let state = metadata_state();
+        let mut buf = vec![0u8; MetadataState::LEN];
+        state.pack_into_slice(&mut buf);
+        let without_treasury = MetadataState {
+            treasury: Pubkey::default(),
+            ..state.clone()
+        };
+        let without_links = MetadataState {
+            root: Pubkey::default(),
+            revision: 0,
+            previous: Pubkey::default(),
+            ..without_treasury.clone()
+        };
+        let without_committed = MetadataState {
+            total_committed: 0,
+            ..without_links.clone()
+        };
+        let without_counters = MetadataState {
+            active_vestings: 0,
+            ..without_committed.clone()
+        };
+        let without_expiry = MetadataState {
+            created_ts: 0,
+            sweepable: false,
+            sweep_grace_period: 0,
+            ..without_counters.clone()
+        };
+        let without_basis = MetadataState {
+            reward_basis: RewardBasis::Outstanding,
+            ..without_expiry.clone()
+        };
+
+        let legacy = &buf[IS_INIT..BASIS];
+        assert_eq!(MetadataState::version(legacy), Some(0));
+        assert_eq!(
+            MetadataState::unpack_versioned(0, legacy).unwrap(),
+            without_basis
+        );
+
+        let mut v1 = buf[..BASIS].to_vec();
+        v1[VERSION] = 1;
+        assert_eq!(MetadataState::version(&v1), Some(1));
+        assert_eq!(
+            MetadataState::unpack_versioned(1, &v1).unwrap(),
+            without_basis
+        );
+
+        let mut v2 = buf[..C_TS].to_vec();
+        v2[VERSION] = 2;
+        assert_eq!(
+            MetadataState::unpack_versioned(2, &v2).unwrap(),
+            without_expiry
+        );
+
+        let mut v3 = buf[..ACTIVE].to_vec();
+        v3[VERSION] = 3;
+        assert_eq!(
+            MetadataState::unpack_versioned(3, &v3).unwrap(),
+            without_counters
+        );
+
+        let mut v4 = buf[..COMMITTED].to_vec();
+        v4[VERSION] = 4;
+        assert_eq!(
+            MetadataState::unpack_versioned(4, &v4).unwrap(),
+            without_committed
+        );
+
+        let mut v5 = buf[..ROOT].to_vec();
+        v5[VERSION] = 5;
+        assert_eq!(
+            MetadataState::unpack_versioned(5, &v5).unwrap(),
+            without_links
+        );
+
+        let mut v6 = buf[..TREAS].to_vec();
+        v6[VERSION] = 6;
+        assert_eq!(
+            MetadataState::unpack_versioned(6, &v6).unwrap(),
+            without_treasury
+        );
+
+        assert_eq!(
+            MetadataState::unpack_versioned(METADATA_VERSION, &buf).unwrap(),
+            state
+        );
+        assert!(MetadataState::unpack_versioned(0, &buf).is_err());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let state = metadata_state();
+        assert_eq!(state.expiry_ts(), Some(1_763_072_000));
+        assert_eq!(state.sweep_ts(), Some(1_765_664_000));
+
+        let opted_out = MetadataState {
+            sweepable: false,
+            ..state.clone()
+        };
+        assert_eq!(opted_out.sweep_ts(), None);
+
+        let unlimited = MetadataState {
+            lifetime: 0,
+            ..state
+        };
+        assert_eq!(unlimited.expiry_ts(), None);
+        assert_eq!(unlimited.sweep_ts(), None);
+    }
+
+    #[test]
+    fn test_plan_versions() {
+        let address = Pubkey::new_unique();
+        let first = MetadataState {
+            root: Pubkey::default(),
+            revision: 0,
+            previous: Pubkey::default(),
+            ..metadata_state()
+        };
+        assert_eq!(first.plan_root(&address), address);
+        assert!(!first.is_superseded());
+
+        let second = metadata_state();
+        assert_eq!(second.plan_root(&address), second.root);
+
+        let superseded = MetadataState {
+            successor: Pubkey::new_unique(),
+            ..second
+        };
+        assert!(superseded.is_superseded());
+
+        let program_id = Pubkey::new_unique();
+        let (authority, vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(
+            find_plan_version_address(&program_id, &authority, &vault, 0),
+            find_metadata_address(&program_id, &authority, &vault)
+        );
+        assert_ne!(
+            find_plan_version_address(&program_id, &authority, &vault, 1).0,
+            find_plan_version_address(&program_id, &authority, &vault, 2).0
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Addresses of the vesting accounts created by the associated vesting account program.
+
+use solana_program::pubkey::Pubkey;
+
+solana_program::declare_id!("Gdv2dsTqNyFhguadXmzNxqjiqA9h1yibx671fb33xDtd");
+
+/// Returns the associated vesting account address for `wallet_address` and `mint`.
+pub fn get_associated_vesting_address(wallet_address: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_vesting_address_and_bump_seed(wallet_address, mint, &crate::id(), &id()).0
+}
+
+pub fn get_associated_vesting_address_and_bump_seed(
+    wallet_address: &Pubkey,
+    mint: &Pubkey,
+    vesting_program: &Pubkey,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            wallet_address.as_ref(),
+            vesting_program.as_ref(),
+            mint.as_ref(),
+        ],
+        program_id,
+    )
+}
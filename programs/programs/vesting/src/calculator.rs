@@ -1,13 +1,320 @@
 //! Utility functions for calculating unlock schedules for a vesting account.
 
-use crate::Vesting;
+use crate::{
+    civil,
+    state::{Milestone, PeriodUnit, ScheduleMode, Tranche, VestingState, MAX_MERGED_TRANCHES},
+};
 
-pub fn available_for_withdrawal(vesting: &Vesting, current_ts: i64) -> u64 {
-    std::cmp::min(outstanding_vested(vesting, current_ts), balance(vesting))
+/// `tranches` is only read for `ScheduleMode::Tranches` accounts and may be
+/// empty otherwise. Milestone accounts pass `milestone_tranches` instead.
+pub fn available_for_withdrawal(
+    vesting: &VestingState,
+    tranches: &[Tranche],
+    current_ts: u64,
+) -> u64 {
+    std::cmp::min(
+        outstanding_vested(vesting, tranches, current_ts),
+        balance(vesting),
+    )
+}
+
+/// Carves `amount` of the outstanding balance out of `vesting` into a second
+/// schedule with the same timeline, shrinking `vesting` and `tranches` in
+/// place. `start_balance`, the cliff, every tranche and the accrued rewards
+/// are divided in the same proportion.
+///
+/// The linear schedule rounds per period, so when the carved out part does
+/// not divide evenly into the periods both halves are converted to tranches
+/// and `tranches` grows to hold the schedule.
+///
+/// Returns `None`, leaving the inputs untouched, when the two schedules would
+/// not unlock exactly what the original does at every timestamp.
+pub fn split(
+    vesting: &mut VestingState,
+    tranches: &mut Vec<Tranche>,
+    amount: u64,
+    current_ts: u64,
+) -> Option<(VestingState, Vec<Tranche>)> {
+    if amount == 0 || amount >= vesting.outstanding || vesting.whitelist_owned > 0 {
+        return None;
+    }
+
+    let mut original = *vesting;
+    let mut schedule = tranches.clone();
+    let start_balance = mul_div(original.start_balance, amount, original.outstanding)?;
+    if original.schedule_mode == ScheduleMode::Linear
+        && (start_balance - mul_div(original.cliff_amount, start_balance, original.start_balance)?)
+            % original.period_count
+            != 0
+    {
+        schedule = convert_to_tranches(&mut original, tranches)?;
+    }
+    let cliff_amount = mul_div(original.cliff_amount, start_balance, original.start_balance)?;
+
+    let accrued_rewards = mul_div(original.accrued_rewards, amount, original.outstanding)?;
+    let new = VestingState {
+        outstanding: amount,
+        start_balance,
+        cliff_amount,
+        accrued_rewards,
+        ..original
+    };
+    let remaining = VestingState {
+        outstanding: original.outstanding - amount,
+        start_balance: original.start_balance - start_balance,
+        cliff_amount: original.cliff_amount - cliff_amount,
+        accrued_rewards: original.accrued_rewards - accrued_rewards,
+        ..original
+    };
+
+    // Splitting the running total keeps each new tranche within the original.
+    let mut new_tranches = Vec::with_capacity(schedule.len());
+    let mut cumulative = 0u64;
+    let mut new_cumulative = 0u64;
+    for tranche in schedule.iter() {
+        cumulative = cumulative.checked_add(tranche.amount)?;
+        let next = mul_div(cumulative, start_balance, original.start_balance)?;
+        new_tranches.push(Tranche {
+            timestamp: tranche.timestamp,
+            amount: next - new_cumulative,
+        });
+        new_cumulative = next;
+    }
+    let remaining_tranches: Vec<Tranche> = schedule
+        .iter()
+        .zip(&new_tranches)
+        .map(|(tranche, new)| Tranche {
+            timestamp: tranche.timestamp,
+            amount: tranche.amount - new.amount,
+        })
+        .collect();
+
+    // Neither half may have withdrawn more than it has vested so far.
+    if total_vested(&new, &new_tranches, current_ts) < withdrawn_amount(&new)
+        || total_vested(&remaining, &remaining_tranches, current_ts) < withdrawn_amount(&remaining)
+    {
+        return None;
+    }
+
+    *vesting = remaining;
+    *tranches = remaining_tranches;
+    Some((new, new_tranches))
+}
+
+/// Attested milestones vest at their attestation, or at their time floor if
+/// that comes later. Pending milestones are left out.
+pub fn milestone_tranches(milestones: &[Milestone]) -> Vec<Tranche> {
+    milestones
+        .iter()
+        .filter(|milestone| milestone.completed_ts != 0)
+        .map(|milestone| Tranche {
+            timestamp: std::cmp::max(milestone.timestamp, milestone.completed_ts),
+            amount: milestone.amount,
+        })
+        .collect()
+}
+
+/// Expresses the whole schedule of `vesting`, cliff included, as tranches
+/// that add up to `start_balance`. Returns `None` if more than
+/// `MAX_MERGED_TRANCHES` would be needed.
+pub fn schedule_tranches(vesting: &VestingState, tranches: &[Tranche]) -> Option<Vec<Tranche>> {
+    // Whatever is left of a revoked grant has already vested.
+    if vesting.revoked {
+        return Some(vec![Tranche {
+            timestamp: 0,
+            amount: vesting.start_balance,
+        }]);
+    }
+
+    match vesting.schedule_mode {
+        ScheduleMode::Linear => {}
+        ScheduleMode::Tranches => return Some(tranches.to_vec()),
+        // Pending milestones have no timestamp to convert to.
+        ScheduleMode::Milestones => return None,
+    }
+
+    if vesting.period_count as usize > MAX_MERGED_TRANCHES {
+        return None;
+    }
+
+    // The linear schedule only steps at the cliff, the start and the end of
+    // each period, see `linear_unlock`.
+    let mut steps = vec![vesting.cliff_ts, vesting.start_ts, vesting.end_ts];
+    for period in 1..=vesting.period_count {
+        steps.push(period_end(vesting, period)?);
+    }
+    steps.sort_unstable();
+    steps.dedup();
+
+    let mut schedule = Vec::new();
+    let mut vested = 0;
+    for timestamp in steps {
+        let total = total_vested(vesting, &[], timestamp);
+        if total > vested {
+            schedule.push(Tranche {
+                timestamp,
+                amount: total - vested,
+            });
+            vested = total;
+        }
+    }
+
+    if schedule.len() > MAX_MERGED_TRANCHES {
+        return None;
+    }
+    Some(schedule)
+}
+
+/// Switches `vesting` to `ScheduleMode::Tranches`, returning the tranches
+/// from `schedule_tranches` that now make up its schedule.
+pub fn convert_to_tranches(
+    vesting: &mut VestingState,
+    tranches: &[Tranche],
+) -> Option<Vec<Tranche>> {
+    let tranches = schedule_tranches(vesting, tranches)?;
+
+    // The cliff is part of the tranches now.
+    vesting.schedule_mode = ScheduleMode::Tranches;
+    vesting.period_unit = PeriodUnit::Seconds;
+    vesting.start_ts = tranches.first().map_or(0, |tranche| tranche.timestamp);
+    vesting.end_ts = tranches.last().map_or(0, |tranche| tranche.timestamp);
+    vesting.period_count = tranches.len() as u64;
+    vesting.cliff_ts = 0;
+    vesting.cliff_amount = 0;
+
+    Some(tranches)
+}
+
+/// Combines several tranche schedules into one, adding up tranches that
+/// unlock at the same timestamp.
+pub fn merge_tranches(schedules: &[Vec<Tranche>]) -> Option<Vec<Tranche>> {
+    let mut all: Vec<Tranche> = schedules.iter().flatten().copied().collect();
+    all.sort_by_key(|tranche| tranche.timestamp);
+
+    let mut merged: Vec<Tranche> = Vec::with_capacity(all.len());
+    for tranche in all {
+        match merged.last_mut() {
+            Some(last) if last.timestamp == tranche.timestamp => {
+                last.amount = last.amount.checked_add(tranche.amount)?;
+            }
+            _ => merged.push(tranche),
+        }
+    }
+
+    if merged.len() > MAX_MERGED_TRANCHES {
+        return None;
+    }
+    Some(merged)
+}
+
+/// Takes `amount` out of the tranches that unlock after `current_ts`, each
+/// in proportion to its size. Earlier tranches, and so everything vested so
+/// far, are left as they are.
+///
+/// Returns `None`, leaving `tranches` untouched, when less than `amount` is
+/// still to vest.
+pub fn shrink_tranches(tranches: &mut [Tranche], amount: u64, current_ts: u64) -> Option<()> {
+    shrink(
+        tranches
+            .iter_mut()
+            .filter(|tranche| tranche.timestamp > current_ts)
+            .map(|tranche| &mut tranche.amount)
+            .collect(),
+        amount,
+    )
+}
+
+/// Like `shrink_tranches`, for the milestones that have not vested at
+/// `current_ts`, attested or not.
+pub fn shrink_milestones(milestones: &mut [Milestone], amount: u64, current_ts: u64) -> Option<()> {
+    shrink(
+        milestones
+            .iter_mut()
+            .filter(|milestone| {
+                milestone.completed_ts == 0
+                    || std::cmp::max(milestone.timestamp, milestone.completed_ts) > current_ts
+            })
+            .map(|milestone| &mut milestone.amount)
+            .collect(),
+        amount,
+    )
+}
+
+// Scales `amounts` down so that they add up to `amount` less than before.
+fn shrink(mut amounts: Vec<&mut u64>, amount: u64) -> Option<()> {
+    let total = amounts
+        .iter()
+        .try_fold(0u64, |sum, amount| sum.checked_add(**amount))?;
+    let remaining = total.checked_sub(amount)?;
+
+    // Scaling the running total keeps the parts adding up to `remaining`.
+    let mut cumulative = 0u64;
+    let mut scaled = 0u64;
+    for amount in amounts.iter_mut() {
+        cumulative += **amount;
+        let next = mul_div(cumulative, remaining, total)?;
+        **amount = next - scaled;
+        scaled = next;
+    }
+
+    Some(())
+}
+
+/// The amount vested before withdrawals, summed over every second of
+/// `[from_ts, to_ts)`. `tranches` is read as in `available_for_withdrawal`.
+pub fn vested_seconds(
+    vesting: &VestingState,
+    tranches: &[Tranche],
+    from_ts: u64,
+    to_ts: u64,
+) -> Option<u128> {
+    if from_ts >= to_ts {
+        return Some(0);
+    }
+
+    // The vested amount is constant between these steps, except within the
+    // linear part of a schedule, see `linear_seconds`.
+    let mut steps = vec![from_ts, to_ts, vesting.cliff_ts];
+    match vesting.schedule_mode {
+        ScheduleMode::Linear => steps.extend([vesting.start_ts, vesting.end_ts]),
+        ScheduleMode::Tranches | ScheduleMode::Milestones => {
+            steps.extend(tranches.iter().map(|tranche| tranche.timestamp))
+        }
+    }
+    steps.retain(|ts| (from_ts..=to_ts).contains(ts));
+    steps.sort_unstable();
+    steps.dedup();
+
+    let mut total = 0u128;
+    for window in steps.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let seconds = if !vesting.revoked
+            && vesting.schedule_mode == ScheduleMode::Linear
+            && start >= vesting.cliff_ts
+            && start >= vesting.start_ts
+            && start < vesting.end_ts
+        {
+            linear_seconds(vesting, start, end)?
+        } else {
+            (total_vested(vesting, tranches, start) as u128).checked_mul((end - start) as u128)?
+        };
+        total = total.checked_add(seconds)?;
+    }
+
+    Some(total)
+}
+
+/// `value * numerator / denominator`, rounded down.
+pub fn mul_div(value: u64, numerator: u64, denominator: u64) -> Option<u64> {
+    (value as u128)
+        .checked_mul(numerator as u128)?
+        .checked_div(denominator as u128)?
+        .try_into()
+        .ok()
 }
 
 // The amount of funds currently in the vault.
-fn balance(vesting: &Vesting) -> u64 {
+fn balance(vesting: &VestingState) -> u64 {
     vesting
         .outstanding
         .checked_sub(vesting.whitelist_owned)
@@ -15,14 +322,14 @@ fn balance(vesting: &Vesting) -> u64 {
 }
 
 // The amount of outstanding locked tokens vested.
-fn outstanding_vested(vesting: &Vesting, current_ts: i64) -> u64 {
-    total_vested(vesting, current_ts)
+fn outstanding_vested(vesting: &VestingState, tranches: &[Tranche], current_ts: u64) -> u64 {
+    total_vested(vesting, tranches, current_ts)
         .checked_sub(withdrawn_amount(vesting))
         .unwrap()
 }
 
 // Returns the amount withdrawn from this vesting account.
-fn withdrawn_amount(vesting: &Vesting) -> u64 {
+fn withdrawn_amount(vesting: &VestingState) -> u64 {
     vesting
         .start_balance
         .checked_sub(vesting.outstanding)
@@ -31,47 +338,176 @@ fn withdrawn_amount(vesting: &Vesting) -> u64 {
 
 // Returns the total vested amount up to the given ts, assuming zero
 // withdrawals.
-fn total_vested(vesting: &Vesting, current_ts: i64) -> u64 {
+fn total_vested(vesting: &VestingState, tranches: &[Tranche], current_ts: u64) -> u64 {
+    // Revoking removes the unvested remainder from `start_balance`.
+    if vesting.revoked {
+        return vesting.start_balance;
+    }
+
+    if current_ts < vesting.cliff_ts {
+        return 0;
+    }
+
+    match vesting.schedule_mode {
+        ScheduleMode::Linear => linear_vested(vesting, current_ts),
+        ScheduleMode::Tranches | ScheduleMode::Milestones => tranche_unlock(tranches, current_ts),
+    }
+}
+
+// Sums every tranche whose timestamp has passed.
+fn tranche_unlock(tranches: &[Tranche], current_ts: u64) -> u64 {
+    tranches
+        .iter()
+        .filter(|tranche| tranche.timestamp <= current_ts)
+        .map(|tranche| tranche.amount)
+        .sum()
+}
+
+fn linear_vested(vesting: &VestingState, current_ts: u64) -> u64 {
+    // The cliff amount is released in one step, the rest follows the
+    // linear schedule.
+    let linear_balance = vesting
+        .start_balance
+        .checked_sub(vesting.cliff_amount)
+        .unwrap();
+
     if current_ts < vesting.start_ts {
-        0
+        vesting.cliff_amount
     } else if current_ts >= vesting.end_ts {
         vesting.start_balance
     } else {
-        linear_unlock(vesting, current_ts).unwrap()
+        linear_unlock(vesting, linear_balance, current_ts)
+            .and_then(|unlocked| unlocked.checked_add(vesting.cliff_amount))
+            .unwrap()
     }
 }
 
-fn linear_unlock(vesting: &Vesting, current_ts: i64) -> Option<u64> {
-    // Signed division not supported.
-    let current_ts = current_ts as u64;
-    let start_ts = vesting.start_ts as u64;
-    let end_ts = vesting.end_ts as u64;
+/// The `end_ts` and `period_count` of a linear schedule that starts at
+/// `start_ts`, lasts `duration` seconds and vests about once a day. The
+/// duration is split evenly into one period per whole day, so durations that
+/// are not whole days get periods slightly longer than a day, and durations
+/// under a day vest in one period. `None` for a zero duration.
+pub fn plan_schedule(start_ts: u64, duration: u64) -> Option<(u64, u64)> {
+    if duration == 0 {
+        return None;
+    }
+    let end_ts = start_ts.checked_add(duration)?;
+    Some((end_ts, std::cmp::max(duration / civil::SECS_PER_DAY, 1)))
+}
+
+/// The unix timestamp at which `period` periods of a linear schedule have
+/// passed. For calendar units this is also where `end_ts` must fall after
+/// `period_count` periods.
+pub fn period_end(vesting: &VestingState, period: u64) -> Option<u64> {
+    match vesting.period_unit {
+        PeriodUnit::Seconds => {
+            let (shifted_start_ts, period_secs) = shifted_periods(vesting)?;
+            shifted_start_ts.checked_add(period.checked_mul(period_secs)?)
+        }
+        PeriodUnit::Days => civil::add_days(vesting.start_ts, period),
+        PeriodUnit::Months => civil::add_months(vesting.start_ts, period),
+        PeriodUnit::Years => civil::add_months(vesting.start_ts, period.checked_mul(12)?),
+    }
+}
+
+// If we can't perfectly partition the vesting window into periods of equal
+// seconds, push the start of the window back so that we can.
+//
+// This has the effect of making the first vesting period shorter than the
+// rest.
+fn shifted_periods(vesting: &VestingState) -> Option<(u64, u64)> {
+    let start_ts = vesting.start_ts;
+    let end_ts = vesting.end_ts;
 
-    // If we can't perfectly partition the vesting window,
-    // push the start of the window back so that we can.
-    //
-    // This has the effect of making the first vesting period shorter
-    // than the rest.
     let shifted_start_ts =
         start_ts.checked_sub(end_ts.checked_sub(start_ts)? % vesting.period_count)?;
+    let period_secs = (end_ts.checked_sub(shifted_start_ts)?).checked_div(vesting.period_count)?;
+
+    Some((shifted_start_ts, period_secs))
+}
+
+// Number of vesting periods that have passed.
+fn periods_elapsed(vesting: &VestingState, current_ts: u64) -> Option<u64> {
+    if vesting.period_unit == PeriodUnit::Seconds {
+        let (shifted_start_ts, period_secs) = shifted_periods(vesting)?;
+        let current_period_count =
+            (current_ts.checked_sub(shifted_start_ts)?).checked_div(period_secs)?;
+        return Some(std::cmp::min(current_period_count, vesting.period_count));
+    }
+
+    // Calendar periods differ in length, find the last one that has ended.
+    let (mut low, mut high) = (0, vesting.period_count);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if period_end(vesting, mid)? <= current_ts {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Some(low)
+}
+
+// `vested_seconds` for a window within `[start_ts, end_ts)` and past the cliff.
+fn linear_seconds(vesting: &VestingState, from_ts: u64, to_ts: u64) -> Option<u128> {
+    let linear_balance = vesting.start_balance.checked_sub(vesting.cliff_amount)?;
+    let reward_overflow = (linear_balance % vesting.period_count) as u128;
+    let reward_per_period = (linear_balance / vesting.period_count) as u128;
+
+    // Periods of fixed length are counted in closed form, calendar periods
+    // one by one.
+    let fixed = match vesting.period_unit {
+        PeriodUnit::Seconds => Some(shifted_periods(vesting)?),
+        PeriodUnit::Days => Some((vesting.start_ts, civil::SECS_PER_DAY)),
+        PeriodUnit::Months | PeriodUnit::Years => None,
+    };
+    let periods = match fixed {
+        Some((origin, period_secs)) => period_seconds(to_ts - origin, period_secs)
+            .checked_sub(period_seconds(from_ts - origin, period_secs))?,
+        None => {
+            let mut period = periods_elapsed(vesting, from_ts)?;
+            let mut ts = from_ts;
+            let mut periods = 0u128;
+            while ts < to_ts {
+                let next = std::cmp::min(period_end(vesting, period + 1)?, to_ts);
+                periods =
+                    periods.checked_add((period as u128).checked_mul((next - ts) as u128)?)?;
+                ts = next;
+                period += 1;
+            }
+            periods
+        }
+    };
 
-    // Similarly, if we can't perfectly divide up the vesting rewards
+    // The overflow is paid with the first period, see `linear_unlock`.
+    let first_period_end = period_end(vesting, 1)?;
+    let after_first = to_ts.saturating_sub(std::cmp::max(from_ts, first_period_end)) as u128;
+
+    (vesting.cliff_amount as u128)
+        .checked_mul((to_ts - from_ts) as u128)?
+        .checked_add(reward_overflow.checked_mul(after_first)?)?
+        .checked_add(reward_per_period.checked_mul(periods)?)
+}
+
+// The number of whole `period_secs` periods passed, summed over every second
+// of `[0, elapsed)`.
+fn period_seconds(elapsed: u64, period_secs: u64) -> u128 {
+    let periods = (elapsed / period_secs) as u128;
+    let remainder = (elapsed % period_secs) as u128;
+    period_secs as u128 * periods * periods.saturating_sub(1) / 2 + periods * remainder
+}
+
+fn linear_unlock(vesting: &VestingState, linear_balance: u64, current_ts: u64) -> Option<u64> {
+    // If we can't perfectly divide up the vesting rewards
     // then make the first period act as a cliff, earning slightly more than
     // subsequent periods.
-    let reward_overflow = vesting.start_balance % vesting.period_count;
+    let reward_overflow = linear_balance % vesting.period_count;
 
     // Reward per period ignoring the overflow.
     let reward_per_period =
-        (vesting.start_balance.checked_sub(reward_overflow)?).checked_div(vesting.period_count)?;
+        (linear_balance.checked_sub(reward_overflow)?).checked_div(vesting.period_count)?;
 
-    // Number of vesting periods that have passed.
-    let current_period = {
-        let period_secs =
-            (end_ts.checked_sub(shifted_start_ts)?).checked_div(vesting.period_count)?;
-        let current_period_count =
-            (current_ts.checked_sub(shifted_start_ts)?).checked_div(period_secs)?;
-        std::cmp::min(current_period_count, vesting.period_count)
-    };
+    let current_period = periods_elapsed(vesting, current_ts)?;
 
     if current_period == 0 {
         return Some(0);
@@ -81,3 +517,561 @@ fn linear_unlock(vesting: &Vesting, current_ts: i64) -> Option<u64> {
         .checked_mul(reward_per_period)?
         .checked_add(reward_overflow)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: u64 = 1_600_000_000;
+    const MONTH: u64 = 2_592_000;
+
+    fn vesting(cliff_ts: u64, cliff_amount: u64) -> VestingState {
+        VestingState {
+            is_initialized: true,
+            outstanding: 3_600,
+            start_balance: 3_600,
+            start_ts: START,
+            end_ts: START + 36 * MONTH,
+            period_count: 36,
+            cliff_ts,
+            cliff_amount,
+            ..VestingState::default()
+        }
+    }
+
+    #[test]
+    fn test_linear_without_cliff() {
+        let vesting = vesting(0, 0);
+
+        assert_eq!(available_for_withdrawal(&vesting, &[], START - 1), 0);
+        assert_eq!(available_for_withdrawal(&vesting, &[], START + MONTH), 100);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 12 * MONTH),
+            1_200
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 36 * MONTH),
+            3_600
+        );
+    }
+
+    #[test]
+    fn test_nothing_vested_before_cliff() {
+        let vesting = vesting(START + 12 * MONTH, 1_200);
+
+        assert_eq!(available_for_withdrawal(&vesting, &[], START + MONTH), 0);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 12 * MONTH - 1),
+            0
+        );
+    }
+
+    #[test]
+    fn test_cliff_released_in_one_step() {
+        // The cliff starts the linear schedule for the remainder.
+        let mut vesting = vesting(START + 12 * MONTH, 1_200);
+        vesting.start_ts = START + 12 * MONTH;
+        vesting.period_count = 24;
+
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 12 * MONTH),
+            1_200
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 13 * MONTH),
+            1_300
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 36 * MONTH),
+            3_600
+        );
+    }
+
+    #[test]
+    fn test_cliff_respects_withdrawals() {
+        let mut vesting = vesting(START + 12 * MONTH, 1_200);
+        vesting.start_ts = START + 12 * MONTH;
+        vesting.period_count = 24;
+        vesting.outstanding = 3_000;
+
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 13 * MONTH),
+            700
+        );
+    }
+
+    #[test]
+    fn test_calendar_months() {
+        // 2024-01-31T12:00:00Z, monthly for a year.
+        const JAN_31_2024: u64 = 1_706_702_400;
+        const DAY: u64 = 86_400;
+        let mut vesting = vesting(0, 0);
+        vesting.start_balance = 1_200;
+        vesting.outstanding = 1_200;
+        vesting.start_ts = JAN_31_2024;
+        vesting.period_count = 12;
+        vesting.period_unit = PeriodUnit::Months;
+        vesting.end_ts = period_end(&vesting, 12).unwrap();
+
+        // Jan 31 2025.
+        assert_eq!(vesting.end_ts, JAN_31_2024 + 366 * DAY);
+
+        // Unlocks on Feb 29, then Mar 31.
+        let feb_29 = JAN_31_2024 + 29 * DAY;
+        assert_eq!(available_for_withdrawal(&vesting, &[], feb_29 - 1), 0);
+        assert_eq!(available_for_withdrawal(&vesting, &[], feb_29), 100);
+        let mar_31 = feb_29 + 31 * DAY;
+        assert_eq!(available_for_withdrawal(&vesting, &[], mar_31 - 1), 100);
+        assert_eq!(available_for_withdrawal(&vesting, &[], mar_31), 200);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], vesting.end_ts - 1),
+            1_100
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], vesting.end_ts),
+            1_200
+        );
+    }
+
+    #[test]
+    fn test_calendar_years_from_leap_day() {
+        // 2024-02-29T00:00:00Z, yearly for four years.
+        const FEB_29_2024: u64 = 1_709_164_800;
+        let mut vesting = vesting(0, 0);
+        vesting.start_balance = 400;
+        vesting.outstanding = 400;
+        vesting.start_ts = FEB_29_2024;
+        vesting.period_count = 4;
+        vesting.period_unit = PeriodUnit::Years;
+        vesting.end_ts = period_end(&vesting, 4).unwrap();
+
+        // Feb 28 2025, then Feb 29 2028.
+        assert_eq!(period_end(&vesting, 1), Some(FEB_29_2024 + 365 * 86_400));
+        assert_eq!(vesting.end_ts, FEB_29_2024 + 1_461 * 86_400);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], FEB_29_2024 + 365 * 86_400),
+            100
+        );
+    }
+
+    #[test]
+    fn test_tranches() {
+        let mut vesting = vesting(0, 0);
+        vesting.schedule_mode = ScheduleMode::Tranches;
+        let tranches = [
+            Tranche {
+                timestamp: START,
+                amount: 600,
+            },
+            Tranche {
+                timestamp: START + 6 * MONTH,
+                amount: 1_000,
+            },
+            Tranche {
+                timestamp: START + 24 * MONTH,
+                amount: 2_000,
+            },
+        ];
+
+        assert_eq!(available_for_withdrawal(&vesting, &tranches, START - 1), 0);
+
+        vesting.outstanding = 3_400;
+        assert_eq!(available_for_withdrawal(&vesting, &tranches, START), 400);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &tranches, START + 6 * MONTH),
+            1_400
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &tranches, START + 24 * MONTH),
+            3_400
+        );
+    }
+
+    fn assert_split_adds_up(
+        original: &VestingState,
+        tranches: &[Tranche],
+        amount: u64,
+        current_ts: u64,
+    ) {
+        let mut remaining = *original;
+        let mut remaining_tranches = tranches.to_vec();
+        let (new, new_tranches) =
+            split(&mut remaining, &mut remaining_tranches, amount, current_ts).unwrap();
+
+        assert_eq!(new.outstanding, amount);
+        assert_eq!(
+            remaining.outstanding + new.outstanding,
+            original.outstanding
+        );
+        assert_eq!(
+            remaining.start_balance + new.start_balance,
+            original.start_balance
+        );
+        assert_eq!(
+            remaining.accrued_rewards + new.accrued_rewards,
+            original.accrued_rewards
+        );
+        for ts in (current_ts..=START + 37 * MONTH).step_by(MONTH as usize / 3) {
+            assert_eq!(
+                available_for_withdrawal(&remaining, &remaining_tranches, ts)
+                    + available_for_withdrawal(&new, &new_tranches, ts),
+                available_for_withdrawal(original, tranches, ts)
+            );
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let mut vesting = vesting(START + 12 * MONTH, 1_200);
+        vesting.start_ts = START + 12 * MONTH;
+        vesting.period_count = 24;
+        vesting.accrued_rewards = 97;
+        assert_split_adds_up(&vesting, &[], 1_080, START);
+
+        // With 900 already withdrawn, 810 outstanding carries 1_080 of the
+        // start balance with it.
+        vesting.outstanding = 2_700;
+        assert_split_adds_up(&vesting, &[], 810, START + 15 * MONTH);
+    }
+
+    #[test]
+    fn test_split_uneven() {
+        // 1_000 does not divide into the 36 periods, so both halves become
+        // tranches.
+        let original = vesting(START + 6 * MONTH, 500);
+        assert_split_adds_up(&original, &[], 1_000, START);
+        assert_split_adds_up(&original, &[], 1, START + 7 * MONTH);
+
+        let mut vesting = original;
+        let mut tranches = Vec::new();
+        let (new, new_tranches) = split(&mut vesting, &mut tranches, 1_000, START).unwrap();
+        assert_eq!(vesting.schedule_mode, ScheduleMode::Tranches);
+        assert_eq!(new.schedule_mode, ScheduleMode::Tranches);
+        assert_eq!(new.cliff_amount, 0);
+        assert_eq!(tranches.len(), new_tranches.len());
+        assert_eq!(new_tranches.iter().map(|t| t.amount).sum::<u64>(), 1_000);
+        assert_eq!(tranches.iter().map(|t| t.amount).sum::<u64>(), 2_600);
+    }
+
+    #[test]
+    fn test_split_tranches() {
+        let mut vesting = vesting(0, 0);
+        vesting.start_balance = 1_000;
+        vesting.outstanding = 1_000;
+        vesting.schedule_mode = ScheduleMode::Tranches;
+        let tranches = [
+            Tranche {
+                timestamp: START,
+                amount: 333,
+            },
+            Tranche {
+                timestamp: START + 6 * MONTH,
+                amount: 333,
+            },
+            Tranche {
+                timestamp: START + 12 * MONTH,
+                amount: 334,
+            },
+        ];
+
+        assert_split_adds_up(&vesting, &tranches, 500, START);
+        assert_split_adds_up(&vesting, &tranches, 1, START);
+    }
+
+    #[test]
+    fn test_split_rejected() {
+        let original = vesting(0, 0);
+        let mut vesting = original;
+
+        assert!(split(&mut vesting, &mut vec![], 0, START).is_none());
+        assert!(split(&mut vesting, &mut vec![], 3_600, START).is_none());
+        assert_eq!(vesting, original);
+
+        vesting.whitelist_owned = 100;
+        assert!(split(&mut vesting, &mut vec![], 360, START).is_none());
+
+        // Too many periods to convert to tranches.
+        let mut vesting = original;
+        vesting.period_count = MAX_MERGED_TRANCHES as u64 + 1;
+        assert!(split(&mut vesting, &mut vec![], 1_000, START).is_none());
+        assert_eq!(vesting.period_count, MAX_MERGED_TRANCHES as u64 + 1);
+    }
+
+    #[test]
+    fn test_schedule_tranches() {
+        let mut vesting = vesting(START + 12 * MONTH, 1_200);
+        vesting.start_ts = START + 12 * MONTH;
+        vesting.period_count = 24;
+        vesting.outstanding = 3_000;
+
+        let schedule = schedule_tranches(&vesting, &[]).unwrap();
+        assert_eq!(schedule.len(), 25);
+        assert_eq!(schedule.iter().map(|t| t.amount).sum::<u64>(), 3_600);
+
+        let mut converted = vesting;
+        converted.schedule_mode = ScheduleMode::Tranches;
+        converted.cliff_ts = 0;
+        converted.cliff_amount = 0;
+        for ts in (START + 12 * MONTH..=START + 37 * MONTH).step_by(MONTH as usize / 3) {
+            assert_eq!(
+                available_for_withdrawal(&converted, &schedule, ts),
+                available_for_withdrawal(&vesting, &[], ts)
+            );
+        }
+
+        vesting.period_count = MAX_MERGED_TRANCHES as u64 + 1;
+        assert!(schedule_tranches(&vesting, &[]).is_none());
+    }
+
+    #[test]
+    fn test_merge_tranches() {
+        let tranche = |timestamp, amount| Tranche { timestamp, amount };
+        let merged = merge_tranches(&[
+            vec![tranche(START, 100), tranche(START + MONTH, 100)],
+            vec![tranche(START - MONTH, 50), tranche(START + MONTH, 25)],
+        ])
+        .unwrap();
+
+        assert_eq!(
+            merged,
+            vec![
+                tranche(START - MONTH, 50),
+                tranche(START, 100),
+                tranche(START + MONTH, 125)
+            ]
+        );
+        assert!(
+            merge_tranches(&[vec![tranche(START, u64::MAX)], vec![tranche(START, 1)]]).is_none()
+        );
+    }
+
+    #[test]
+    fn test_milestones() {
+        let mut vesting = vesting(0, 0);
+        vesting.schedule_mode = ScheduleMode::Milestones;
+        let milestone = |timestamp, amount, completed_ts| Milestone {
+            timestamp,
+            amount,
+            attestor: Default::default(),
+            completed_ts,
+        };
+        // Attested early but floored at 12 months, attested at 3 months, and
+        // still pending.
+        let milestones = [
+            milestone(START + 12 * MONTH, 1_200, START + MONTH),
+            milestone(0, 1_000, START + 3 * MONTH),
+            milestone(0, 1_400, 0),
+        ];
+        let tranches = milestone_tranches(&milestones);
+
+        assert_eq!(available_for_withdrawal(&vesting, &tranches, START), 0);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &tranches, START + 3 * MONTH),
+            1_000
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &tranches, START + 12 * MONTH),
+            2_200
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &tranches, START + 48 * MONTH),
+            2_200
+        );
+        assert!(schedule_tranches(&vesting, &tranches).is_none());
+    }
+
+    #[test]
+    fn test_revoked() {
+        // Revoked after 12 months with 200 withdrawn: 1_000 left vested and
+        // 2_400 clawed back.
+        let mut vesting = vesting(0, 0);
+        vesting.start_balance = 1_200;
+        vesting.outstanding = 1_000;
+        vesting.revoked = true;
+
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + MONTH),
+            1_000
+        );
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 36 * MONTH),
+            1_000
+        );
+    }
+
+    #[test]
+    fn test_shrink_tranches() {
+        let mut tranches = vec![
+            Tranche {
+                timestamp: START,
+                amount: 1_000,
+            },
+            Tranche {
+                timestamp: START + MONTH,
+                amount: 1_000,
+            },
+            Tranche {
+                timestamp: START + 2 * MONTH,
+                amount: 3_000,
+            },
+        ];
+
+        assert_eq!(shrink_tranches(&mut tranches, 4_001, START), None);
+        assert_eq!(tranches[1].amount, 1_000);
+
+        shrink_tranches(&mut tranches, 1_000, START).unwrap();
+        let amounts: Vec<u64> = tranches.iter().map(|tranche| tranche.amount).collect();
+        assert_eq!(amounts, vec![1_000, 750, 2_250]);
+    }
+
+    #[test]
+    fn test_shrink_milestones() {
+        let milestone = |timestamp, completed_ts, amount| Milestone {
+            timestamp,
+            amount,
+            completed_ts,
+            ..Milestone::default()
+        };
+        let mut milestones = vec![
+            milestone(0, START, 500),
+            // Attested, but its time floor has not passed yet.
+            milestone(START + MONTH, START, 500),
+            milestone(0, 0, 1_000),
+        ];
+
+        shrink_milestones(&mut milestones, 300, START).unwrap();
+        let amounts: Vec<u64> = milestones.iter().map(|m| m.amount).collect();
+        assert_eq!(amounts, vec![500, 400, 800]);
+    }
+
+    // Sums `total_vested` one `stride` at a time, for windows where the
+    // schedule only steps on multiples of `stride`.
+    fn brute_force_seconds(
+        vesting: &VestingState,
+        tranches: &[Tranche],
+        from_ts: u64,
+        to_ts: u64,
+        stride: u64,
+    ) -> u128 {
+        (from_ts..to_ts)
+            .step_by(stride as usize)
+            .map(|ts| total_vested(vesting, tranches, ts) as u128 * stride as u128)
+            .sum()
+    }
+
+    #[test]
+    fn test_vested_seconds_linear() {
+        // Uneven periods and amounts exercise the shifted start and overflow.
+        let seconds = VestingState {
+            start_balance: 1_003,
+            outstanding: 1_003,
+            end_ts: START + 1_000,
+            period_count: 7,
+            cliff_ts: START + 300,
+            cliff_amount: 100,
+            ..vesting(0, 0)
+        };
+        for (from_ts, to_ts) in [
+            (START - 50, START + 1_050),
+            (START + 301, START + 302),
+            (START + 123, START + 877),
+        ] {
+            assert_eq!(
+                vested_seconds(&seconds, &[], from_ts, to_ts),
+                Some(brute_force_seconds(&seconds, &[], from_ts, to_ts, 1))
+            );
+        }
+
+        // Monthly periods step on whole hours of a start at a whole hour.
+        let months = VestingState {
+            start_ts: 1_704_067_200,
+            end_ts: civil::add_months(1_704_067_200, 3).unwrap(),
+            period_count: 3,
+            period_unit: PeriodUnit::Months,
+            ..vesting(0, 0)
+        };
+        let (from_ts, to_ts) = (months.start_ts - 3_600, months.end_ts + 7_200);
+        assert_eq!(
+            vested_seconds(&months, &[], from_ts, to_ts),
+            Some(brute_force_seconds(&months, &[], from_ts, to_ts, 3_600))
+        );
+
+        let days = VestingState {
+            end_ts: START + 5 * civil::SECS_PER_DAY,
+            period_count: 5,
+            period_unit: PeriodUnit::Days,
+            ..vesting(0, 0)
+        };
+        assert_eq!(
+            vested_seconds(&days, &[], START + 3_600, days.end_ts),
+            Some(brute_force_seconds(
+                &days,
+                &[],
+                START + 3_600,
+                days.end_ts,
+                3_600
+            ))
+        );
+    }
+
+    #[test]
+    fn test_vested_seconds_tranches() {
+        let tranched = VestingState {
+            schedule_mode: ScheduleMode::Tranches,
+            ..vesting(START + 10, 0)
+        };
+        let tranches = [
+            Tranche {
+                timestamp: START,
+                amount: 600,
+            },
+            Tranche {
+                timestamp: START + 40,
+                amount: 3_000,
+            },
+        ];
+
+        // 600 from the cliff at +10, 3_600 from +40.
+        assert_eq!(
+            vested_seconds(&tranched, &tranches, START, START + 100),
+            Some(600 * 30 + 3_600 * 60)
+        );
+        assert_eq!(
+            vested_seconds(&tranched, &tranches, START + 5, START + 5),
+            Some(0)
+        );
+
+        let revoked = VestingState {
+            revoked: true,
+            ..tranched
+        };
+        assert_eq!(
+            vested_seconds(&revoked, &tranches, START, START + 100),
+            Some(3_600 * 100)
+        );
+    }
+
+    #[test]
+    fn test_plan_schedule() {
+        const DAY: u64 = civil::SECS_PER_DAY;
+
+        assert_eq!(plan_schedule(START, 30 * DAY), Some((START + 30 * DAY, 30)));
+        assert_eq!(plan_schedule(START, DAY / 2), Some((START + DAY / 2, 1)));
+        assert_eq!(plan_schedule(START, 0), None);
+        assert_eq!(plan_schedule(u64::MAX, DAY), None);
+
+        // Ten and a half days vest in ten even periods of 1.05 days.
+        let (end_ts, period_count) = plan_schedule(START, 21 * DAY / 2).unwrap();
+        let vesting = VestingState {
+            end_ts,
+            period_count,
+            ..vesting(0, 0)
+        };
+        assert_eq!(available_for_withdrawal(&vesting, &[], START + DAY), 0);
+        assert_eq!(
+            available_for_withdrawal(&vesting, &[], START + 3 * DAY / 2),
+            360
+        );
+        assert_eq!(available_for_withdrawal(&vesting, &[], end_ts), 3_600);
+    }
+}
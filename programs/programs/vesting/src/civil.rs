@@ -0,0 +1,123 @@
+//! Calendar arithmetic on unix timestamps, in UTC and the proleptic Gregorian
+//! calendar. Based on Howard Hinnant's `days_from_civil` and `civil_from_days`.
+
+pub const SECS_PER_DAY: u64 = 86_400;
+
+pub fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap(year) => 29,
+        _ => 28,
+    }
+}
+
+/// Days since 1970-01-01 of the given date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The `(year, month, day)` that is `days` days after 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+/// Moves `ts` forward by `months` calendar months, keeping the time of day.
+/// The day of the month is clamped to the length of the target month, so
+/// Jan 31 plus one month is Feb 28, or Feb 29 in a leap year.
+pub fn add_months(ts: u64, months: u64) -> Option<u64> {
+    let days = (ts / SECS_PER_DAY) as i64;
+    let secs_of_day = ts % SECS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+
+    let month_index = (month as i64 - 1).checked_add(months.try_into().ok()?)?;
+    let target_year = year.checked_add(month_index.div_euclid(12))?;
+    let target_month = month_index.rem_euclid(12) as u32 + 1;
+    let target_day = std::cmp::min(day, days_in_month(target_year, target_month));
+
+    let target_days: u64 = days_from_civil(target_year, target_month, target_day)
+        .try_into()
+        .ok()?;
+    target_days
+        .checked_mul(SECS_PER_DAY)?
+        .checked_add(secs_of_day)
+}
+
+/// Moves `ts` forward by `days` days of 86,400 seconds.
+pub fn add_days(ts: u64, days: u64) -> Option<u64> {
+    ts.checked_add(days.checked_mul(SECS_PER_DAY)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2024-01-31T12:00:00Z
+    const JAN_31_2024: u64 = 1_706_702_400;
+
+    #[test]
+    fn test_civil_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_leap_years() {
+        assert!(is_leap(2000));
+        assert!(is_leap(2024));
+        assert!(!is_leap(1900));
+        assert!(!is_leap(2023));
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+    }
+
+    #[test]
+    fn test_add_months_clamps_day() {
+        // Feb 29 2024 in a leap year, Feb 28 2025 otherwise.
+        assert_eq!(add_months(JAN_31_2024, 1), Some(1_709_208_000));
+        assert_eq!(add_months(JAN_31_2024, 13), Some(1_740_744_000));
+        // Back to the 31st two months later.
+        assert_eq!(add_months(JAN_31_2024, 2), Some(1_711_886_400));
+        assert_eq!(add_months(JAN_31_2024, 12), Some(1_738_324_800));
+        assert_eq!(add_months(JAN_31_2024, 0), Some(JAN_31_2024));
+    }
+
+    #[test]
+    fn test_add_days() {
+        assert_eq!(add_days(JAN_31_2024, 1), Some(JAN_31_2024 + 86_400));
+        assert_eq!(add_days(u64::MAX, 1), None);
+    }
+}
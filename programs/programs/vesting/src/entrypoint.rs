@@ -1,11 +1,10 @@
 use solana_program::{
-    account_info::AccountInfo, declare_id, entrypoint, entrypoint::ProgramResult, msg,
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
     program_error::PrintProgramError, pubkey::Pubkey,
 };
 
 use crate::{error::ErrorCode, processor::Processor};
 
-declare_id!("");
 entrypoint!(vesting_entrypoint);
 
 pub fn vesting_entrypoint(
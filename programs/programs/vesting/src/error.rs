@@ -18,6 +18,8 @@ pub enum ErrorCode {
     InvalidPeriod,
     #[error("The vesting deposit amount must be greater than zero.")]
     InvalidDepositAmount,
+    #[error("The Whitelist entry is not a valid program address.")]
+    InvalidWhitelistEntry,
     #[error("Invalid program address. Did you provide the correct nonce?")]
     InvalidProgramAddress,
     #[error("Invalid vault owner.")]
@@ -38,6 +40,52 @@ pub enum ErrorCode {
     UnrealizedVesting,
     #[error("Invalid vesting schedule given.")]
     InvalidSchedule,
+    #[error("This vesting account cannot be revoked.")]
+    Irrevocable,
+    #[error("This vesting account has already been revoked.")]
+    AlreadyRevoked,
+    #[error("Whitelisted program moved more than the requested amount.")]
+    WhitelistWithdrawLimit,
+    #[error("Whitelist deposit exceeds the amount held by whitelisted programs.")]
+    WhitelistDepositOverflow,
+    #[error("The split amount does not divide the vesting schedule evenly.")]
+    InvalidSplitAmount,
+    #[error("Merged vesting accounts must share mint, beneficiary, authority, revocability and metadata.")]
+    IncompatibleMerge,
+    #[error("Only fully withdrawn vesting accounts with an empty vault can be closed.")]
+    VestingNotEmpty,
+    #[error("Withdrawals are paused.")]
+    Paused,
+    #[error("The milestone does not exist.")]
+    InvalidMilestone,
+    #[error("The milestone has already been attested.")]
+    MilestoneAlreadyAttested,
+    #[error("The token transfer fee changed the amount received.")]
+    UnexpectedTransferFee,
+    #[error("This instruction does not support native SOL vesting accounts.")]
+    NativeVaultUnsupported,
+    #[error("The account layout version is not supported.")]
+    UnsupportedVersion,
+    #[error("A batch must hold between one and sixteen entries.")]
+    InvalidBatchSize,
+    #[error("A withdrawal request is already pending.")]
+    WithdrawalPending,
+    #[error("No withdrawal request is pending.")]
+    NoPendingWithdrawal,
+    #[error("The withdrawal timelock has not passed yet.")]
+    WithdrawalTimelocked,
+    #[error("Withdrawals from this vesting account must be requested first.")]
+    WithdrawalRequestRequired,
+    #[error("The vesting plan has expired.")]
+    PlanExpired,
+    #[error("The vesting plan cannot be swept yet.")]
+    SweepUnavailable,
+    #[error("A newer version of the vesting plan exists.")]
+    PlanSuperseded,
+    #[error("The plan is not a newer version of the vesting plan.")]
+    InvalidPlanVersion,
+    #[error("Accrued rewards must be claimed before closing.")]
+    UnclaimedRewards,
 }
 
 impl From<ErrorCode> for ProgramError {
@@ -69,8 +117,10 @@ impl PrintProgramError for ErrorCode {
                 msg!("The vesting deposit amount must be greater than zero.")
             }
             ErrorCode::InvalidWhitelistEntry => {
-                msg!("The Whitelist entry is not a valid program address".)
+                msg!("The Whitelist entry is not a valid program address.")
             }
+            ErrorCode::WhitelistFull => msg!("Whitelist is full"),
+            ErrorCode::WhitelistEntryAlreadyExists => msg!("Whitelist entry already exists"),
             ErrorCode::InvalidProgramAddress => {
                 msg!("Invalid program address. Did you provide the correct nonce?")
             }
@@ -85,6 +135,53 @@ impl PrintProgramError for ErrorCode {
             }
             ErrorCode::UnrealizedVesting => msg!("You have not realized this vesting account."),
             ErrorCode::InvalidSchedule => msg!("The provided vesting schedule is invalid."),
+            ErrorCode::Irrevocable => msg!("This vesting account cannot be revoked."),
+            ErrorCode::AlreadyRevoked => msg!("This vesting account has already been revoked."),
+            ErrorCode::WhitelistWithdrawLimit => {
+                msg!("Whitelisted program moved more than the requested amount.")
+            }
+            ErrorCode::WhitelistDepositOverflow => {
+                msg!("Whitelist deposit exceeds the amount held by whitelisted programs.")
+            }
+            ErrorCode::InvalidSplitAmount => {
+                msg!("The split amount does not divide the vesting schedule evenly.")
+            }
+            ErrorCode::IncompatibleMerge => msg!(
+                "Merged vesting accounts must share mint, beneficiary, authority, revocability and metadata."
+            ),
+            ErrorCode::VestingNotEmpty => {
+                msg!("Only fully withdrawn vesting accounts with an empty vault can be closed.")
+            }
+            ErrorCode::Paused => msg!("Withdrawals are paused."),
+            ErrorCode::InvalidMilestone => msg!("The milestone does not exist."),
+            ErrorCode::MilestoneAlreadyAttested => {
+                msg!("The milestone has already been attested.")
+            }
+            ErrorCode::UnexpectedTransferFee => {
+                msg!("The token transfer fee changed the amount received.")
+            }
+            ErrorCode::NativeVaultUnsupported => {
+                msg!("This instruction does not support native SOL vesting accounts.")
+            }
+            ErrorCode::UnsupportedVersion => msg!("The account layout version is not supported."),
+            ErrorCode::InvalidBatchSize => {
+                msg!("A batch must hold between one and sixteen entries.")
+            }
+            ErrorCode::WithdrawalPending => msg!("A withdrawal request is already pending."),
+            ErrorCode::NoPendingWithdrawal => msg!("No withdrawal request is pending."),
+            ErrorCode::WithdrawalTimelocked => {
+                msg!("The withdrawal timelock has not passed yet.")
+            }
+            ErrorCode::WithdrawalRequestRequired => {
+                msg!("Withdrawals from this vesting account must be requested first.")
+            }
+            ErrorCode::PlanExpired => msg!("The vesting plan has expired."),
+            ErrorCode::SweepUnavailable => msg!("The vesting plan cannot be swept yet."),
+            ErrorCode::PlanSuperseded => msg!("A newer version of the vesting plan exists."),
+            ErrorCode::InvalidPlanVersion => {
+                msg!("The plan is not a newer version of the vesting plan.")
+            }
+            ErrorCode::UnclaimedRewards => msg!("Accrued rewards must be claimed before closing."),
         }
     }
 }
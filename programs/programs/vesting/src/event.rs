@@ -0,0 +1,380 @@
+//! Events logged with `sol_log_data` whenever a vesting account changes, so
+//! that indexers can follow grants from transaction logs alone.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program::{log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+
+use std::convert::TryInto;
+
+use crate::state::{PK_LEN, VESTING_DISCRIMINATOR};
+
+const CREATED: u8 = 0;
+const WITHDRAWN: u8 = 1;
+const BENEFICIARY_CHANGED: u8 = 2;
+const REVOKED: u8 = 3;
+const CLOSED: u8 = 4;
+const ASSOCIATED_ACCOUNT_CREATED: u8 = 5;
+const EARLY_WITHDRAWN: u8 = 6;
+const REWARDS_CLAIMED: u8 = 7;
+const WITHDRAWAL_REQUESTED: u8 = 8;
+const WITHDRAWAL_CANCELLED: u8 = 9;
+const SWEPT: u8 = 10;
+const PLAN_ADOPTED: u8 = 11;
+
+const EV_DISC: usize = 0;
+const EV_TAG: usize = EV_DISC + 1;
+const EV_ACCOUNT: usize = EV_TAG + 1;
+const EV_AMOUNT: usize = EV_ACCOUNT + PK_LEN;
+const EV_TS: usize = EV_AMOUNT + 8;
+const EV_OUTSTANDING: usize = EV_TS + 8;
+const EV_BENE: usize = EV_OUTSTANDING + 8;
+const EV_PREVIOUS: usize = EV_BENE + PK_LEN;
+const EV_PENALTY: usize = EV_BENE;
+const EV_UNLOCK_TS: usize = EV_BENE;
+const EV_PLAN: usize = EV_BENE;
+
+/// Prefix of the log lines written by `sol_log_data`.
+pub const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+/// Fields shared by every vesting event.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EventData {
+    /// The vesting account that changed.
+    pub account: Pubkey,
+    /// Tokens, or lamports for native vaults, moved by the instruction.
+    pub amount: u64,
+    /// Cluster time of the change.
+    pub timestamp: u64,
+    /// `outstanding` of the vesting account after the change.
+    pub outstanding: u64,
+}
+
+/// Events start with `VESTING_DISCRIMINATOR` followed by a one byte tag, the
+/// `EventData` fields and any variant specific fields.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VestingEvent {
+    /// A grant was funded by `CreateVesting` or `CreateMilestoneVesting`.
+    Created(EventData),
+    Withdrawn(EventData),
+    /// The grant moved from `previous_account` to `data.account`, the
+    /// associated vesting account of `beneficiary`.
+    BeneficiaryChanged {
+        data: EventData,
+        beneficiary: Pubkey,
+        previous_account: Pubkey,
+    },
+    /// `amount` went back to the grantor.
+    Revoked(EventData),
+    Closed(EventData),
+    /// Logged by the associated vesting account program.
+    AssociatedAccountCreated(EventData),
+    /// `amount` left the vault before vesting, `penalty` of it went to the
+    /// reward vault.
+    EarlyWithdrawn {
+        data: EventData,
+        penalty: u64,
+    },
+    /// `amount` of accrued rewards was paid from the reward vault.
+    RewardsClaimed(EventData),
+    /// A withdrawal of `amount` was requested, completing it is possible
+    /// from `unlock_ts`.
+    WithdrawalRequested {
+        data: EventData,
+        unlock_ts: u64,
+    },
+    /// The authority cancelled the pending withdrawal of `amount`.
+    WithdrawalCancelled(EventData),
+    /// `amount` of unclaimed vested tokens went back to the grantor after
+    /// the plan expired.
+    Swept(EventData),
+    /// The beneficiary moved the grant to the terms of `plan`, a newer
+    /// version of its plan.
+    PlanAdopted {
+        data: EventData,
+        plan: Pubkey,
+    },
+}
+
+impl EventData {
+    fn pack_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.account.as_ref());
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        buf.extend_from_slice(&self.outstanding.to_le_bytes());
+    }
+
+    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let account = data
+            .get(EV_ACCOUNT..EV_AMOUNT)
+            .and_then(|s| s.try_into().ok())
+            .map(Pubkey::new_from_array)
+            .ok_or(ProgramError::InvalidAccountData)?;
+
+        let amount = data
+            .get(EV_AMOUNT..EV_TS)
+            .and_then(|s| s.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidAccountData)?;
+
+        let timestamp = data
+            .get(EV_TS..EV_OUTSTANDING)
+            .and_then(|s| s.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidAccountData)?;
+
+        let outstanding = data
+            .get(EV_OUTSTANDING..EV_BENE)
+            .and_then(|s| s.try_into().ok())
+            .map(u64::from_le_bytes)
+            .ok_or(ProgramError::InvalidAccountData)?;
+
+        Ok(Self {
+            account,
+            amount,
+            timestamp,
+            outstanding,
+        })
+    }
+}
+
+impl VestingEvent {
+    pub fn data(&self) -> &EventData {
+        match self {
+            Self::Created(data)
+            | Self::Withdrawn(data)
+            | Self::BeneficiaryChanged { data, .. }
+            | Self::Revoked(data)
+            | Self::Closed(data)
+            | Self::AssociatedAccountCreated(data)
+            | Self::EarlyWithdrawn { data, .. }
+            | Self::RewardsClaimed(data)
+            | Self::WithdrawalRequested { data, .. }
+            | Self::WithdrawalCancelled(data)
+            | Self::Swept(data)
+            | Self::PlanAdopted { data, .. } => data,
+        }
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(EV_PREVIOUS + PK_LEN);
+        buf.push(VESTING_DISCRIMINATOR);
+        buf.push(match self {
+            Self::Created(_) => CREATED,
+            Self::Withdrawn(_) => WITHDRAWN,
+            Self::BeneficiaryChanged { .. } => BENEFICIARY_CHANGED,
+            Self::Revoked(_) => REVOKED,
+            Self::Closed(_) => CLOSED,
+            Self::AssociatedAccountCreated(_) => ASSOCIATED_ACCOUNT_CREATED,
+            Self::EarlyWithdrawn { .. } => EARLY_WITHDRAWN,
+            Self::RewardsClaimed(_) => REWARDS_CLAIMED,
+            Self::WithdrawalRequested { .. } => WITHDRAWAL_REQUESTED,
+            Self::WithdrawalCancelled(_) => WITHDRAWAL_CANCELLED,
+            Self::Swept(_) => SWEPT,
+            Self::PlanAdopted { .. } => PLAN_ADOPTED,
+        });
+        self.data().pack_into(&mut buf);
+
+        if let Self::BeneficiaryChanged {
+            beneficiary,
+            previous_account,
+            ..
+        } = self
+        {
+            buf.extend_from_slice(beneficiary.as_ref());
+            buf.extend_from_slice(previous_account.as_ref());
+        }
+        if let Self::EarlyWithdrawn { penalty, .. } = self {
+            buf.extend_from_slice(&penalty.to_le_bytes());
+        }
+        if let Self::WithdrawalRequested { unlock_ts, .. } = self {
+            buf.extend_from_slice(&unlock_ts.to_le_bytes());
+        }
+        if let Self::PlanAdopted { plan, .. } = self {
+            buf.extend_from_slice(plan.as_ref());
+        }
+
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.get(EV_DISC) != Some(&VESTING_DISCRIMINATOR) {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let tag = *data.get(EV_TAG).ok_or(ProgramError::InvalidAccountData)?;
+        let event_data = EventData::unpack(data)?;
+
+        let event = match tag {
+            CREATED => Self::Created(event_data),
+            WITHDRAWN => Self::Withdrawn(event_data),
+            BENEFICIARY_CHANGED => {
+                let beneficiary = data
+                    .get(EV_BENE..EV_PREVIOUS)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+
+                let previous_account = data
+                    .get(EV_PREVIOUS..EV_PREVIOUS + PK_LEN)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+
+                Self::BeneficiaryChanged {
+                    data: event_data,
+                    beneficiary,
+                    previous_account,
+                }
+            }
+            REVOKED => Self::Revoked(event_data),
+            CLOSED => Self::Closed(event_data),
+            ASSOCIATED_ACCOUNT_CREATED => Self::AssociatedAccountCreated(event_data),
+            EARLY_WITHDRAWN => {
+                let penalty = data
+                    .get(EV_PENALTY..EV_PENALTY + 8)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+
+                Self::EarlyWithdrawn {
+                    data: event_data,
+                    penalty,
+                }
+            }
+            REWARDS_CLAIMED => Self::RewardsClaimed(event_data),
+            WITHDRAWAL_REQUESTED => {
+                let unlock_ts = data
+                    .get(EV_UNLOCK_TS..EV_UNLOCK_TS + 8)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+
+                Self::WithdrawalRequested {
+                    data: event_data,
+                    unlock_ts,
+                }
+            }
+            WITHDRAWAL_CANCELLED => Self::WithdrawalCancelled(event_data),
+            SWEPT => Self::Swept(event_data),
+            PLAN_ADOPTED => {
+                let plan = data
+                    .get(EV_PLAN..EV_PLAN + PK_LEN)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+
+                Self::PlanAdopted {
+                    data: event_data,
+                    plan,
+                }
+            }
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        if event.pack().len() != data.len() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(event)
+    }
+
+    /// Logs the event as a single `sol_log_data` field.
+    pub fn emit(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+
+    /// Decodes a single log message. Anything but a vesting event, including
+    /// data logged by other programs, gives `None`.
+    pub fn from_log(log: &str) -> Option<Self> {
+        let field = log.strip_prefix(PROGRAM_DATA_PREFIX)?;
+        let data = STANDARD.decode(field.trim()).ok()?;
+        Self::unpack(&data).ok()
+    }
+
+    /// Decodes every vesting event in the log messages of a transaction, in
+    /// the order they were logged.
+    pub fn from_logs<S: AsRef<str>>(logs: &[S]) -> Vec<Self> {
+        logs.iter()
+            .filter_map(|log| Self::from_log(log.as_ref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event_data() -> EventData {
+        EventData {
+            account: Pubkey::new_unique(),
+            amount: 250,
+            timestamp: 1_706_702_400,
+            outstanding: 750,
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let data = event_data();
+        let events = [
+            VestingEvent::Created(data),
+            VestingEvent::Withdrawn(data),
+            VestingEvent::BeneficiaryChanged {
+                data,
+                beneficiary: Pubkey::new_unique(),
+                previous_account: Pubkey::new_unique(),
+            },
+            VestingEvent::Revoked(data),
+            VestingEvent::Closed(data),
+            VestingEvent::AssociatedAccountCreated(data),
+            VestingEvent::EarlyWithdrawn { data, penalty: 25 },
+            VestingEvent::RewardsClaimed(data),
+            VestingEvent::WithdrawalRequested {
+                data,
+                unlock_ts: 1_706_788_800,
+            },
+            VestingEvent::WithdrawalCancelled(data),
+            VestingEvent::Swept(data),
+            VestingEvent::PlanAdopted {
+                data,
+                plan: Pubkey::new_unique(),
+            },
+        ];
+
+        for event in events {
+            let buf = event.pack();
+            assert_eq!(VestingEvent::unpack(&buf).unwrap(), event);
+            assert!(VestingEvent::unpack(&buf[..buf.len() - 1]).is_err());
+        }
+
+        let mut buf = VestingEvent::Closed(data).pack();
+        buf[EV_DISC] = b'M';
+        assert!(VestingEvent::unpack(&buf).is_err());
+        buf[EV_DISC] = VESTING_DISCRIMINATOR;
+        buf[EV_TAG] = 0xff;
+        assert!(VestingEvent::unpack(&buf).is_err());
+    }
+
+    #[test]
+    fn test_from_logs() {
+        let created = VestingEvent::Created(event_data());
+        let withdrawn = VestingEvent::Withdrawn(event_data());
+        let logs = vec![
+            "Program SCYQKBoArPUXrAykFDX2XVp21kfrVe7mxoVmgrVsKLv invoke [1]".to_string(),
+            format!("{}{}", PROGRAM_DATA_PREFIX, STANDARD.encode(created.pack())),
+            format!(
+                "{}{}",
+                PROGRAM_DATA_PREFIX,
+                STANDARD.encode(b"not an event")
+            ),
+            "Program log: Vesting: Withdraw".to_string(),
+            format!(
+                "{}{}",
+                PROGRAM_DATA_PREFIX,
+                STANDARD.encode(withdrawn.pack())
+            ),
+        ];
+
+        assert_eq!(VestingEvent::from_logs(&logs), vec![created, withdrawn]);
+        assert_eq!(VestingEvent::from_log(&logs[0]), None);
+    }
+}
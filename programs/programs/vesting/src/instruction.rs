@@ -2,10 +2,20 @@ use solana_program::{
     instruction::{AccountMeta, Instruction},
     msg,
     program_error::ProgramError,
+    program_pack::Pack,
     pubkey::Pubkey,
 };
 
-use crate::{error::ErrorCode::InvalidInstruction, state::PK_LEN};
+use crate::{
+    error::ErrorCode::InvalidInstruction,
+    state::{
+        find_program_data_address, find_reward_authority_address, find_vesting_address,
+        find_vesting_signer_address, pack_milestones, pack_tranches, unpack_milestones,
+        unpack_tranches, Milestone, PeriodUnit, Tranche, PK_LEN,
+    },
+};
+
+use vesting_metadata::state::find_registrar_address;
 
 use std::convert::TryInto;
 use std::mem::size_of;
@@ -14,103 +24,765 @@ const IX_INIT: u8 = 0;
 const IX_CREATE: u8 = 1;
 const IX_WITHDRAW: u8 = 2;
 const IX_SETBENEFICIARY: u8 = 3;
+const IX_REVOKE: u8 = 4;
+const IX_INIT_WHITELIST: u8 = 5;
+const IX_WHITELIST_ADD: u8 = 6;
+const IX_WHITELIST_DELETE: u8 = 7;
+const IX_WHITELIST_WITHDRAW: u8 = 8;
+const IX_WHITELIST_DEPOSIT: u8 = 9;
+const IX_SPLIT: u8 = 10;
+const IX_MERGE: u8 = 11;
+const IX_CLOSE: u8 = 12;
+const IX_INIT_CONFIG: u8 = 13;
+const IX_PAUSE: u8 = 14;
+const IX_RESUME: u8 = 15;
+const IX_CREATE_MILESTONES: u8 = 16;
+const IX_ATTEST_MILESTONE: u8 = 17;
+const IX_MIGRATE: u8 = 18;
+const IX_CREATE_BATCH: u8 = 19;
+const IX_EARLY_WITHDRAW: u8 = 20;
+const IX_CLAIM_REWARDS: u8 = 21;
+const IX_REQUEST_WITHDRAWAL: u8 = 22;
+const IX_COMPLETE_WITHDRAWAL: u8 = 23;
+const IX_CANCEL_WITHDRAWAL: u8 = 24;
+const IX_SWEEP: u8 = 25;
+const IX_CREATE_FROM_METADATA: u8 = 26;
+const IX_ADOPT_PLAN_VERSION: u8 = 27;
 
+const BENEFICIARY: usize = 0;
 const S_TS: usize = BENEFICIARY + PK_LEN;
 const E_TS: usize = S_TS + 8;
 const N: usize = E_TS + 8;
-const NONCE: usize = N + 8;
-const AMOUNT: usize = NONCE + 8;
+const PERIOD_UNIT: usize = N + 8;
+const CLIFF_TS: usize = PERIOD_UNIT + 1;
+const CLIFF_AMT: usize = CLIFF_TS + 8;
+const AMOUNT: usize = CLIFF_AMT + 8;
+const REVOCABLE: usize = AMOUNT + 8;
+const TRANCHES: usize = REVOCABLE + 1;
+
+const MS_BENEFICIARY: usize = 0;
+const MS_AMOUNT: usize = MS_BENEFICIARY + PK_LEN;
+const MS_REVOCABLE: usize = MS_AMOUNT + 8;
+const MILESTONES: usize = MS_REVOCABLE + 1;
+
+const B_S_TS: usize = 0;
+const B_E_TS: usize = B_S_TS + 8;
+const B_N: usize = B_E_TS + 8;
+const B_PERIOD_UNIT: usize = B_N + 8;
+const B_CLIFF_TS: usize = B_PERIOD_UNIT + 1;
+const B_CLIFF_BPS: usize = B_CLIFF_TS + 8;
+const B_REVOCABLE: usize = B_CLIFF_BPS + 2;
+const B_ENTRIES: usize = B_REVOCABLE + 1;
+
+const P_BENEFICIARY: usize = 0;
+const P_AMOUNT: usize = P_BENEFICIARY + PK_LEN;
+const P_HAS_START: usize = P_AMOUNT + 8;
+const P_START_TS: usize = P_HAS_START + 1;
+
+/// A beneficiary of a `CreateVestingBatch` and the amount granted to them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BatchEntry {
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+}
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+impl BatchEntry {
+    pub const LEN: usize = PK_LEN + 8;
+
+    fn unpack(src: &[u8]) -> Option<Self> {
+        let beneficiary = Pubkey::new_from_array(src.get(..PK_LEN)?.try_into().ok()?);
+        let amount = u64::from_le_bytes(src.get(PK_LEN..Self::LEN)?.try_into().ok()?);
+
+        Some(Self {
+            beneficiary,
+            amount,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum VestingInstruction {
-    /// Convenience function for creating vesting account
+    /// Convenience function for creating vesting account with room for
+    /// `tranche_count` tranches or `milestone_count` milestones after the
+    /// `VestingState` header. The vesting account must be at
+    /// `find_vesting_address(mint, beneficiary, index)`, and can then only be
+    /// created for that beneficiary and mint.
     ///
     /// Accounts expected:
     /// `[s,w]` Authority
     /// `[w]` Vesting Account
+    /// `[]` Mint
+    /// `[]` Beneficiary
     /// `[]` System Program
-    Init,
+    Init {
+        tranche_count: u32,
+        milestone_count: u32,
+        index: u64,
+    },
 
+    /// Creates a linear vesting when `tranches` is empty. Otherwise the
+    /// tranches are the schedule: timestamps must be strictly increasing,
+    /// the amounts must add up to `amount`, and `start_ts`, `end_ts` and
+    /// `period_count` are taken from them.
+    ///
+    /// The Vesting Account is either one `Init` created for `beneficiary` and
+    /// the mint, or their associated vesting account.
+    ///
+    /// Vaults may belong to the token program or Token-2022. For mints with
+    /// a transfer fee the fee is paid on top, so that exactly `amount`
+    /// reaches the vault.
+    ///
+    /// When the Metadata Account is a plan of the metadata program, the plan
+    /// must not have outlived its `lifetime`, and the grant is committed to
+    /// it through the Registrar, see `vesting_metadata::state`.
+    ///
+    /// To vest SOL, pass the vesting account as the Vault, the authority as
+    /// the Token Account, the native mint and the System Program. The
+    /// lamports are then held by the vesting account above its rent-exempt
+    /// minimum. Native vesting accounts support `Withdraw`, `Revoke` and
+    /// `Close`, but cannot be split, merged, moved or relayed.
+    ///
     /// Accounts expected:
     ///
     /// `[s,w]` Authority
     /// `[w]` Token Account
     /// `[w]` Vesting Account
     /// `[w]` Vault
-    /// `[]` Metadata Account
+    /// `[w]` Metadata Account
+    /// `[]` Mint
     /// `[]` Token Program
+    /// `[]` Metadata Program
+    /// `[]` Registrar
     CreateVesting {
         beneficiary: Pubkey,
         start_ts: u64,
         end_ts: u64,
         period_count: u64,
-        nonce: u8,
+        /// The length of each period. For calendar units `end_ts` must be
+        /// `period_count` units after `start_ts`.
+        period_unit: PeriodUnit,
+        /// Nothing vests before this timestamp, zero for no cliff.
+        cliff_ts: u64,
+        /// Released in one step at `cliff_ts`.
+        cliff_amount: u64,
+        amount: u64,
+        /// Whether the authority may later `Revoke` the grant.
+        revocable: bool,
+        tranches: Vec<Tranche>,
+    },
+
+    /// Creates a vesting that unlocks each milestone once its attestor signs
+    /// `AttestMilestone`, and not before the milestone's time floor. The
+    /// milestone amounts must add up to `amount`.
+    ///
+    /// Accounts expected are the same as `CreateVesting`.
+    CreateMilestoneVesting {
+        beneficiary: Pubkey,
         amount: u64,
+        revocable: bool,
+        milestones: Vec<Milestone>,
     },
 
+    /// Rejected while the vesting account or the whole program is paused.
+    /// Transfer fees of Token-2022 mints are paid out of `amount`. Native
+    /// vesting accounts pay lamports straight to the Token Account and expect
+    /// the System Program. Grants whose metadata sets a `withdrawal_timelock`
+    /// withdraw through `RequestWithdrawal` instead.
+    ///
     /// Accounts expected:
     ///
-    /// `[s,w]` Authority
+    /// `[s,w]` Beneficiary
     /// `[w]` Token Account
     /// `[w]` Vesting Account
     /// `[w]` Vault
+    /// `[]` Vesting Signer
     /// `[]` Metadata Account
+    /// `[]` Mint
     /// `[]` Token Program
+    /// `[]` Config
     Withdraw { amount: u64 },
 
+    /// Moves the grant to the associated vesting account of
+    /// `new_beneficiary`, which must already exist and be uninitialized.
+    /// The vault balance and the remaining schedule are moved over and the
+    /// old vesting account and vault are closed, refunding the rent receiver
+    /// of the grant. The authority pays the rent of the new vesting account,
+    /// which keeps the same rent receiver.
+    ///
     /// Accounts Expected:
     ///
     /// `[w,s]` Authority
-    /// `[w]` New Beneficiary Authority
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[]` New Beneficiary Authority
     /// `[w]` New Beneficiary Vesting Account
     /// `[w]` New Beneficiary Token Account
+    /// `[]` Mint
     /// `[]` Token Program
+    /// `[]` System Program
+    /// `[w]` Rent Receiver
     SetBeneficiary { new_beneficiary: Pubkey },
+
+    /// Ends a revocable grant. Tokens vested so far stay withdrawable by the
+    /// beneficiary, the unvested remainder is sent to the given token account.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Authority
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[w]` Grantor Token Account
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[w]` Metadata Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    Revoke,
+
+    /// Creates the program-wide whitelist at `find_whitelist_address` with
+    /// `admin` as its administrator. Only the upgrade authority of the
+    /// program, as recorded in its Program Data, may create it.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Upgrade Authority
+    /// `[w]` Whitelist
+    /// `[]` System Program
+    /// `[]` Program Data
+    InitWhitelist { admin: Pubkey },
+
+    /// Accounts expected:
+    ///
+    /// `[s]` Admin
+    /// `[w]` Whitelist
+    WhitelistAdd { entry: Pubkey },
+
+    /// Accounts expected:
+    ///
+    /// `[s]` Admin
+    /// `[w]` Whitelist
+    WhitelistDelete { entry: Pubkey },
+
+    /// Relays `data` to a whitelisted program, signed by the vesting signer,
+    /// so that it can move at most `amount` locked tokens out of the vault.
+    /// The tokens moved are tracked in `whitelist_owned`, and those of a
+    /// pending withdrawal cannot be moved. Fails while the vesting account or
+    /// the program is paused.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Beneficiary
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[]` Whitelist
+    /// `[]` Whitelisted Program
+    /// `[]` Config
+    /// Remaining accounts are passed through to the whitelisted program.
+    WhitelistWithdraw { amount: u64, data: Vec<u8> },
+
+    /// Relays `data` to a whitelisted program that returns tokens to the
+    /// vault, reducing `whitelist_owned` accordingly. Fails while the vesting
+    /// account or the program is paused.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Beneficiary
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[]` Whitelist
+    /// `[]` Whitelisted Program
+    /// `[]` Config
+    /// Remaining accounts are passed through to the whitelisted program.
+    WhitelistDeposit { data: Vec<u8> },
+
+    /// Moves `amount` of the outstanding balance, and a proportional share of
+    /// the start balance, cliff and tranches, into a new vesting account for
+    /// the new beneficiary. The new vesting account must have been created
+    /// with `Init` and its vault must be owned by its canonical vesting
+    /// signer. A linear schedule that cannot be divided evenly into its
+    /// periods is converted to tranches, and the authority pays for any
+    /// growth of either account.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Authority
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[]` New Beneficiary
+    /// `[w]` New Vesting Account
+    /// `[w]` New Vault
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` System Program
+    /// `[w]` Metadata Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    Split { amount: u64 },
+
+    /// Merges one or more source vesting accounts into the destination. All
+    /// of them must share the mint, beneficiary and authority, and have the
+    /// same `revocable` setting. The schedules are combined into a tranche
+    /// list, the source vaults are emptied into the destination vault and the
+    /// source accounts are closed, refunding the rent receiver of each.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Authority
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` System Program
+    /// `[w]` Metadata Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    /// For each source, in this order:
+    /// `[w]` Source Vesting Account
+    /// `[w]` Source Vault
+    /// `[]` Source Vesting Signer
+    /// `[w]` Source Rent Receiver
+    Merge,
+
+    /// Closes a fully withdrawn vesting account and its empty vault. All of
+    /// their lamports go to the `rent_receiver` recorded in the vesting
+    /// account. Refused while accrued rewards have not been claimed.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Authority or Beneficiary
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[w]` Rent Receiver
+    /// `[]` Token Program
+    /// `[w]` Metadata Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    Close,
+
+    /// Creates the program-wide config at `find_config_address` with `admin`
+    /// as the global admin. Only the upgrade authority of the program may
+    /// create it, as with `InitWhitelist`.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Upgrade Authority
+    /// `[w]` Config
+    /// `[]` System Program
+    /// `[]` Program Data
+    InitConfig { admin: Pubkey },
+
+    /// Freezes withdrawals. Passing the config pauses the whole program and
+    /// requires the global admin, passing a vesting account pauses only that
+    /// account and requires its authority.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Authority or Admin
+    /// `[w]` Vesting Account or Config
+    Pause,
+
+    /// Lifts a `Pause`, with the same accounts.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Authority or Admin
+    /// `[w]` Vesting Account or Config
+    Resume,
+
+    /// Marks the milestone at `index` as completed.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Attestor
+    /// `[w]` Vesting Account
+    AttestMilestone { index: u32 },
+
+    /// Rewrites a vesting account in the current `VestingState` layout,
+    /// resizing it and topping up its rent from the payer. Accounts that are
+    /// already up to date are left untouched. Migrated grants are committed
+    /// to the counters of their plan, which must have been migrated first.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Payer
+    /// `[w]` Vesting Account
+    /// `[]` System Program
+    /// `[w]` Metadata Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    Migrate,
+
+    /// Creates one linear vesting per entry, all on the same schedule. The
+    /// total is pulled from the Token Account in a single transfer into the
+    /// first vault, which then funds the other vaults. For mints with a
+    /// transfer fee the fee is paid on top for both transfers, so that each
+    /// vault receives exactly its entry's amount. Every entry is checked
+    /// before any tokens move.
+    ///
+    /// Each vesting account must be uninitialized and sized for a linear
+    /// schedule, like for `CreateVesting`, and the plan must not have
+    /// expired. Native SOL is not supported.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Authority
+    /// `[w]` Token Account
+    /// `[w]` Metadata Account
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` Vault Signer of the first Vesting Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    /// For each entry, in order:
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    CreateVestingBatch {
+        start_ts: u64,
+        end_ts: u64,
+        period_count: u64,
+        period_unit: PeriodUnit,
+        cliff_ts: u64,
+        /// Share of each entry released at `cliff_ts`, in basis points.
+        cliff_bps: u16,
+        revocable: bool,
+        entries: Vec<BatchEntry>,
+    },
+
+    /// Withdraws `amount` of the tokens that have not vested yet. The early
+    /// withdrawal fee of the metadata account, in basis points of `amount`,
+    /// goes to the treasury of the metadata account, the rest to the Token
+    /// Account. The unlocks still to come shrink in proportion to make up
+    /// for it, so that nothing already vested changes. Linear schedules are
+    /// turned into tranches for this, paid for by the beneficiary.
+    ///
+    /// Rejected while paused, while tokens are held by whitelisted programs,
+    /// when the metadata sets a withdrawal timelock and for native vesting
+    /// accounts.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Beneficiary
+    /// `[w]` Token Account
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[w]` Metadata Account
+    /// `[w]` Treasury
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` Config
+    /// `[]` System Program
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    EarlyWithdraw { amount: u64 },
+
+    /// Pays out the rewards accrued by the vesting account at the APR of its
+    /// metadata, see the `rewards` module. The reward vault is the metadata
+    /// vault and must be owned by `find_reward_authority_address` of the
+    /// first version of the plan. If it holds less than was accrued, the rest
+    /// stays accrued for a later claim.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Beneficiary
+    /// `[w]` Token Account
+    /// `[w]` Vesting Account
+    /// `[]` Metadata Account
+    /// `[w]` Reward Vault
+    /// `[]` Reward Authority
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` Config
+    ClaimRewards,
+
+    /// Records a withdrawal of `amount` vested tokens that can be completed
+    /// with `CompleteWithdrawal` once the `withdrawal_timelock` of the
+    /// metadata has passed. Only one request can be pending at a time.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Beneficiary
+    /// `[w]` Vesting Account
+    /// `[]` Metadata Account
+    /// `[]` Config
+    RequestWithdrawal { amount: u64 },
+
+    /// Releases the pending withdrawal to the token account, as `Withdraw`
+    /// would.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Beneficiary
+    /// `[w]` Token Account
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[]` Metadata Account
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` Config
+    CompleteWithdrawal,
+
+    /// Drops the pending withdrawal, e.g. when the beneficiary key may be
+    /// compromised.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Authority
+    /// `[w]` Vesting Account
+    CancelWithdrawal,
+
+    /// Sends whatever has vested but was not withdrawn back to the grantor,
+    /// once the plan of the vesting account has expired and its sweep grace
+    /// period has passed. Only plans that opted in with `sweepable` can be
+    /// swept. Anyone may send this instruction.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[]` Vesting Signer
+    /// `[]` Metadata Account
+    /// `[w]` Grantor Token Account
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` Config
+    Sweep,
+
+    /// Creates a linear vesting on the schedule of a plan. It starts at
+    /// `start_ts`, or now if `None`, and lasts the plan's `duration`, vesting
+    /// once a day, see `calculator::plan_schedule`. The grant has no cliff
+    /// and cannot be revoked. Otherwise this is `CreateVesting`, except that
+    /// the Metadata Account must be an initialized plan of the metadata
+    /// program.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s,w]` Authority
+    /// `[w]` Token Account
+    /// `[w]` Vesting Account
+    /// `[w]` Vault
+    /// `[w]` Metadata Account
+    /// `[]` Mint
+    /// `[]` Token Program
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    CreateVestingFromMetadata {
+        beneficiary: Pubkey,
+        amount: u64,
+        start_ts: Option<u64>,
+    },
+
+    /// Moves the grant to the New Metadata Account, a newer version of the
+    /// plan it was signed under. Rewards accrued so far are settled on the
+    /// old terms. The new version must be the latest and not expired, and
+    /// the grant moves from the counters of the old version to the new one.
+    ///
+    /// Accounts expected:
+    ///
+    /// `[s]` Beneficiary
+    /// `[w]` Vesting Account
+    /// `[w]` Metadata Account
+    /// `[w]` New Metadata Account
+    /// `[]` Metadata Program
+    /// `[]` Registrar
+    AdoptPlanVersion,
 }
 
 impl VestingInstruction {
-    fn pack(&self) -> Vec<u8> {
+    pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
 
         match self {
-            Self::Init => buf.push(IX_INIT),
+            Self::Init {
+                tranche_count,
+                milestone_count,
+                index,
+            } => {
+                buf.push(IX_INIT);
+                buf.extend_from_slice(&tranche_count.to_le_bytes());
+                buf.extend_from_slice(&milestone_count.to_le_bytes());
+                buf.extend_from_slice(&index.to_le_bytes());
+            }
             Self::CreateVesting {
                 beneficiary,
                 start_ts,
                 end_ts,
                 period_count,
-                nonce,
+                period_unit,
+                cliff_ts,
+                cliff_amount,
                 amount,
+                revocable,
+                tranches,
             } => {
                 buf.push(IX_CREATE);
                 buf.extend_from_slice(beneficiary.as_ref());
                 buf.extend_from_slice(&start_ts.to_le_bytes());
                 buf.extend_from_slice(&end_ts.to_le_bytes());
                 buf.extend_from_slice(&period_count.to_le_bytes());
-                buf.extend_from_slice(&nonce.to_le_bytes());
+                buf.push(*period_unit as u8);
+                buf.extend_from_slice(&cliff_ts.to_le_bytes());
+                buf.extend_from_slice(&cliff_amount.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.push(*revocable as u8);
+
+                let offset = buf.len();
+                buf.resize(offset + tranches.len() * Tranche::LEN, 0);
+                pack_tranches(tranches, &mut buf[offset..]);
+            }
+            Self::CreateMilestoneVesting {
+                beneficiary,
+                amount,
+                revocable,
+                milestones,
+            } => {
+                buf.push(IX_CREATE_MILESTONES);
+                buf.extend_from_slice(beneficiary.as_ref());
                 buf.extend_from_slice(&amount.to_le_bytes());
+                buf.push(*revocable as u8);
+
+                let offset = buf.len();
+                buf.resize(offset + milestones.len() * Milestone::LEN, 0);
+                pack_milestones(milestones, &mut buf[offset..]);
             }
             Self::Withdraw { amount } => {
                 buf.push(IX_WITHDRAW);
                 buf.extend_from_slice(&amount.to_le_bytes());
             }
-            Self::SetBeneficiary {
-                new_beneficiary: Pubkey,
-            } => {
+            Self::SetBeneficiary { new_beneficiary } => {
                 buf.push(IX_SETBENEFICIARY);
                 buf.extend_from_slice(new_beneficiary.as_ref());
             }
+            Self::Revoke => buf.push(IX_REVOKE),
+            Self::InitWhitelist { admin } => {
+                buf.push(IX_INIT_WHITELIST);
+                buf.extend_from_slice(admin.as_ref());
+            }
+            Self::WhitelistAdd { entry } => {
+                buf.push(IX_WHITELIST_ADD);
+                buf.extend_from_slice(entry.as_ref());
+            }
+            Self::WhitelistDelete { entry } => {
+                buf.push(IX_WHITELIST_DELETE);
+                buf.extend_from_slice(entry.as_ref());
+            }
+            Self::WhitelistWithdraw { amount, data } => {
+                buf.push(IX_WHITELIST_WITHDRAW);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(data);
+            }
+            Self::WhitelistDeposit { data } => {
+                buf.push(IX_WHITELIST_DEPOSIT);
+                buf.extend_from_slice(data);
+            }
+            Self::Split { amount } => {
+                buf.push(IX_SPLIT);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Merge => buf.push(IX_MERGE),
+            Self::Close => buf.push(IX_CLOSE),
+            Self::InitConfig { admin } => {
+                buf.push(IX_INIT_CONFIG);
+                buf.extend_from_slice(admin.as_ref());
+            }
+            Self::Pause => buf.push(IX_PAUSE),
+            Self::Resume => buf.push(IX_RESUME),
+            Self::AttestMilestone { index } => {
+                buf.push(IX_ATTEST_MILESTONE);
+                buf.extend_from_slice(&index.to_le_bytes());
+            }
+            Self::Migrate => buf.push(IX_MIGRATE),
+            Self::CreateVestingBatch {
+                start_ts,
+                end_ts,
+                period_count,
+                period_unit,
+                cliff_ts,
+                cliff_bps,
+                revocable,
+                entries,
+            } => {
+                buf.push(IX_CREATE_BATCH);
+                buf.extend_from_slice(&start_ts.to_le_bytes());
+                buf.extend_from_slice(&end_ts.to_le_bytes());
+                buf.extend_from_slice(&period_count.to_le_bytes());
+                buf.push(*period_unit as u8);
+                buf.extend_from_slice(&cliff_ts.to_le_bytes());
+                buf.extend_from_slice(&cliff_bps.to_le_bytes());
+                buf.push(*revocable as u8);
+                for entry in entries {
+                    buf.extend_from_slice(entry.beneficiary.as_ref());
+                    buf.extend_from_slice(&entry.amount.to_le_bytes());
+                }
+            }
+            Self::EarlyWithdraw { amount } => {
+                buf.push(IX_EARLY_WITHDRAW);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::ClaimRewards => buf.push(IX_CLAIM_REWARDS),
+            Self::RequestWithdrawal { amount } => {
+                buf.push(IX_REQUEST_WITHDRAWAL);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::CompleteWithdrawal => buf.push(IX_COMPLETE_WITHDRAWAL),
+            Self::CancelWithdrawal => buf.push(IX_CANCEL_WITHDRAWAL),
+            Self::Sweep => buf.push(IX_SWEEP),
+            Self::CreateVestingFromMetadata {
+                beneficiary,
+                amount,
+                start_ts,
+            } => {
+                buf.push(IX_CREATE_FROM_METADATA);
+                buf.extend_from_slice(beneficiary.as_ref());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                match start_ts {
+                    Some(start_ts) => {
+                        buf.push(1);
+                        buf.extend_from_slice(&start_ts.to_le_bytes());
+                    }
+                    None => buf.push(0),
+                }
+            }
+            Self::AdoptPlanVersion => buf.push(IX_ADOPT_PLAN_VERSION),
         }
         buf
     }
 
-    fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
         let (tag, rest) = data.split_first().ok_or(InvalidInstruction)?;
-        Ok(match tag {
-            IX_INIT => Self::Init,
+        Ok(match *tag {
+            IX_INIT => {
+                let tranche_count = rest
+                    .get(..4)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u32::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let milestone_count = rest
+                    .get(4..8)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u32::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let index = rest
+                    .get(8..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::Init {
+                    tranche_count,
+                    milestone_count,
+                    index,
+                }
+            }
             IX_CREATE => {
                 let beneficiary = rest
-                    .get(..S_TS)
+                    .get(BENEFICIARY..S_TS)
                     .and_then(|s| s.try_into().ok())
                     .map(Pubkey::new_from_array)
                     .ok_or(InvalidInstruction)?;
@@ -125,36 +797,85 @@ impl VestingInstruction {
                     .map(u64::from_le_bytes)
                     .ok_or(InvalidInstruction)?;
                 let period_count = rest
-                    .get(N..NONCE)
+                    .get(N..PERIOD_UNIT)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let period_unit = rest
+                    .get(PERIOD_UNIT)
+                    .and_then(|unit| PeriodUnit::from_u8(*unit))
+                    .ok_or(InvalidInstruction)?;
+                let cliff_ts = rest
+                    .get(CLIFF_TS..CLIFF_AMT)
                     .and_then(|s| s.try_into().ok())
                     .map(u64::from_le_bytes)
                     .ok_or(InvalidInstruction)?;
-                let nonce = rest
-                    .get(NONCE..AMOUNT)
+                let cliff_amount = rest
+                    .get(CLIFF_AMT..AMOUNT)
                     .and_then(|s| s.try_into().ok())
-                    .map(u8::from_le_bytes)
+                    .map(u64::from_le_bytes)
                     .ok_or(InvalidInstruction)?;
                 let amount = rest
-                    .get(AMOUNT..)
+                    .get(AMOUNT..REVOCABLE)
                     .and_then(|s| s.try_into().ok())
                     .map(u64::from_le_bytes)
                     .ok_or(InvalidInstruction)?;
+                let revocable = match rest.get(REVOCABLE) {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let tranches = rest
+                    .get(TRANCHES..)
+                    .and_then(|s| unpack_tranches(s).ok())
+                    .ok_or(InvalidInstruction)?;
                 Self::CreateVesting {
                     beneficiary,
                     start_ts,
                     end_ts,
                     period_count,
-                    nonce,
+                    period_unit,
+                    cliff_ts,
+                    cliff_amount,
                     amount,
+                    revocable,
+                    tranches,
                 }
             }
-            IX_WITHDRAW => {
+            IX_CREATE_MILESTONES => {
+                let beneficiary = rest
+                    .get(MS_BENEFICIARY..MS_AMOUNT)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
                 let amount = rest
-                    .get(..)
+                    .get(MS_AMOUNT..MS_REVOCABLE)
                     .and_then(|s| s.try_into().ok())
                     .map(u64::from_le_bytes)
                     .ok_or(InvalidInstruction)?;
-                Self::Withdraw { amount }
+                let revocable = match rest.get(MS_REVOCABLE) {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let milestones = rest
+                    .get(MILESTONES..)
+                    .and_then(|s| unpack_milestones(s).ok())
+                    .ok_or(InvalidInstruction)?;
+                Self::CreateMilestoneVesting {
+                    beneficiary,
+                    amount,
+                    revocable,
+                    milestones,
+                }
+            }
+            IX_WITHDRAW => {
+                let amount = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::Withdraw { amount }
             }
             IX_SETBENEFICIARY => {
                 let new_beneficiary = rest
@@ -164,26 +885,217 @@ impl VestingInstruction {
                     .ok_or(InvalidInstruction)?;
                 Self::SetBeneficiary { new_beneficiary }
             }
+            IX_REVOKE => Self::Revoke,
+            IX_INIT_WHITELIST => {
+                let admin = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
+                Self::InitWhitelist { admin }
+            }
+            IX_WHITELIST_ADD => {
+                let entry = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
+                Self::WhitelistAdd { entry }
+            }
+            IX_WHITELIST_DELETE => {
+                let entry = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
+                Self::WhitelistDelete { entry }
+            }
+            IX_WHITELIST_WITHDRAW => {
+                let amount = rest
+                    .get(..8)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let data = rest[8..].to_vec();
+                Self::WhitelistWithdraw { amount, data }
+            }
+            IX_WHITELIST_DEPOSIT => Self::WhitelistDeposit {
+                data: rest.to_vec(),
+            },
+            IX_SPLIT => {
+                let amount = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::Split { amount }
+            }
+            IX_MERGE => Self::Merge,
+            IX_CLOSE => Self::Close,
+            IX_INIT_CONFIG => {
+                let admin = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
+                Self::InitConfig { admin }
+            }
+            IX_PAUSE => Self::Pause,
+            IX_RESUME => Self::Resume,
+            IX_ATTEST_MILESTONE => {
+                let index = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u32::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::AttestMilestone { index }
+            }
+            IX_MIGRATE => Self::Migrate,
+            IX_CREATE_BATCH => {
+                let start_ts = rest
+                    .get(B_S_TS..B_E_TS)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let end_ts = rest
+                    .get(B_E_TS..B_N)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let period_count = rest
+                    .get(B_N..B_PERIOD_UNIT)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let period_unit = rest
+                    .get(B_PERIOD_UNIT)
+                    .and_then(|unit| PeriodUnit::from_u8(*unit))
+                    .ok_or(InvalidInstruction)?;
+                let cliff_ts = rest
+                    .get(B_CLIFF_TS..B_CLIFF_BPS)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let cliff_bps = rest
+                    .get(B_CLIFF_BPS..B_REVOCABLE)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u16::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let revocable = match rest.get(B_REVOCABLE) {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                let entries = rest
+                    .get(B_ENTRIES..)
+                    .filter(|s| s.len() % BatchEntry::LEN == 0)
+                    .and_then(|s| {
+                        s.chunks_exact(BatchEntry::LEN)
+                            .map(BatchEntry::unpack)
+                            .collect()
+                    })
+                    .ok_or(InvalidInstruction)?;
+                Self::CreateVestingBatch {
+                    start_ts,
+                    end_ts,
+                    period_count,
+                    period_unit,
+                    cliff_ts,
+                    cliff_bps,
+                    revocable,
+                    entries,
+                }
+            }
+            IX_EARLY_WITHDRAW => {
+                let amount = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::EarlyWithdraw { amount }
+            }
+            IX_CLAIM_REWARDS => Self::ClaimRewards,
+            IX_REQUEST_WITHDRAWAL => {
+                let amount = rest
+                    .get(..)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                Self::RequestWithdrawal { amount }
+            }
+            IX_COMPLETE_WITHDRAWAL => Self::CompleteWithdrawal,
+            IX_CANCEL_WITHDRAWAL => Self::CancelWithdrawal,
+            IX_SWEEP => Self::Sweep,
+            IX_CREATE_FROM_METADATA => {
+                let beneficiary = rest
+                    .get(P_BENEFICIARY..P_AMOUNT)
+                    .and_then(|s| s.try_into().ok())
+                    .map(Pubkey::new_from_array)
+                    .ok_or(InvalidInstruction)?;
+                let amount = rest
+                    .get(P_AMOUNT..P_HAS_START)
+                    .and_then(|s| s.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(InvalidInstruction)?;
+                let start_ts = match rest.get(P_HAS_START) {
+                    Some(0) if rest.len() == P_START_TS => None,
+                    Some(1) => rest
+                        .get(P_START_TS..)
+                        .and_then(|s| s.try_into().ok())
+                        .map(u64::from_le_bytes)
+                        .map(Some)
+                        .ok_or(InvalidInstruction)?,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::CreateVestingFromMetadata {
+                    beneficiary,
+                    amount,
+                    start_ts,
+                }
+            }
+            IX_ADOPT_PLAN_VERSION => Self::AdoptPlanVersion,
             _ => return Err(ProgramError::InvalidArgument),
         })
     }
 }
 
+// The accounts instructions that open or close grants of a plan pass on to
+// its `Commit` or `Release`.
+fn plan_accounts(program_id: &Pubkey) -> [AccountMeta; 2] {
+    [
+        AccountMeta::new_readonly(vesting_metadata::id(), false),
+        AccountMeta::new_readonly(find_registrar_address(program_id).0, false),
+    ]
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn init(
     program_id: &Pubkey,
     payer: &Pubkey,
-    vesting: &Pubkey,
+    mint: &Pubkey,
+    beneficiary: &Pubkey,
     system_program: &Pubkey,
+    tranche_count: u32,
+    milestone_count: u32,
+    index: u64,
 ) -> Result<Instruction, ProgramError> {
     msg!("Vesting: Init");
 
+    let (vesting, _) = find_vesting_address(program_id, mint, beneficiary, index);
     let accounts = vec![
         AccountMeta::new(*payer, true),
-        AccountMeta::new(*vesting, false),
+        AccountMeta::new(vesting, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*beneficiary, false),
         AccountMeta::new_readonly(*system_program, false),
     ];
 
-    let data = VestingIntruction::Init.pack();
+    let data = VestingInstruction::Init {
+        tranche_count,
+        milestone_count,
+        index,
+    }
+    .pack();
 
     Ok(Instruction {
         program_id: *program_id,
@@ -192,6 +1104,7 @@ pub fn init(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_vesting(
     program_id: &Pubkey,
     vesting: &Pubkey,
@@ -199,31 +1112,127 @@ pub fn create_vesting(
     authority: &Pubkey,
     token_account: &Pubkey,
     metadata: &Pubkey,
+    mint: &Pubkey,
     token_program: &Pubkey,
+    beneficiary: Pubkey,
     start_ts: u64,
     end_ts: u64,
     period_count: u64,
-    nonce: u8,
+    period_unit: PeriodUnit,
+    cliff_ts: u64,
+    cliff_amount: u64,
     amount: u64,
-) -> Result<Instruction, ProgramErro> {
+    revocable: bool,
+    tranches: Vec<Tranche>,
+) -> Result<Instruction, ProgramError> {
     msg!("Vesting: Create");
 
-    let accounts = vec![
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*token_account, false),
         AccountMeta::new(*vesting, false),
         AccountMeta::new(*vault, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::CreateVesting {
+        beneficiary,
+        start_ts,
+        end_ts,
+        period_count,
+        period_unit,
+        cliff_ts,
+        cliff_amount,
+        amount,
+        revocable,
+        tranches,
+    }
+    .pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_vesting_from_metadata(
+    program_id: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    token_account: &Pubkey,
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    beneficiary: Pubkey,
+    amount: u64,
+    start_ts: Option<u64>,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Create From Metadata");
+
+    let mut accounts = vec![
         AccountMeta::new(*authority, true),
         AccountMeta::new(*token_account, false),
-        AccountMeta::new_readonly(*metadata, false),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new_readonly(*mint, false),
         AccountMeta::new_readonly(*token_program, false),
     ];
+    accounts.extend(plan_accounts(program_id));
 
-    let data = VestingInstruction::CreateVesting {
+    let data = VestingInstruction::CreateVestingFromMetadata {
         beneficiary,
+        amount,
         start_ts,
-        end_ts,       // should be calculated utlizing metadata
-        period_count, // should pull from metadata
-        nonce,
+    }
+    .pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_milestone_vesting(
+    program_id: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    authority: &Pubkey,
+    token_account: &Pubkey,
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    beneficiary: Pubkey,
+    amount: u64,
+    revocable: bool,
+    milestones: Vec<Milestone>,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Create Milestones");
+
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::CreateMilestoneVesting {
+        beneficiary,
         amount,
+        revocable,
+        milestones,
     }
     .pack();
 
@@ -234,28 +1243,35 @@ pub fn create_vesting(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn withdraw(
     program_id: &Pubkey,
     vesting: &Pubkey,
     vault: &Pubkey,
-    authority: &Pubkey,
+    vesting_signer: &Pubkey,
+    beneficiary: &Pubkey,
     token_account: &Pubkey,
     metadata: &Pubkey,
+    mint: &Pubkey,
     token_program: &Pubkey,
+    config: &Pubkey,
     amount: u64,
 ) -> Result<Instruction, ProgramError> {
     msg!("Vesting: Withdraw");
 
     let accounts = vec![
+        AccountMeta::new(*beneficiary, true),
+        AccountMeta::new(*token_account, false),
         AccountMeta::new(*vesting, false),
         AccountMeta::new(*vault, false),
-        AccountMeta::new(*authority, true),
-        AccountMeta::new(*token_account, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
         AccountMeta::new_readonly(*metadata, false),
+        AccountMeta::new_readonly(*mint, false),
         AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*config, false),
     ];
 
-    let data = VestingInstruction::Withdraw { amount: u64 }.pack();
+    let data = VestingInstruction::Withdraw { amount }.pack();
 
     Ok(Instruction {
         program_id: *program_id,
@@ -264,22 +1280,35 @@ pub fn withdraw(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn set_beneficiary(
     program_id: &Pubkey,
     authority: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
     new_beneficiary: &Pubkey,
     new_beneficiary_vesting_address: &Pubkey,
     new_beneficiary_token_address: &Pubkey,
+    mint: &Pubkey,
     token_program: &Pubkey,
+    system_program: &Pubkey,
+    rent_receiver: &Pubkey,
 ) -> Result<Instruction, ProgramError> {
     msg!("Vesting: Set Beneficiary");
 
     let accounts = vec![
         AccountMeta::new(*authority, true),
-        AccountMeta::new(*new_beneficiary, false),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new_readonly(*new_beneficiary, false),
         AccountMeta::new(*new_beneficiary_vesting_address, false),
         AccountMeta::new(*new_beneficiary_token_address, false),
+        AccountMeta::new_readonly(*mint, false),
         AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*system_program, false),
+        AccountMeta::new(*rent_receiver, false),
     ];
 
     let data = VestingInstruction::SetBeneficiary {
@@ -293,3 +1322,863 @@ pub fn set_beneficiary(
         data,
     })
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn revoke(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    grantor_token_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    metadata: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Revoke");
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new(*grantor_token_account, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new(*metadata, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::Revoke.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn init_whitelist(
+    program_id: &Pubkey,
+    upgrade_authority: &Pubkey,
+    whitelist: &Pubkey,
+    system_program: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Init Whitelist");
+
+    let accounts = vec![
+        AccountMeta::new(*upgrade_authority, true),
+        AccountMeta::new(*whitelist, false),
+        AccountMeta::new_readonly(*system_program, false),
+        AccountMeta::new_readonly(find_program_data_address(program_id), false),
+    ];
+
+    let data = VestingInstruction::InitWhitelist { admin: *admin }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn whitelist_add(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    whitelist: &Pubkey,
+    entry: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Whitelist Add");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*whitelist, false),
+    ];
+
+    let data = VestingInstruction::WhitelistAdd { entry: *entry }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn whitelist_delete(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    whitelist: &Pubkey,
+    entry: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Whitelist Delete");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*whitelist, false),
+    ];
+
+    let data = VestingInstruction::WhitelistDelete { entry: *entry }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn whitelist_withdraw(
+    program_id: &Pubkey,
+    beneficiary: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    whitelist: &Pubkey,
+    whitelisted_program: &Pubkey,
+    config: &Pubkey,
+    relay_accounts: Vec<AccountMeta>,
+    amount: u64,
+    data: Vec<u8>,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Whitelist Withdraw");
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*beneficiary, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new_readonly(*whitelist, false),
+        AccountMeta::new_readonly(*whitelisted_program, false),
+        AccountMeta::new_readonly(*config, false),
+    ];
+    accounts.extend(relay_accounts);
+
+    let data = VestingInstruction::WhitelistWithdraw { amount, data }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn whitelist_deposit(
+    program_id: &Pubkey,
+    beneficiary: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    whitelist: &Pubkey,
+    whitelisted_program: &Pubkey,
+    config: &Pubkey,
+    relay_accounts: Vec<AccountMeta>,
+    data: Vec<u8>,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Whitelist Deposit");
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*beneficiary, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new_readonly(*whitelist, false),
+        AccountMeta::new_readonly(*whitelisted_program, false),
+        AccountMeta::new_readonly(*config, false),
+    ];
+    accounts.extend(relay_accounts);
+
+    let data = VestingInstruction::WhitelistDeposit { data }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn split(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    new_beneficiary: &Pubkey,
+    new_vesting: &Pubkey,
+    new_vault: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    system_program: &Pubkey,
+    metadata: &Pubkey,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Split");
+
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new_readonly(*new_beneficiary, false),
+        AccountMeta::new(*new_vesting, false),
+        AccountMeta::new(*new_vault, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*system_program, false),
+        AccountMeta::new(*metadata, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::Split { amount }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// `sources` holds the vesting account, vault, vesting signer and rent
+/// receiver of each account merged into `vesting`.
+#[allow(clippy::too_many_arguments)]
+pub fn merge(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    system_program: &Pubkey,
+    metadata: &Pubkey,
+    sources: &[(Pubkey, Pubkey, Pubkey, Pubkey)],
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Merge");
+
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*system_program, false),
+        AccountMeta::new(*metadata, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+    for (source_vesting, source_vault, source_signer, source_rent_receiver) in sources {
+        accounts.push(AccountMeta::new(*source_vesting, false));
+        accounts.push(AccountMeta::new(*source_vault, false));
+        accounts.push(AccountMeta::new_readonly(*source_signer, false));
+        accounts.push(AccountMeta::new(*source_rent_receiver, false));
+    }
+
+    let data = VestingInstruction::Merge.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn close(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    rent_receiver: &Pubkey,
+    token_program: &Pubkey,
+    metadata: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Close");
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*signer, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new(*rent_receiver, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new(*metadata, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::Close.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn init_config(
+    program_id: &Pubkey,
+    upgrade_authority: &Pubkey,
+    config: &Pubkey,
+    system_program: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Init Config");
+
+    let accounts = vec![
+        AccountMeta::new(*upgrade_authority, true),
+        AccountMeta::new(*config, false),
+        AccountMeta::new_readonly(*system_program, false),
+        AccountMeta::new_readonly(find_program_data_address(program_id), false),
+    ];
+
+    let data = VestingInstruction::InitConfig { admin: *admin }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// `target` is either a vesting account, signed for by its authority, or the
+/// config, signed for by the global admin.
+pub fn pause(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    target: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Pause");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*signer, true),
+        AccountMeta::new(*target, false),
+    ];
+
+    let data = VestingInstruction::Pause.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn resume(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    target: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Resume");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*signer, true),
+        AccountMeta::new(*target, false),
+    ];
+
+    let data = VestingInstruction::Resume.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn attest_milestone(
+    program_id: &Pubkey,
+    attestor: &Pubkey,
+    vesting: &Pubkey,
+    index: u32,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Attest Milestone");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*attestor, true),
+        AccountMeta::new(*vesting, false),
+    ];
+
+    let data = VestingInstruction::AttestMilestone { index }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// `accounts` holds the vesting account and vault of each entry, in order.
+#[allow(clippy::too_many_arguments)]
+pub fn create_vesting_batch(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    token_account: &Pubkey,
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    accounts: &[(Pubkey, Pubkey)],
+    start_ts: u64,
+    end_ts: u64,
+    period_count: u64,
+    period_unit: PeriodUnit,
+    cliff_ts: u64,
+    cliff_bps: u16,
+    revocable: bool,
+    entries: Vec<BatchEntry>,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Create Batch");
+
+    let first_vesting = accounts
+        .first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?
+        .0;
+    let (vesting_signer, _) = find_vesting_signer_address(program_id, &first_vesting);
+
+    let mut metas = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(vesting_signer, false),
+    ];
+    metas.extend(plan_accounts(program_id));
+    for (vesting, vault) in accounts {
+        metas.push(AccountMeta::new(*vesting, false));
+        metas.push(AccountMeta::new(*vault, false));
+    }
+
+    let data = VestingInstruction::CreateVestingBatch {
+        start_ts,
+        end_ts,
+        period_count,
+        period_unit,
+        cliff_ts,
+        cliff_bps,
+        revocable,
+        entries,
+    }
+    .pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: metas,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn early_withdraw(
+    program_id: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    beneficiary: &Pubkey,
+    token_account: &Pubkey,
+    metadata: &Pubkey,
+    treasury: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    config: &Pubkey,
+    system_program: &Pubkey,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Early Withdraw");
+
+    let mut accounts = vec![
+        AccountMeta::new(*beneficiary, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new(*treasury, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*config, false),
+        AccountMeta::new_readonly(*system_program, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::EarlyWithdraw { amount }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn claim_rewards(
+    program_id: &Pubkey,
+    beneficiary: &Pubkey,
+    token_account: &Pubkey,
+    vesting: &Pubkey,
+    metadata: &Pubkey,
+    plan_root: &Pubkey,
+    reward_vault: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    config: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Claim Rewards");
+
+    let (reward_authority, _) = find_reward_authority_address(program_id, plan_root);
+    let accounts = vec![
+        AccountMeta::new_readonly(*beneficiary, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new_readonly(*metadata, false),
+        AccountMeta::new(*reward_vault, false),
+        AccountMeta::new_readonly(reward_authority, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*config, false),
+    ];
+
+    let data = VestingInstruction::ClaimRewards.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn request_withdrawal(
+    program_id: &Pubkey,
+    beneficiary: &Pubkey,
+    vesting: &Pubkey,
+    metadata: &Pubkey,
+    config: &Pubkey,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Request Withdrawal");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*beneficiary, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new_readonly(*metadata, false),
+        AccountMeta::new_readonly(*config, false),
+    ];
+
+    let data = VestingInstruction::RequestWithdrawal { amount }.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn complete_withdrawal(
+    program_id: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    beneficiary: &Pubkey,
+    token_account: &Pubkey,
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    config: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Complete Withdrawal");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*beneficiary, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new_readonly(*metadata, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*config, false),
+    ];
+
+    let data = VestingInstruction::CompleteWithdrawal.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn cancel_withdrawal(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    vesting: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Cancel Withdrawal");
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*vesting, false),
+    ];
+
+    let data = VestingInstruction::CancelWithdrawal.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn sweep(
+    program_id: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    vesting_signer: &Pubkey,
+    metadata: &Pubkey,
+    grantor_token_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    config: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Sweep");
+
+    let accounts = vec![
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*vault, false),
+        AccountMeta::new_readonly(*vesting_signer, false),
+        AccountMeta::new_readonly(*metadata, false),
+        AccountMeta::new(*grantor_token_account, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(*config, false),
+    ];
+
+    let data = VestingInstruction::Sweep.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn migrate(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    vesting: &Pubkey,
+    system_program: &Pubkey,
+    metadata: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Migrate");
+
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new_readonly(*system_program, false),
+        AccountMeta::new(*metadata, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::Migrate.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+pub fn adopt_plan_version(
+    program_id: &Pubkey,
+    beneficiary: &Pubkey,
+    vesting: &Pubkey,
+    metadata: &Pubkey,
+    new_metadata: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    msg!("Vesting: Adopt Plan Version");
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*beneficiary, true),
+        AccountMeta::new(*vesting, false),
+        AccountMeta::new(*metadata, false),
+        AccountMeta::new(*new_metadata, false),
+    ];
+    accounts.extend(plan_accounts(program_id));
+
+    let data = VestingInstruction::AdoptPlanVersion.pack();
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pack_unpack_create_vesting() {
+        let ix = VestingInstruction::CreateVesting {
+            beneficiary: Pubkey::new_unique(),
+            start_ts: 1_600_000_000,
+            end_ts: 1_700_000_000,
+            period_count: 36,
+            period_unit: PeriodUnit::Months,
+            cliff_ts: 1_631_536_000,
+            cliff_amount: 250,
+            amount: 1_000,
+            revocable: true,
+            tranches: vec![],
+        };
+        let data = ix.pack();
+
+        assert_eq!(data.len(), 1 + TRANCHES);
+        assert_eq!(VestingInstruction::unpack(&data).unwrap(), ix);
+
+        let mut data = data;
+        data[1 + REVOCABLE] = 2;
+        assert!(VestingInstruction::unpack(&data).is_err());
+
+        data[1 + REVOCABLE] = 1;
+        data[1 + PERIOD_UNIT] = 4;
+        assert!(VestingInstruction::unpack(&data).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_create_vesting_tranches() {
+        let ix = VestingInstruction::CreateVesting {
+            beneficiary: Pubkey::new_unique(),
+            start_ts: 0,
+            end_ts: 0,
+            period_count: 0,
+            period_unit: PeriodUnit::Seconds,
+            cliff_ts: 0,
+            cliff_amount: 0,
+            amount: 300,
+            revocable: false,
+            tranches: vec![
+                Tranche {
+                    timestamp: 1_600_000_000,
+                    amount: 100,
+                },
+                Tranche {
+                    timestamp: 1_602_592_000,
+                    amount: 200,
+                },
+            ],
+        };
+        let data = ix.pack();
+
+        assert_eq!(data.len(), 1 + TRANCHES + 2 * Tranche::LEN);
+        assert_eq!(VestingInstruction::unpack(&data).unwrap(), ix);
+        assert!(VestingInstruction::unpack(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_create_milestone_vesting() {
+        let ix = VestingInstruction::CreateMilestoneVesting {
+            beneficiary: Pubkey::new_unique(),
+            amount: 300,
+            revocable: true,
+            milestones: vec![
+                Milestone {
+                    timestamp: 0,
+                    amount: 100,
+                    attestor: Pubkey::new_unique(),
+                    completed_ts: 0,
+                },
+                Milestone {
+                    timestamp: 1_700_000_000,
+                    amount: 200,
+                    attestor: Pubkey::new_unique(),
+                    completed_ts: 0,
+                },
+            ],
+        };
+        let data = ix.pack();
+
+        assert_eq!(data.len(), 1 + MILESTONES + 2 * Milestone::LEN);
+        assert_eq!(VestingInstruction::unpack(&data).unwrap(), ix);
+        assert!(VestingInstruction::unpack(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_create_vesting_from_metadata() {
+        for start_ts in [None, Some(1_700_000_000)] {
+            let ix = VestingInstruction::CreateVestingFromMetadata {
+                beneficiary: Pubkey::new_unique(),
+                amount: 1_000,
+                start_ts,
+            };
+            let data = ix.pack();
+
+            assert_eq!(VestingInstruction::unpack(&data).unwrap(), ix);
+            assert!(VestingInstruction::unpack(&data[..data.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack_init() {
+        let ix = VestingInstruction::Init {
+            tranche_count: 3,
+            milestone_count: 0,
+            index: 7,
+        };
+        let data = ix.pack();
+
+        assert_eq!(data.len(), 1 + 4 + 4 + 8);
+        assert_eq!(VestingInstruction::unpack(&data).unwrap(), ix);
+        assert!(VestingInstruction::unpack(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_create_vesting_batch() {
+        let ix = VestingInstruction::CreateVestingBatch {
+            start_ts: 1_700_000_000,
+            end_ts: 1_731_536_000,
+            period_count: 12,
+            period_unit: PeriodUnit::Months,
+            cliff_ts: 1_702_592_000,
+            cliff_bps: 2_500,
+            revocable: true,
+            entries: vec![
+                BatchEntry {
+                    beneficiary: Pubkey::new_unique(),
+                    amount: 1_000,
+                },
+                BatchEntry {
+                    beneficiary: Pubkey::new_unique(),
+                    amount: 2_000,
+                },
+            ],
+        };
+        let data = ix.pack();
+
+        assert_eq!(data.len(), 1 + B_ENTRIES + 2 * BatchEntry::LEN);
+        assert_eq!(VestingInstruction::unpack(&data).unwrap(), ix);
+        assert!(VestingInstruction::unpack(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_withdraw() {
+        let ix = VestingInstruction::Withdraw { amount: 42 };
+        assert_eq!(VestingInstruction::unpack(&ix.pack()).unwrap(), ix);
+
+        let ix = VestingInstruction::RequestWithdrawal { amount: 42 };
+        assert_eq!(VestingInstruction::unpack(&ix.pack()).unwrap(), ix);
+    }
+
+    #[test]
+    fn test_pack_unpack_whitelist_relay() {
+        let ix = VestingInstruction::WhitelistWithdraw {
+            amount: 500,
+            data: vec![1, 2, 3],
+        };
+        assert_eq!(VestingInstruction::unpack(&ix.pack()).unwrap(), ix);
+
+        let ix = VestingInstruction::WhitelistDeposit { data: vec![] };
+        assert_eq!(VestingInstruction::unpack(&ix.pack()).unwrap(), ix);
+    }
+}
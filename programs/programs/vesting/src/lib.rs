@@ -1,8 +1,14 @@
+pub mod associated;
 pub mod calculator;
+pub mod civil;
 pub mod error;
+pub mod event;
 pub mod instruction;
 pub mod processor;
+pub mod rewards;
 pub mod state;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
+
+solana_program::declare_id!("SCYQKBoArPUXrAykFDX2XVp21kfrVe7mxoVmgrVsKLv");
@@ -1,12 +1,51 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
+    program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    program_utils::limited_deserialize,
     pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::{create_account, transfer as transfer_lamports},
+    system_program,
+    sysvar::Sysvar,
 };
 
-use crate::{error::ErrorCode, instruction::VestingInstruction, state::VestingState};
+use spl_token_2022::{
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+    instruction::{close_account, transfer_checked},
+    native_mint,
+    state::{Account, Mint},
+};
+
+use vesting_metadata::{
+    instruction as metadata_instruction,
+    state::{find_registrar_address, MetadataState, REGISTRAR_SEED},
+};
+
+use crate::{
+    associated::{
+        self, get_associated_vesting_address, get_associated_vesting_address_and_bump_seed,
+    },
+    calculator,
+    error::ErrorCode,
+    event::{EventData, VestingEvent},
+    instruction::{BatchEntry, VestingInstruction},
+    rewards,
+    state::{
+        find_config_address, find_program_data_address, find_reward_authority_address,
+        find_vesting_address, find_vesting_signer_address, find_whitelist_address, pack_milestones,
+        pack_tranches, unpack_milestones, unpack_tranches, ConfigState, Milestone, PeriodUnit,
+        ScheduleMode, Tranche, VaultKind, VestingState, WhitelistState, CONFIG_SEED,
+        MAX_BATCH_SIZE, MAX_BPS, REWARD_SEED, VESTING_SEED, VESTING_VERSION, WHITELIST_SEED,
+        WHITELIST_SIZE,
+    },
+};
 
 pub struct Processor;
 
@@ -15,16 +54,22 @@ impl Processor {
         let instruction = VestingInstruction::unpack(data)?;
 
         match instruction {
-            VestingInstruction::Init => Self::process_init(program_id, accounts)?,
+            VestingInstruction::Init {
+                tranche_count,
+                milestone_count,
+                index,
+            } => Self::process_init(program_id, accounts, tranche_count, milestone_count, index)?,
             VestingInstruction::CreateVesting {
-                program_id,
-                accounts,
                 beneficiary,
                 start_ts,
                 end_ts,
                 period_count,
-                nonce,
+                period_unit,
+                cliff_ts,
+                cliff_amount,
                 amount,
+                revocable,
+                tranches,
             } => Self::process_create_vesting(
                 program_id,
                 accounts,
@@ -32,54 +77,193 @@ impl Processor {
                 start_ts,
                 end_ts,
                 period_count,
-                nonce,
+                period_unit,
+                cliff_ts,
+                cliff_amount,
+                amount,
+                revocable,
+                tranches,
+                vec![],
+            )?,
+            VestingInstruction::CreateMilestoneVesting {
+                beneficiary,
+                amount,
+                revocable,
+                milestones,
+            } => Self::process_create_vesting(
+                program_id,
+                accounts,
+                &beneficiary,
+                0,
+                0,
+                0,
+                PeriodUnit::Seconds,
+                0,
+                0,
                 amount,
+                revocable,
+                vec![],
+                milestones,
             )?,
             VestingInstruction::Withdraw { amount } => {
-                Self::process_withdraw(program_id, accounts, amount)?;
+                Self::process_withdraw(program_id, accounts, Some(amount))?;
             }
             VestingInstruction::SetBeneficiary { new_beneficiary } => {
-                Self::process_set_beneficiary(program_id, accounts, new_beneficiary)?;
+                Self::process_set_beneficiary(program_id, accounts, new_beneficiary)?
+            }
+            VestingInstruction::Revoke => Self::process_revoke(program_id, accounts)?,
+            VestingInstruction::InitWhitelist { admin } => {
+                Self::process_init_whitelist(program_id, accounts, admin)?
+            }
+            VestingInstruction::WhitelistAdd { entry } => {
+                Self::process_whitelist_add(program_id, accounts, entry)?
+            }
+            VestingInstruction::WhitelistDelete { entry } => {
+                Self::process_whitelist_delete(program_id, accounts, entry)?
+            }
+            VestingInstruction::WhitelistWithdraw { amount, data } => {
+                Self::process_whitelist_relay(program_id, accounts, Some(amount), data)?
+            }
+            VestingInstruction::WhitelistDeposit { data } => {
+                Self::process_whitelist_relay(program_id, accounts, None, data)?
+            }
+            VestingInstruction::Split { amount } => {
+                Self::process_split(program_id, accounts, amount)?
+            }
+            VestingInstruction::Merge => Self::process_merge(program_id, accounts)?,
+            VestingInstruction::Close => Self::process_close(program_id, accounts)?,
+            VestingInstruction::InitConfig { admin } => {
+                Self::process_init_config(program_id, accounts, admin)?
+            }
+            VestingInstruction::Pause => Self::process_set_paused(program_id, accounts, true)?,
+            VestingInstruction::Resume => Self::process_set_paused(program_id, accounts, false)?,
+            VestingInstruction::AttestMilestone { index } => {
+                Self::process_attest_milestone(program_id, accounts, index)?
+            }
+            VestingInstruction::Migrate => Self::process_migrate(program_id, accounts)?,
+            VestingInstruction::EarlyWithdraw { amount } => {
+                Self::process_early_withdraw(program_id, accounts, amount)?
+            }
+            VestingInstruction::CreateVestingBatch {
+                start_ts,
+                end_ts,
+                period_count,
+                period_unit,
+                cliff_ts,
+                cliff_bps,
+                revocable,
+                entries,
+            } => Self::process_create_vesting_batch(
+                program_id,
+                accounts,
+                start_ts,
+                end_ts,
+                period_count,
+                period_unit,
+                cliff_ts,
+                cliff_bps,
+                revocable,
+                entries,
+            )?,
+            VestingInstruction::ClaimRewards => Self::process_claim_rewards(program_id, accounts)?,
+            VestingInstruction::RequestWithdrawal { amount } => {
+                Self::process_request_withdrawal(program_id, accounts, amount)?
             }
+            VestingInstruction::CompleteWithdrawal => {
+                Self::process_withdraw(program_id, accounts, None)?
+            }
+            VestingInstruction::CancelWithdrawal => {
+                Self::process_cancel_withdrawal(program_id, accounts)?
+            }
+            VestingInstruction::Sweep => Self::process_sweep(program_id, accounts)?,
+            VestingInstruction::AdoptPlanVersion => {
+                Self::process_adopt_plan_version(program_id, accounts)?
+            }
+            VestingInstruction::CreateVestingFromMetadata {
+                beneficiary,
+                amount,
+                start_ts,
+            } => Self::process_create_vesting_from_metadata(
+                program_id,
+                accounts,
+                &beneficiary,
+                amount,
+                start_ts,
+            )?,
         }
         Ok(())
     }
 
-    fn process_init(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+    fn process_init(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        tranche_count: u32,
+        milestone_count: u32,
+        index: u64,
+    ) -> Result<(), ProgramError> {
         let accounts_iter = &mut accounts.iter();
 
         let authority = next_account_info(accounts_iter)?;
-        let vesting = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let beneficiary = next_account_info(accounts_iter)?;
         let system_program = next_account_info(accounts_iter)?;
 
-        let rent = Rent::get()?;
-        let vesting_account_pda = Pubkey::create_program_address(&[&seed], &program_id).unwrap();
-        if vesting_account_pda != *vesting_account.key {
-            msg!("Incorrect vesting account address");
-            return Err(ProgramError::InvalidArgument);
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
         }
 
+        let (vesting_address, bump) =
+            find_vesting_address(program_id, mint.key, beneficiary.key, index);
+        if vesting_address != *vesting_account.key {
+            msg!("Incorrect vesting address");
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        let rent = Rent::get()?;
+        let space = VestingState::LEN
+            + tranche_count as usize * Tranche::LEN
+            + milestone_count as usize * Milestone::LEN;
+
         let initialize_vesting_account_ix = create_account(
-            &authority.key,
-            &vesting_account_pda,
-            rent.minimum_balance(VestingState::LEN),
-            VestingState::LEN as u64,
-            &program_id,
+            authority.key,
+            vesting_account.key,
+            rent.minimum_balance(space),
+            space as u64,
+            program_id,
         );
 
         invoke_signed(
             &initialize_vesting_account_ix,
             &[
-                system_program.clone(),
-                token_authority.clone(),
+                authority.clone(),
                 vesting_account.clone(),
+                system_program.clone(),
             ],
-            &[&[&seed]],
+            &[&[
+                VESTING_SEED,
+                mint.key.as_ref(),
+                beneficiary.key.as_ref(),
+                &index.to_le_bytes(),
+                &[bump],
+            ]],
         )?;
 
+        // Remember who paid for the account so that `Close` can refund them,
+        // and who it was derived for so that `CreateVesting` can check it.
+        let vesting = VestingState {
+            beneficiary: *beneficiary.key,
+            mint: *mint.key,
+            rent_receiver: *authority.key,
+            ..VestingState::default()
+        };
+        vesting.pack_into_slice(&mut vesting_account.data.borrow_mut()[..VestingState::LEN]);
+
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_create_vesting(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
@@ -87,8 +271,13 @@ impl Processor {
         start_ts: u64,
         end_ts: u64,
         period_count: u64,
-        nonce: u8,
+        period_unit: PeriodUnit,
+        cliff_ts: u64,
+        cliff_amount: u64,
         amount: u64,
+        revocable: bool,
+        tranches: Vec<Tranche>,
+        milestones: Vec<Milestone>,
     ) -> Result<(), ProgramError> {
         let accounts_iter = &mut accounts.iter();
 
@@ -97,179 +286,4801 @@ impl Processor {
         let vesting_account = next_account_info(accounts_iter)?;
         let vault = next_account_info(accounts_iter)?;
         let metadata = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
         let token_program = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
 
         if !authority.is_signer {
             msg!("Authority must be a signer");
-            return Err(ProgramError::InvalidArgument);
+            return Err(ProgramError::MissingRequiredSignature);
         }
 
-        let vesting_account_pda = Pubkey::create_program_address(&[&], &program_id)?;
-        if vesting_account_pda != *vesting_account.key {
-            msg!("Incorrect vesting account address");
-            return Err(ProgramError::InvalidArgument);
-        }
         if *vesting_account.owner != *program_id {
             msg!("Vesting program must own the vesting account");
-            return Err(ProgramError::InvalidArgument);
+            return Err(ProgramError::IncorrectProgramId);
         }
 
-        let is_initialized = vesting_account.try_borrow_data()?[0] == 1;
+        // Passing the vesting account as the vault keeps the deposit as
+        // lamports in the vesting account itself.
+        let vault_kind = if vault.key == vesting_account.key {
+            VaultKind::Native
+        } else {
+            VaultKind::Token
+        };
+        check_token_program(token_program, vault_kind)?;
 
-        if is_initialized {
-            msg!("Vesting contract with this seed already exists");
-            return Err(ProgramError::InvalidArgument);
-        }
-        let vesting_vault_data = Account::unpack(&vesting_vault.data.borrow())?;
+        let (vesting_signer, nonce) = find_vesting_signer_address(program_id, vesting_account.key);
 
-        if vesting_vault_data.owner != vesting_account_pda {
-            msg!("Vesting vault is not owned by the vesting program");
-            return Err(ProgramError::InvalidArgument);
+        if vesting_account.data_len()
+            != VestingState::LEN + tranches.len() * Tranche::LEN + milestones.len() * Milestone::LEN
+        {
+            msg!("Vesting account size does not match the schedule");
+            return Err(ProgramError::InvalidAccountData);
         }
 
-        if vesting_vault_data.delegate.is_some() {
-            msg!("Vesting vault should not have a delegate authority");
-            return Err(ProgramError::InvalidArgument);
+        let mut vesting =
+            VestingState::unpack_unchecked(&vesting_account.data.borrow()[..VestingState::LEN])?;
+        if vesting.is_initialized() {
+            msg!("Vesting account is already initialized");
+            return Err(ProgramError::AccountAlreadyInitialized);
         }
-
-        if vesting_vault_data.close_authority.is_some() {
-            msg!("Vesting vault should not have a close authority");
-            return Err(ProgramError::InvalidArgument);
+        if !vesting_matches(
+            program_id,
+            vesting_account.key,
+            &vesting,
+            beneficiary,
+            mint.key,
+        ) {
+            msg!("Vesting account was derived for another beneficiary or mint");
+            return Err(ErrorCode::InvalidProgramAddress.into());
         }
 
-        let header = VestingHeader {
+        // Tranche schedules carry their own start, end and period count.
+        let (schedule_mode, period_unit, start_ts, end_ts, period_count) =
+            match (tranches.first(), tranches.last()) {
+                (Some(first), Some(last)) => {
+                    validate_tranches(&tranches, amount)?;
+                    if cliff_ts != 0 || cliff_amount != 0 {
+                        msg!("Tranche schedules cannot have a cliff");
+                        return Err(ErrorCode::InvalidSchedule.into());
+                    }
+                    (
+                        ScheduleMode::Tranches,
+                        PeriodUnit::Seconds,
+                        first.timestamp,
+                        last.timestamp,
+                        tranches.len() as u64,
+                    )
+                }
+                // Milestones vest on attestation, their time floors only
+                // bound the schedule.
+                _ if !milestones.is_empty() => {
+                    validate_milestones(&milestones, amount)?;
+                    let floors = milestones.iter().map(|milestone| milestone.timestamp);
+                    (
+                        ScheduleMode::Milestones,
+                        PeriodUnit::Seconds,
+                        floors.clone().min().unwrap_or(0),
+                        floors.max().unwrap_or(0),
+                        milestones.len() as u64,
+                    )
+                }
+                _ => (
+                    ScheduleMode::Linear,
+                    period_unit,
+                    start_ts,
+                    end_ts,
+                    period_count,
+                ),
+            };
+
+        let clock = Clock::get()?;
+        let current_ts = clock.unix_timestamp as u64;
+        check_plan_active(metadata, current_ts)?;
+
+        validate_schedule(
+            &VestingState {
+                start_balance: amount,
+                start_ts,
+                end_ts,
+                period_count,
+                cliff_ts,
+                cliff_amount,
+                schedule_mode,
+                period_unit,
+                ..VestingState::default()
+            },
+            current_ts,
+        )?;
+
+        let (vesting_mint, grantor) = match vault_kind {
+            VaultKind::Native => {
+                deposit_lamports(
+                    authority,
+                    token_account,
+                    vesting_account,
+                    mint,
+                    token_program,
+                    amount,
+                )?;
+                (native_mint::id(), *authority.key)
+            }
+            VaultKind::Token => deposit_tokens(
+                authority,
+                token_account,
+                vault,
+                &vesting_signer,
+                mint,
+                token_program,
+                amount,
+            )?,
+        };
+
+        vesting = VestingState {
             is_initialized: true,
-            mint,
-            recipient,
+            authority: *authority.key,
+            beneficiary: *beneficiary,
+            vault: *vault.key,
+            mint: vesting_mint,
+            grantor,
+            metadata: *metadata.key,
+            rent_receiver: if vesting.rent_receiver == Pubkey::default() {
+                *authority.key
+            } else {
+                vesting.rent_receiver
+            },
+            outstanding: amount,
+            start_balance: amount,
+            created_ts: current_ts,
+            start_ts,
+            end_ts,
+            period_count,
+            cliff_ts,
+            cliff_amount,
+            whitelist_owned: 0,
+            schedule_mode,
+            period_unit,
+            revocable,
+            revoked: false,
+            paused: false,
+            vault_kind,
+            nonce,
+            last_accrual_ts: current_ts,
+            accrued_rewards: 0,
+            pending_withdrawal: 0,
+            withdrawal_unlock_ts: 0,
         };
 
+        count_grants(
+            program_id,
+            metadata,
+            metadata_program,
+            registrar,
+            1,
+            amount,
+            true,
+        )?;
+
         let mut data = vesting_account.data.borrow_mut();
-        let mut offset = VestingHeader::LEN;
-        let mut total_tokens: u64 = 0;
-        header.pack_into_slice(&mut data);
-
-        for release in releases {
-            let increment = total_tokens.checked_add(release.quantity);
-            match increment {
-                Some(n) => total_tokens = n,
-                None => return Err(ProgramError::InvalidInstructionData),
+        VestingState::pack(vesting, &mut data[..VestingState::LEN])?;
+        match schedule_mode {
+            ScheduleMode::Milestones => {
+                pack_milestones(&milestones, &mut data[VestingState::LEN..])
+            }
+            _ => pack_tranches(&tranches, &mut data[VestingState::LEN..]),
+        }
+
+        VestingEvent::Created(EventData {
+            account: *vesting_account.key,
+            amount,
+            timestamp: current_ts,
+            outstanding: amount,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_create_vesting_from_metadata(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        beneficiary: &Pubkey,
+        amount: u64,
+        start_ts: Option<u64>,
+    ) -> Result<(), ProgramError> {
+        // The accounts are those of `CreateVesting`, the plan comes fifth.
+        let metadata_account = accounts.get(4).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if *metadata_account.owner != vesting_metadata::id() {
+            msg!("Metadata program must own the metadata account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let metadata = unpack_metadata(metadata_account)?;
+
+        let start_ts = match start_ts {
+            Some(start_ts) => start_ts,
+            None => Clock::get()?.unix_timestamp as u64,
+        };
+        let (end_ts, period_count) = calculator::plan_schedule(start_ts, metadata.duration)
+            .ok_or(ErrorCode::InvalidSchedule)?;
+
+        Self::process_create_vesting(
+            program_id,
+            accounts,
+            beneficiary,
+            start_ts,
+            end_ts,
+            period_count,
+            PeriodUnit::Seconds,
+            0,
+            0,
+            amount,
+            false,
+            vec![],
+            vec![],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn process_create_vesting_batch(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        start_ts: u64,
+        end_ts: u64,
+        period_count: u64,
+        period_unit: PeriodUnit,
+        cliff_ts: u64,
+        cliff_bps: u16,
+        revocable: bool,
+        entries: Vec<BatchEntry>,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let token_account = next_account_info(accounts_iter)?;
+        let metadata = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let first_vesting_signer = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        check_token_program(token_program, VaultKind::Token)?;
+
+        if entries.is_empty() || entries.len() > MAX_BATCH_SIZE {
+            return Err(ErrorCode::InvalidBatchSize.into());
+        }
+        if cliff_bps as u64 > MAX_BPS {
+            msg!("Cliff share cannot exceed 10000 basis points");
+            return Err(ErrorCode::InvalidSchedule.into());
+        }
+
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        check_plan_active(metadata, current_ts)?;
+
+        // Every entry is checked before any tokens move.
+        let mut batch: Vec<(&AccountInfo, &AccountInfo, VestingState)> =
+            Vec::with_capacity(entries.len());
+        for entry in &entries {
+            let vesting_account = next_account_info(accounts_iter)?;
+            let vault = next_account_info(accounts_iter)?;
+
+            if *vesting_account.owner != *program_id {
+                msg!("Vesting program must own the vesting account");
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            if batch
+                .iter()
+                .any(|(account, ..)| account.key == vesting_account.key)
+            {
+                msg!("Vesting accounts of a batch must be distinct");
+                return Err(ProgramError::InvalidArgument);
+            }
+            if vesting_account.data_len() != VestingState::LEN {
+                msg!("Vesting account size does not match the schedule");
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let vesting = VestingState::unpack_unchecked(&vesting_account.data.borrow())?;
+            if vesting.is_initialized() {
+                msg!("Vesting account is already initialized");
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            if !vesting_matches(
+                program_id,
+                vesting_account.key,
+                &vesting,
+                &entry.beneficiary,
+                mint.key,
+            ) {
+                msg!("Vesting account was derived for another beneficiary or mint");
+                return Err(ErrorCode::InvalidProgramAddress.into());
+            }
+
+            let (vesting_signer, nonce) =
+                find_vesting_signer_address(program_id, vesting_account.key);
+            check_new_vault(vault, &vesting_signer, mint.key)?;
+
+            let vesting = VestingState {
+                is_initialized: true,
+                authority: *authority.key,
+                beneficiary: entry.beneficiary,
+                vault: *vault.key,
+                mint: *mint.key,
+                grantor: Pubkey::default(),
+                metadata: *metadata.key,
+                rent_receiver: if vesting.rent_receiver == Pubkey::default() {
+                    *authority.key
+                } else {
+                    vesting.rent_receiver
+                },
+                outstanding: entry.amount,
+                start_balance: entry.amount,
+                created_ts: current_ts,
+                start_ts,
+                end_ts,
+                period_count,
+                cliff_ts,
+                cliff_amount: calculator::mul_div(entry.amount, cliff_bps as u64, MAX_BPS)
+                    .ok_or(ErrorCode::InvalidSchedule)?,
+                whitelist_owned: 0,
+                schedule_mode: ScheduleMode::Linear,
+                period_unit,
+                revocable,
+                revoked: false,
+                paused: false,
+                vault_kind: VaultKind::Token,
+                nonce,
+                last_accrual_ts: current_ts,
+                accrued_rewards: 0,
+                pending_withdrawal: 0,
+                withdrawal_unlock_ts: 0,
             };
-            msg!("{}", total_tokens);
-            release.pack_into_slice(&mut data[offset..]);
-            offset += VestingInfo::LEN;
+            validate_schedule(&vesting, current_ts)?;
+
+            batch.push((vesting_account, vault, vesting));
         }
 
-        if Account::unpack(&token_account.data.borrow())?.amount < total_tokens {
-            msg!("Token vault has insufficient funds.");
-            return Err(ProgramError::InsufficientFunds);
+        let (first_vesting_account, first_vault, first) = batch[0];
+        let signer_seeds: &[&[u8]] = &[first_vesting_account.key.as_ref(), &[first.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *first_vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
         }
 
-        let transfer_tokens_ix = transfer(
-            spl_token_program.key,
-            token_account.key,
-            vesting_vault.key,
-            token_authority.key,
-            &[],
-            total_tokens,
+        // The first vault keeps its own amount and pays the transfer fee of
+        // every onward transfer.
+        let mut onward = Vec::with_capacity(batch.len() - 1);
+        let mut deposit = first.outstanding;
+        for (_, _, vesting) in &batch[1..] {
+            let sent = amount_before_fee(mint, vesting.outstanding)?;
+            deposit = deposit
+                .checked_add(sent)
+                .ok_or(ErrorCode::InvalidDepositAmount)?;
+            onward.push(sent);
+        }
+
+        let (_, grantor) = deposit_tokens(
+            authority,
+            token_account,
+            first_vault,
+            first_vesting_signer.key,
+            mint,
+            token_program,
+            deposit,
         )?;
 
-        invoke(
-            &transfer_tokens_ix,
-            &[
-                token_account.clone(),
-                vesting_vault.clone(),
-                token_authority.clone(),
-            ],
+        let committed = batch.iter().try_fold(0u64, |total, (_, _, vesting)| {
+            total.checked_add(vesting.outstanding)
+        });
+        count_grants(
+            program_id,
+            metadata,
+            metadata_program,
+            registrar,
+            batch.len() as u64,
+            committed.ok_or(ProgramError::ArithmeticOverflow)?,
+            true,
         )?;
 
+        for ((_, vault, vesting), sent) in batch[1..].iter().zip(onward) {
+            let received = transfer_tokens(
+                token_program,
+                first_vault,
+                mint,
+                vault,
+                first_vesting_signer,
+                &[signer_seeds],
+                sent,
+            )?;
+            if received != vesting.outstanding {
+                return Err(ErrorCode::UnexpectedTransferFee.into());
+            }
+        }
+
+        for (vesting_account, _, vesting) in batch {
+            VestingState::pack(
+                VestingState { grantor, ..vesting },
+                &mut vesting_account.data.borrow_mut(),
+            )?;
+
+            VestingEvent::Created(EventData {
+                account: *vesting_account.key,
+                amount: vesting.outstanding,
+                timestamp: current_ts,
+                outstanding: vesting.outstanding,
+            })
+            .emit();
+        }
+
         Ok(())
     }
 
-    fn process_unlock_tokens(
+    // Withdraws `amount`, or completes the pending withdrawal if `None`.
+    fn process_withdraw(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        seed: [u8; 32],
+        amount: Option<u64>,
     ) -> Result<(), ProgramError> {
-        msg!("Instruction: Unlock Tokens");
         let accounts_iter = &mut accounts.iter();
 
+        let beneficiary = next_account_info(accounts_iter)?;
+        let token_account = next_account_info(accounts_iter)?;
         let vesting_account = next_account_info(accounts_iter)?;
-        let vesting_vault = next_account_info(accounts_iter)?;
-        let recipient_account = next_account_info(accounts_iter)?;
-        let recipient_token_account = next_account_info(accounts_iter)?;
-        let spl_token_program = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
 
-        let clock = Clock::get()?;
+        if !beneficiary.is_signer {
+            msg!("Beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
-        let vesting_account_pda = Pubkey::create_program_address(&[&seed], &program_id)?;
-        if vesting_account_pda != *vesting_account.key {
-            msg!("Incorrect vesting account address");
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.beneficiary != *beneficiary.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
             return Err(ProgramError::InvalidArgument);
         }
-        if *vesting_account.owner != *program_id {
-            msg!("Synchrony vesting program must own the vesting account");
+        check_token_program(token_program, vesting.vault_kind)?;
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        if vesting.paused || Self::is_globally_paused(program_id, config_account)? {
+            return Err(ErrorCode::Paused.into());
+        }
+
+        let metadata = grant_metadata(&vesting, metadata_account)?;
+        let clock = Clock::get()?;
+        let current_ts = clock.unix_timestamp as u64;
+        let completing = amount.is_none();
+        let amount = match amount {
+            // Timelocked grants only withdraw through `RequestWithdrawal`.
+            Some(_) if metadata.as_ref().map_or(0, |m| m.withdrawal_timelock) > 0 => {
+                return Err(ErrorCode::WithdrawalRequestRequired.into());
+            }
+            Some(amount) => amount,
+            None if vesting.pending_withdrawal == 0 => {
+                return Err(ErrorCode::NoPendingWithdrawal.into());
+            }
+            None if current_ts < vesting.withdrawal_unlock_ts => {
+                return Err(ErrorCode::WithdrawalTimelocked.into());
+            }
+            None => vesting.pending_withdrawal,
+        };
+
+        let available = calculator::available_for_withdrawal(&vesting, &tranches, current_ts);
+        if amount > available {
+            return Err(ErrorCode::InsufficientWithdrawalBalance.into());
+        }
+
+        if *mint.key != vesting.mint {
+            msg!("Mint does not match the vesting account");
             return Err(ProgramError::InvalidArgument);
         }
 
-        if *spl_token_program.key != spl_token::id() {
-            msg!("Incorrect spl-token ID");
+        // Rewards up to now accrue on the balance before the withdrawal.
+        accrue_rewards(&mut vesting, &tranches, metadata.as_ref(), current_ts)?;
+
+        match vesting.vault_kind {
+            VaultKind::Native => release_lamports(vesting_account, token_account, amount)?,
+            // Any transfer fee is taken from what the beneficiary receives,
+            // the vault is debited the full amount.
+            VaultKind::Token => {
+                transfer_tokens(
+                    token_program,
+                    vault,
+                    mint,
+                    token_account,
+                    vesting_signer,
+                    &[signer_seeds],
+                    amount,
+                )?;
+            }
+        }
+
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientWithdrawalBalance)?;
+        if completing {
+            vesting.pending_withdrawal = 0;
+            vesting.withdrawal_unlock_ts = 0;
+        }
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::Withdrawn(EventData {
+            account: *vesting_account.key,
+            amount,
+            timestamp: current_ts,
+            outstanding: vesting.outstanding,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_early_withdraw(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let beneficiary = next_account_info(accounts_iter)?;
+        let token_account = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let treasury = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !beneficiary.is_signer {
+            msg!("Beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        let header = VestingHeader::unpack(&vesting_account.data.borrow()[..VestingHeader::LEN])?;
-        if header.recipient != *recipient_account.key {
-            msg!("recipient address does not match");
+        let (mut vesting, schedule) = {
+            let data = vesting_account.data.borrow();
+            (
+                VestingState::unpack(&data[..VestingState::LEN])?,
+                data[VestingState::LEN..].to_vec(),
+            )
+        };
+        if vesting.beneficiary != *beneficiary.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
             return Err(ProgramError::InvalidArgument);
         }
-
-        let vesting_vault_data = Account::unpack(&vesting_vault.data.borrow())?;
-        if vesting_vault_data.owner != vesting_account_pda {
-            msg!("Vault is not owned by the vesting account");
+        if *mint.key != vesting.mint {
+            msg!("Mint does not match the vesting account");
             return Err(ProgramError::InvalidArgument);
         }
+        if vesting.vault_kind == VaultKind::Native {
+            return Err(ErrorCode::NativeVaultUnsupported.into());
+        }
+        check_token_program(token_program, vesting.vault_kind)?;
+        if vesting.whitelist_owned > 0 {
+            msg!("Tokens held by whitelisted programs must be returned before withdrawing early");
+            return Err(ErrorCode::UnableToWithdrawWhileStaked.into());
+        }
 
-        let mut offset = VestingHeader::LEN;
-        let mut release_quantity = 0;
-        let mut releases = unpack_releases(&vesting_account.data.borrow()[offset..])?;
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
 
-        for release in releases.iter_mut() {
-            if clock.unix_timestamp as u32 >= release.timestamp {
-                release_quantity += release.quantity;
-                release.quantity = 0;
-            }
+        if vesting.paused || Self::is_globally_paused(program_id, config_account)? {
+            return Err(ErrorCode::Paused.into());
         }
 
-        if release_quantity == 0 {
-            msg!("No vesting periods have elapsed...");
+        // The fee and the treasury come from the metadata the grant was
+        // created with.
+        if vesting.metadata != *metadata_account.key
+            || *metadata_account.owner != vesting_metadata::id()
+        {
+            msg!("Metadata account does not match the vesting account");
             return Err(ProgramError::InvalidArgument);
         }
+        let metadata = unpack_metadata(metadata_account)?;
+        if metadata.treasury != *treasury.key {
+            msg!("Treasury does not match the metadata treasury");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if metadata.withdrawal_timelock > 0 {
+            return Err(ErrorCode::WithdrawalRequestRequired.into());
+        }
+        if metadata.early_withdrawal_fee > MAX_BPS {
+            msg!("Early withdrawal fee exceeds 10000 basis points");
+            return Err(ProgramError::InvalidAccountData);
+        }
 
-        let release_tokens_ix = transfer(
-            spl_token_program.key,
-            vesting_vault.key,
-            recipient_token_account.key,
-            &vesting_account_pda,
-            &[],
-            release_quantity,
+        if amount == 0 {
+            return Err(ErrorCode::InvalidDepositAmount.into());
+        }
+
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        let (_, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        rewards::accrue(&mut vesting, &tranches, &metadata, current_ts)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        let schedule = match vesting.schedule_mode {
+            ScheduleMode::Milestones => {
+                let mut milestones = unpack_milestones(&schedule)?;
+                calculator::shrink_milestones(&mut milestones, amount, current_ts)
+                    .ok_or(ErrorCode::InsufficientWithdrawalBalance)?;
+                let mut schedule = vec![0u8; milestones.len() * Milestone::LEN];
+                pack_milestones(&milestones, &mut schedule);
+                schedule
+            }
+            _ => {
+                let tranches = unpack_tranches(&schedule)?;
+                let mut tranches = calculator::convert_to_tranches(&mut vesting, &tranches)
+                    .ok_or(ErrorCode::InvalidSchedule)?;
+                calculator::shrink_tranches(&mut tranches, amount, current_ts)
+                    .ok_or(ErrorCode::InsufficientWithdrawalBalance)?;
+
+                let mut schedule = vec![0u8; tranches.len() * Tranche::LEN];
+                pack_tranches(&tranches, &mut schedule);
+                schedule
+            }
+        };
+
+        let penalty = calculator::mul_div(amount, metadata.early_withdrawal_fee, MAX_BPS)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        // Any transfer fee is taken from what the beneficiary and the
+        // treasury receive, the vault is debited the full amount.
+        transfer_tokens(
+            token_program,
+            vault,
+            mint,
+            token_account,
+            vesting_signer,
+            &[signer_seeds],
+            amount - penalty,
         )?;
+        if penalty > 0 {
+            transfer_tokens(
+                token_program,
+                vault,
+                mint,
+                treasury,
+                vesting_signer,
+                &[signer_seeds],
+                penalty,
+            )?;
+        }
 
-        invoke_signed(
-            &release_tokens_ix,
-            &[
-                vesting_vault.clone(),
-                recipient_token_account.clone(),
-                vesting_account.clone(),
-            ],
-            &[&[&seed]],
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientWithdrawalBalance)?;
+        vesting.start_balance = vesting
+            .start_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientWithdrawalBalance)?;
+
+        // The amount leaves the schedule, so the plan no longer commits it.
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            0,
+            amount,
+            false,
         )?;
 
-        let mut data = vesting_account.data.borrow_mut();
-        offset = VestingHeader::LEN;
-        for release in releases {
-            release.pack_into_slice(&mut data[offset..offset + VestingInfo::LEN]);
-            offset += VestingInfo::LEN;
+        resize_account(
+            vesting_account,
+            beneficiary,
+            system_program,
+            VestingState::LEN + schedule.len(),
+            0,
+        )?;
+
+        {
+            let mut data = vesting_account.data.borrow_mut();
+            VestingState::pack(vesting, &mut data[..VestingState::LEN])?;
+            data[VestingState::LEN..].copy_from_slice(&schedule);
+        }
+
+        VestingEvent::EarlyWithdrawn {
+            data: EventData {
+                account: *vesting_account.key,
+                amount,
+                timestamp: current_ts,
+                outstanding: vesting.outstanding,
+            },
+            penalty,
         }
+        .emit();
 
         Ok(())
     }
+
+    fn process_set_beneficiary(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_beneficiary: Pubkey,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let new_beneficiary_account = next_account_info(accounts_iter)?;
+        let new_vesting_account = next_account_info(accounts_iter)?;
+        let new_vault = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let rent_receiver = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id || *new_vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting accounts");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_token_program(token_program, VaultKind::Token)?;
+
+        if *new_beneficiary_account.key != new_beneficiary {
+            msg!("New beneficiary account does not match the instruction");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        // The schedule after the header is moved over as is.
+        let (mut vesting, schedule) = {
+            let data = vesting_account.data.borrow();
+            (
+                VestingState::unpack(&data[..VestingState::LEN])?,
+                data[VestingState::LEN..].to_vec(),
+            )
+        };
+        if vesting.authority != *authority.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if vesting.rent_receiver != *rent_receiver.key {
+            msg!("Rent receiver does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if vesting.whitelist_owned > 0 {
+            msg!("Tokens held by whitelisted programs must be returned before moving the grant");
+            return Err(ErrorCode::UnableToWithdrawWhileStaked.into());
+        }
+        if vesting.vault_kind == VaultKind::Native {
+            return Err(ErrorCode::NativeVaultUnsupported.into());
+        }
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        if get_associated_vesting_address(&new_beneficiary, &vesting.mint)
+            != *new_vesting_account.key
+        {
+            msg!("Incorrect associated vesting address for the new beneficiary");
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        // Only the first `VestingState::LEN` bytes are guaranteed to exist
+        // before the account is resized below.
+        if new_vesting_account.data_len() < VestingState::LEN {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let new_vesting = VestingState::unpack_unchecked(
+            &new_vesting_account.data.borrow()[..VestingState::LEN],
+        )?;
+        if new_vesting.is_initialized() {
+            msg!("New beneficiary vesting account is already initialized");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        let (new_vesting_signer, new_nonce) =
+            find_vesting_signer_address(program_id, new_vesting_account.key);
+
+        let new_vault_data = unpack_token_account(new_vault)?;
+        if new_vault_data.owner != new_vesting_signer {
+            return Err(ErrorCode::InvalidVaultOwner.into());
+        }
+        if new_vault_data.mint != vesting.mint || *mint.key != vesting.mint {
+            msg!("New vault mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if new_vault_data.amount != 0 {
+            return Err(ErrorCode::InvalidVaultAmount.into());
+        }
+        if new_vault_data.delegate.is_some() || new_vault_data.close_authority.is_some() {
+            msg!("New vault should not have a delegate or close authority");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let vault_balance = unpack_token_account(vault)?.amount;
+        if vault_balance > 0
+            && transfer_tokens(
+                token_program,
+                vault,
+                mint,
+                new_vault,
+                vesting_signer,
+                &[signer_seeds],
+                vault_balance,
+            )? != vault_balance
+        {
+            return Err(ErrorCode::UnexpectedTransferFee.into());
+        }
+
+        invoke_signed(
+            &close_account(
+                token_program.key,
+                vault.key,
+                rent_receiver.key,
+                vesting_signer.key,
+                &[],
+            )?,
+            &[
+                vault.clone(),
+                rent_receiver.clone(),
+                vesting_signer.clone(),
+                token_program.clone(),
+            ],
+            &[signer_seeds],
+        )?;
+
+        resize_account(
+            new_vesting_account,
+            authority,
+            system_program,
+            VestingState::LEN + schedule.len(),
+            0,
+        )?;
+
+        vesting.beneficiary = new_beneficiary;
+        vesting.vault = *new_vault.key;
+        vesting.nonce = new_nonce;
+        // A pending request belongs to the previous beneficiary.
+        vesting.pending_withdrawal = 0;
+        vesting.withdrawal_unlock_ts = 0;
+
+        {
+            let mut data = new_vesting_account.data.borrow_mut();
+            VestingState::pack(vesting, &mut data[..VestingState::LEN])?;
+            data[VestingState::LEN..].copy_from_slice(&schedule);
+        }
+
+        close_vesting_account(vesting_account, rent_receiver)?;
+
+        VestingEvent::BeneficiaryChanged {
+            data: EventData {
+                account: *new_vesting_account.key,
+                amount: vault_balance,
+                timestamp: Clock::get()?.unix_timestamp as u64,
+                outstanding: vesting.outstanding,
+            },
+            beneficiary: new_beneficiary,
+            previous_account: *vesting_account.key,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_split(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let new_beneficiary = next_account_info(accounts_iter)?;
+        let new_vesting_account = next_account_info(accounts_iter)?;
+        let new_vault = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id || *new_vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting accounts");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_token_program(token_program, VaultKind::Token)?;
+
+        let (mut vesting, mut tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.authority != *authority.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_grant_metadata(&vesting, metadata_account)?;
+        if vesting.schedule_mode == ScheduleMode::Milestones {
+            msg!("Milestone schedules cannot be split");
+            return Err(ErrorCode::InvalidSchedule.into());
+        }
+        if vesting.whitelist_owned > 0 {
+            msg!("Tokens held by whitelisted programs must be returned before splitting");
+            return Err(ErrorCode::UnableToWithdrawWhileStaked.into());
+        }
+        if vesting.pending_withdrawal > 0 {
+            return Err(ErrorCode::WithdrawalPending.into());
+        }
+        if vesting.vault_kind == VaultKind::Native {
+            return Err(ErrorCode::NativeVaultUnsupported.into());
+        }
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        if new_vesting_account.data_len() < VestingState::LEN {
+            msg!("New vesting account is too small");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let new_vesting = VestingState::unpack_unchecked(
+            &new_vesting_account.data.borrow()[..VestingState::LEN],
+        )?;
+        if new_vesting.is_initialized() {
+            msg!("New vesting account is already initialized");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        if !vesting_matches(
+            program_id,
+            new_vesting_account.key,
+            &new_vesting,
+            new_beneficiary.key,
+            &vesting.mint,
+        ) {
+            msg!("New vesting account was derived for another beneficiary or mint");
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+        let rent_receiver = new_vesting.rent_receiver;
+
+        let (new_vesting_signer, new_nonce) =
+            find_vesting_signer_address(program_id, new_vesting_account.key);
+
+        let new_vault_data = unpack_token_account(new_vault)?;
+        if new_vault_data.owner != new_vesting_signer {
+            return Err(ErrorCode::InvalidVaultOwner.into());
+        }
+        if new_vault_data.mint != vesting.mint || *mint.key != vesting.mint {
+            msg!("New vault mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if new_vault_data.amount != 0 {
+            return Err(ErrorCode::InvalidVaultAmount.into());
+        }
+        if new_vault_data.delegate.is_some() || new_vault_data.close_authority.is_some() {
+            msg!("New vault should not have a delegate or close authority");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let clock = Clock::get()?;
+        let (mut new_vesting, new_tranches) = calculator::split(
+            &mut vesting,
+            &mut tranches,
+            amount,
+            clock.unix_timestamp as u64,
+        )
+        .ok_or(ErrorCode::InvalidSplitAmount)?;
+
+        let received = transfer_tokens(
+            token_program,
+            vault,
+            mint,
+            new_vault,
+            vesting_signer,
+            &[signer_seeds],
+            amount,
+        )?;
+        if received != amount {
+            return Err(ErrorCode::UnexpectedTransferFee.into());
+        }
+
+        new_vesting.beneficiary = *new_beneficiary.key;
+        new_vesting.vault = *new_vault.key;
+        new_vesting.rent_receiver = if rent_receiver == Pubkey::default() {
+            *authority.key
+        } else {
+            rent_receiver
+        };
+        new_vesting.nonce = new_nonce;
+
+        // The start balance is only moved, the new account adds no commitment.
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            1,
+            0,
+            true,
+        )?;
+
+        // A linear schedule that does not divide evenly becomes tranches.
+        resize_account(
+            vesting_account,
+            authority,
+            system_program,
+            VestingState::LEN + tranches.len() * Tranche::LEN,
+            0,
+        )?;
+        resize_account(
+            new_vesting_account,
+            authority,
+            system_program,
+            VestingState::LEN + new_tranches.len() * Tranche::LEN,
+            0,
+        )?;
+
+        {
+            let mut data = vesting_account.data.borrow_mut();
+            VestingState::pack(vesting, &mut data[..VestingState::LEN])?;
+            pack_tranches(&tranches, &mut data[VestingState::LEN..]);
+        }
+        {
+            let mut data = new_vesting_account.data.borrow_mut();
+            VestingState::pack(new_vesting, &mut data[..VestingState::LEN])?;
+            pack_tranches(&new_tranches, &mut data[VestingState::LEN..]);
+        }
+
+        Ok(())
+    }
+
+    fn process_merge(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        check_token_program(token_program, VaultKind::Token)?;
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.authority != *authority.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        let metadata = grant_metadata(&vesting, metadata_account)?;
+        if vesting.vault_kind == VaultKind::Native {
+            return Err(ErrorCode::NativeVaultUnsupported.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if vesting.whitelist_owned > 0 {
+            msg!("Tokens held by whitelisted programs must be returned before merging");
+            return Err(ErrorCode::UnableToWithdrawWhileStaked.into());
+        }
+        if vesting.pending_withdrawal > 0 {
+            return Err(ErrorCode::WithdrawalPending.into());
+        }
+        if *mint.key != vesting.mint {
+            msg!("Mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        // Every account accrues on its own balance up to now, after that the
+        // merged schedule earns for all of them.
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        accrue_rewards(&mut vesting, &tranches, metadata.as_ref(), current_ts)?;
+
+        let mut schedules =
+            vec![calculator::schedule_tranches(&vesting, &tranches)
+                .ok_or(ErrorCode::InvalidSchedule)?];
+
+        while accounts_iter.len() > 0 {
+            let source_account = next_account_info(accounts_iter)?;
+            let source_vault = next_account_info(accounts_iter)?;
+            let source_signer = next_account_info(accounts_iter)?;
+            let source_rent_receiver = next_account_info(accounts_iter)?;
+
+            if *source_account.owner != *program_id {
+                msg!("Vesting program must own the source vesting account");
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            if source_account.key == vesting_account.key {
+                msg!("A vesting account cannot be merged into itself");
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            // Sources that were already merged are zeroed and fail to unpack.
+            let (mut source, source_tranches) = unpack_vesting(&source_account.data.borrow())?;
+            if source.mint != vesting.mint
+                || source.beneficiary != vesting.beneficiary
+                || source.authority != vesting.authority
+                || source.revocable != vesting.revocable
+                || source.vault_kind != vesting.vault_kind
+                || source.metadata != vesting.metadata
+            {
+                return Err(ErrorCode::IncompatibleMerge.into());
+            }
+            accrue_rewards(&mut source, &source_tranches, metadata.as_ref(), current_ts)?;
+            if source.vault != *source_vault.key {
+                msg!("Vault does not match the source vesting account");
+                return Err(ProgramError::InvalidArgument);
+            }
+            if source.rent_receiver != *source_rent_receiver.key {
+                msg!("Rent receiver does not match the source vesting account");
+                return Err(ProgramError::InvalidArgument);
+            }
+            if source.whitelist_owned > 0 {
+                msg!("Tokens held by whitelisted programs must be returned before merging");
+                return Err(ErrorCode::UnableToWithdrawWhileStaked.into());
+            }
+            if source.pending_withdrawal > 0 {
+                return Err(ErrorCode::WithdrawalPending.into());
+            }
+
+            let signer_seeds: &[&[u8]] = &[source_account.key.as_ref(), &[source.nonce]];
+            let source_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+                .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+            if source_signer_pda != *source_signer.key {
+                return Err(ErrorCode::InvalidProgramAddress.into());
+            }
+
+            let source_balance = unpack_token_account(source_vault)?.amount;
+            if source_balance > 0
+                && transfer_tokens(
+                    token_program,
+                    source_vault,
+                    mint,
+                    vault,
+                    source_signer,
+                    &[signer_seeds],
+                    source_balance,
+                )? != source_balance
+            {
+                return Err(ErrorCode::UnexpectedTransferFee.into());
+            }
+
+            invoke_signed(
+                &close_account(
+                    token_program.key,
+                    source_vault.key,
+                    source_rent_receiver.key,
+                    source_signer.key,
+                    &[],
+                )?,
+                &[
+                    source_vault.clone(),
+                    source_rent_receiver.clone(),
+                    source_signer.clone(),
+                    token_program.clone(),
+                ],
+                &[signer_seeds],
+            )?;
+
+            close_vesting_account(source_account, source_rent_receiver)?;
+
+            schedules.push(
+                calculator::schedule_tranches(&source, &source_tranches)
+                    .ok_or(ErrorCode::InvalidSchedule)?,
+            );
+            vesting.outstanding = vesting
+                .outstanding
+                .checked_add(source.outstanding)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            vesting.start_balance = vesting
+                .start_balance
+                .checked_add(source.start_balance)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            vesting.accrued_rewards = vesting
+                .accrued_rewards
+                .checked_add(source.accrued_rewards)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+
+        if schedules.len() < 2 {
+            msg!("No source vesting accounts given");
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // The sources' start balances now count towards the destination.
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            schedules.len() as u64 - 1,
+            0,
+            false,
+        )?;
+
+        let merged = calculator::merge_tranches(&schedules).ok_or(ErrorCode::InvalidSchedule)?;
+        validate_tranches(&merged, vesting.start_balance)?;
+
+        // Revoked sources were converted to already vested tranches.
+        vesting.schedule_mode = ScheduleMode::Tranches;
+        vesting.period_unit = PeriodUnit::Seconds;
+        vesting.start_ts = merged.first().map_or(0, |tranche| tranche.timestamp);
+        vesting.end_ts = merged.last().map_or(0, |tranche| tranche.timestamp);
+        vesting.period_count = merged.len() as u64;
+        vesting.cliff_ts = 0;
+        vesting.cliff_amount = 0;
+        vesting.revoked = false;
+
+        resize_account(
+            vesting_account,
+            authority,
+            system_program,
+            VestingState::LEN + merged.len() * Tranche::LEN,
+            0,
+        )?;
+
+        let mut data = vesting_account.data.borrow_mut();
+        VestingState::pack(vesting, &mut data[..VestingState::LEN])?;
+        pack_tranches(&merged, &mut data[VestingState::LEN..]);
+
+        Ok(())
+    }
+
+    fn process_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let signer = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let rent_receiver = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !signer.is_signer {
+            msg!("Authority or beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.authority != *signer.key && vesting.beneficiary != *signer.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if vesting.rent_receiver != *rent_receiver.key {
+            msg!("Rent receiver does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        let metadata = grant_metadata(&vesting, metadata_account)?;
+        check_token_program(token_program, vesting.vault_kind)?;
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        if vesting.outstanding != 0 {
+            return Err(ErrorCode::VestingNotEmpty.into());
+        }
+
+        // Closing would forfeit what the balance earned before it was
+        // withdrawn.
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        accrue_rewards(&mut vesting, &tranches, metadata.as_ref(), current_ts)?;
+        if vesting.accrued_rewards > 0 {
+            return Err(ErrorCode::UnclaimedRewards.into());
+        }
+
+        // Native vesting accounts are their own vault.
+        if vesting.vault_kind == VaultKind::Token {
+            if unpack_token_account(vault)?.amount != 0 {
+                return Err(ErrorCode::VestingNotEmpty.into());
+            }
+
+            invoke_signed(
+                &close_account(
+                    token_program.key,
+                    vault.key,
+                    rent_receiver.key,
+                    vesting_signer.key,
+                    &[],
+                )?,
+                &[
+                    vault.clone(),
+                    rent_receiver.clone(),
+                    vesting_signer.clone(),
+                    token_program.clone(),
+                ],
+                &[signer_seeds],
+            )?;
+        }
+
+        close_vesting_account(vesting_account, rent_receiver)?;
+
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            1,
+            vesting.start_balance,
+            false,
+        )?;
+
+        VestingEvent::Closed(EventData {
+            account: *vesting_account.key,
+            amount: 0,
+            timestamp: current_ts,
+            outstanding: 0,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_attest_milestone(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        index: u32,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let attestor = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+
+        if !attestor.is_signer {
+            msg!("Attestor must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut data = vesting_account.data.borrow_mut();
+        let vesting = VestingState::unpack(&data[..VestingState::LEN])?;
+        if vesting.schedule_mode != ScheduleMode::Milestones {
+            msg!("Vesting account does not have a milestone schedule");
+            return Err(ErrorCode::InvalidSchedule.into());
+        }
+        if vesting.revoked {
+            return Err(ErrorCode::AlreadyRevoked.into());
+        }
+
+        let mut milestones = unpack_milestones(&data[VestingState::LEN..])?;
+        let milestone = milestones
+            .get_mut(index as usize)
+            .ok_or(ErrorCode::InvalidMilestone)?;
+        if milestone.attestor != *attestor.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if milestone.completed_ts != 0 {
+            return Err(ErrorCode::MilestoneAlreadyAttested.into());
+        }
+
+        milestone.completed_ts = Clock::get()?.unix_timestamp as u64;
+        pack_milestones(&milestones, &mut data[VestingState::LEN..]);
+
+        Ok(())
+    }
+
+    fn process_migrate(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let payer = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !payer.is_signer {
+            msg!("Payer must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Unversioned accounts were allocated with room to spare after the
+        // header and only held linear token grants, so nothing after the
+        // header is carried over and no deposit sits on top of the rent.
+        let vesting = {
+            let data = vesting_account.data.borrow();
+            match VestingState::version(&data) {
+                Some(VESTING_VERSION) => {
+                    msg!("Vesting account is already up to date");
+                    return Ok(());
+                }
+                Some(version) => match VestingState::versioned_len(version) {
+                    Some(len) if data.len() >= len => {
+                        VestingState::unpack_versioned(version, &data[..len])?
+                    }
+                    _ => return Err(ErrorCode::UnsupportedVersion.into()),
+                },
+                None => return Err(ErrorCode::UnsupportedVersion.into()),
+            }
+        };
+        check_grant_metadata(&vesting, metadata_account)?;
+
+        resize_account(vesting_account, payer, system_program, VestingState::LEN, 0)?;
+
+        // Grants from before the plan counters join them as they are now.
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            1,
+            vesting.start_balance,
+            true,
+        )?;
+
+        vesting.pack_into_slice(&mut vesting_account.data.borrow_mut());
+
+        Ok(())
+    }
+
+    fn process_revoke(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let grantor_token_account = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.authority != *authority.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        check_token_program(token_program, vesting.vault_kind)?;
+        if !vesting.revocable {
+            return Err(ErrorCode::Irrevocable.into());
+        }
+        if vesting.revoked {
+            return Err(ErrorCode::AlreadyRevoked.into());
+        }
+        if vesting.whitelist_owned > 0 {
+            msg!("Tokens held by whitelisted programs must be returned before revoking");
+            return Err(ErrorCode::UnableToWithdrawWhileStaked.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if *mint.key != vesting.mint {
+            msg!("Mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        // Whatever the beneficiary could withdraw right now stays behind, the
+        // rest of the vault goes back to the grantor.
+        let clock = Clock::get()?;
+        let metadata = grant_metadata(&vesting, metadata_account)?;
+        accrue_rewards(
+            &mut vesting,
+            &tranches,
+            metadata.as_ref(),
+            clock.unix_timestamp as u64,
+        )?;
+        let vested =
+            calculator::available_for_withdrawal(&vesting, &tranches, clock.unix_timestamp as u64);
+        let unvested = vesting
+            .outstanding
+            .checked_sub(vesting.whitelist_owned)
+            .and_then(|balance| balance.checked_sub(vested))
+            .ok_or(ProgramError::InvalidAccountData)?;
+
+        if unvested > 0 {
+            match vesting.vault_kind {
+                VaultKind::Native => {
+                    release_lamports(vesting_account, grantor_token_account, unvested)?
+                }
+                VaultKind::Token => {
+                    transfer_tokens(
+                        token_program,
+                        vault,
+                        mint,
+                        grantor_token_account,
+                        vesting_signer,
+                        &[signer_seeds],
+                        unvested,
+                    )?;
+                }
+            }
+        }
+
+        vesting.outstanding -= unvested;
+        vesting.start_balance -= unvested;
+        vesting.revoked = true;
+
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            0,
+            unvested,
+            false,
+        )?;
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::Revoked(EventData {
+            account: *vesting_account.key,
+            amount: unvested,
+            timestamp: clock.unix_timestamp as u64,
+            outstanding: vesting.outstanding,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_claim_rewards(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let beneficiary = next_account_info(accounts_iter)?;
+        let token_account = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let reward_vault = next_account_info(accounts_iter)?;
+        let reward_authority = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+
+        if !beneficiary.is_signer {
+            msg!("Beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.beneficiary != *beneficiary.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if *mint.key != vesting.mint {
+            msg!("Mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_token_program(token_program, VaultKind::Token)?;
+
+        if vesting.paused || Self::is_globally_paused(program_id, config_account)? {
+            return Err(ErrorCode::Paused.into());
+        }
+
+        if vesting.metadata != *metadata_account.key
+            || *metadata_account.owner != vesting_metadata::id()
+        {
+            msg!("Metadata account does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        let metadata = unpack_metadata(metadata_account)?;
+        if metadata.vault != *reward_vault.key {
+            msg!("Reward vault does not match the metadata vault");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let plan_root = metadata.plan_root(metadata_account.key);
+        let (reward_authority_pda, bump) = find_reward_authority_address(program_id, &plan_root);
+        if reward_authority_pda != *reward_authority.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+        let reward_vault_data = unpack_token_account(reward_vault)?;
+        if reward_vault_data.owner != reward_authority_pda {
+            return Err(ErrorCode::InvalidVaultOwner.into());
+        }
+        if reward_vault_data.mint != vesting.mint {
+            msg!("Reward vault mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        rewards::accrue(&mut vesting, &tranches, &metadata, current_ts)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        // An underfunded vault pays what it has, the rest stays accrued.
+        let amount = std::cmp::min(vesting.accrued_rewards, reward_vault_data.amount);
+        if amount > 0 {
+            let signer_seeds: &[&[u8]] = &[REWARD_SEED, plan_root.as_ref(), &[bump]];
+            transfer_tokens(
+                token_program,
+                reward_vault,
+                mint,
+                token_account,
+                reward_authority,
+                &[signer_seeds],
+                amount,
+            )?;
+        }
+        vesting.accrued_rewards -= amount;
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::RewardsClaimed(EventData {
+            account: *vesting_account.key,
+            amount,
+            timestamp: current_ts,
+            outstanding: vesting.outstanding,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_request_withdrawal(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let beneficiary = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+
+        if !beneficiary.is_signer {
+            msg!("Beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.beneficiary != *beneficiary.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.paused || Self::is_globally_paused(program_id, config_account)? {
+            return Err(ErrorCode::Paused.into());
+        }
+        if vesting.pending_withdrawal > 0 {
+            return Err(ErrorCode::WithdrawalPending.into());
+        }
+        if amount == 0 {
+            return Err(ErrorCode::InvalidDepositAmount.into());
+        }
+
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        if amount > calculator::available_for_withdrawal(&vesting, &tranches, current_ts) {
+            return Err(ErrorCode::InsufficientWithdrawalBalance.into());
+        }
+
+        let timelock = grant_metadata(&vesting, metadata_account)?
+            .map_or(0, |metadata| metadata.withdrawal_timelock);
+        vesting.pending_withdrawal = amount;
+        vesting.withdrawal_unlock_ts = current_ts
+            .checked_add(timelock)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::WithdrawalRequested {
+            data: EventData {
+                account: *vesting_account.key,
+                amount,
+                timestamp: current_ts,
+                outstanding: vesting.outstanding,
+            },
+            unlock_ts: vesting.withdrawal_unlock_ts,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_adopt_plan_version(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let beneficiary = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let new_metadata_account = next_account_info(accounts_iter)?;
+        let metadata_program = next_account_info(accounts_iter)?;
+        let registrar = next_account_info(accounts_iter)?;
+
+        if !beneficiary.is_signer {
+            msg!("Beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.beneficiary != *beneficiary.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        let metadata = grant_metadata(&vesting, metadata_account)?.ok_or_else(|| {
+            msg!("Vesting account has no plan to update");
+            ProgramError::InvalidArgument
+        })?;
+        if *new_metadata_account.owner != vesting_metadata::id() {
+            msg!("Metadata program must own the new metadata account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let new_metadata = unpack_metadata(new_metadata_account)?;
+        if new_metadata.plan_root(new_metadata_account.key)
+            != metadata.plan_root(metadata_account.key)
+            || new_metadata.revision <= metadata.revision
+        {
+            return Err(ErrorCode::InvalidPlanVersion.into());
+        }
+        if new_metadata.is_superseded() {
+            msg!("Only the latest version of the plan can be adopted");
+            return Err(ErrorCode::PlanSuperseded.into());
+        }
+
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        check_plan_active(new_metadata_account, current_ts)?;
+
+        accrue_rewards(&mut vesting, &tranches, Some(&metadata), current_ts)?;
+        vesting.metadata = *new_metadata_account.key;
+
+        count_grants(
+            program_id,
+            metadata_account,
+            metadata_program,
+            registrar,
+            1,
+            vesting.start_balance,
+            false,
+        )?;
+        count_grants(
+            program_id,
+            new_metadata_account,
+            metadata_program,
+            registrar,
+            1,
+            vesting.start_balance,
+            true,
+        )?;
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::PlanAdopted {
+            data: EventData {
+                account: *vesting_account.key,
+                amount: 0,
+                timestamp: current_ts,
+                outstanding: vesting.outstanding,
+            },
+            plan: *new_metadata_account.key,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_cancel_withdrawal(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let authority = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+
+        if !authority.is_signer {
+            msg!("Authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut vesting =
+            VestingState::unpack(&vesting_account.data.borrow()[..VestingState::LEN])?;
+        if vesting.authority != *authority.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.pending_withdrawal == 0 {
+            return Err(ErrorCode::NoPendingWithdrawal.into());
+        }
+
+        let amount = vesting.pending_withdrawal;
+        vesting.pending_withdrawal = 0;
+        vesting.withdrawal_unlock_ts = 0;
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::WithdrawalCancelled(EventData {
+            account: *vesting_account.key,
+            amount,
+            timestamp: Clock::get()?.unix_timestamp as u64,
+            outstanding: vesting.outstanding,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_sweep(program_id: &Pubkey, accounts: &[AccountInfo]) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let metadata_account = next_account_info(accounts_iter)?;
+        let grantor_token_account = next_account_info(accounts_iter)?;
+        let mint = next_account_info(accounts_iter)?;
+        let token_program = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let (mut vesting, tranches) = unpack_vesting(&vesting_account.data.borrow())?;
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if *mint.key != vesting.mint {
+            msg!("Mint does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_token_program(token_program, vesting.vault_kind)?;
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        if vesting.paused || Self::is_globally_paused(program_id, config_account)? {
+            return Err(ErrorCode::Paused.into());
+        }
+
+        let current_ts = Clock::get()?.unix_timestamp as u64;
+        let metadata =
+            grant_metadata(&vesting, metadata_account)?.ok_or(ErrorCode::SweepUnavailable)?;
+        match metadata.sweep_ts() {
+            Some(sweep_ts) if current_ts >= sweep_ts => {}
+            _ => return Err(ErrorCode::SweepUnavailable.into()),
+        }
+
+        // Anyone can sweep, so the balance may only go back to the grantor.
+        let grantor = match vesting.vault_kind {
+            VaultKind::Native => *grantor_token_account.key,
+            VaultKind::Token => unpack_token_account(grantor_token_account)?.owner,
+        };
+        if grantor != vesting.grantor {
+            msg!("Grantor token account does not belong to the grantor");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        accrue_rewards(&mut vesting, &tranches, Some(&metadata), current_ts)?;
+        let amount = calculator::available_for_withdrawal(&vesting, &tranches, current_ts);
+        if amount == 0 {
+            return Err(ErrorCode::InsufficientWithdrawalBalance.into());
+        }
+
+        match vesting.vault_kind {
+            VaultKind::Native => release_lamports(vesting_account, grantor_token_account, amount)?,
+            VaultKind::Token => {
+                transfer_tokens(
+                    token_program,
+                    vault,
+                    mint,
+                    grantor_token_account,
+                    vesting_signer,
+                    &[signer_seeds],
+                    amount,
+                )?;
+            }
+        }
+
+        vesting.outstanding -= amount;
+        vesting.pending_withdrawal = 0;
+        vesting.withdrawal_unlock_ts = 0;
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        VestingEvent::Swept(EventData {
+            account: *vesting_account.key,
+            amount,
+            timestamp: current_ts,
+            outstanding: vesting.outstanding,
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_init_whitelist(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        admin: Pubkey,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let payer = next_account_info(accounts_iter)?;
+        let whitelist_account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let program_data = next_account_info(accounts_iter)?;
+
+        if !payer.is_signer {
+            msg!("Upgrade authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        check_upgrade_authority(program_id, payer, program_data)?;
+
+        let (whitelist_address, bump) = find_whitelist_address(program_id);
+        if whitelist_address != *whitelist_account.key {
+            msg!("Incorrect whitelist address");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let rent = Rent::get()?;
+
+        invoke_signed(
+            &create_account(
+                payer.key,
+                whitelist_account.key,
+                rent.minimum_balance(WhitelistState::LEN),
+                WhitelistState::LEN as u64,
+                program_id,
+            ),
+            &[
+                payer.clone(),
+                whitelist_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[WHITELIST_SEED, &[bump]]],
+        )?;
+
+        let whitelist = WhitelistState {
+            is_initialized: true,
+            admin,
+            bump,
+            entries: vec![],
+        };
+
+        WhitelistState::pack(whitelist, &mut whitelist_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_whitelist_add(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        entry: Pubkey,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let admin = next_account_info(accounts_iter)?;
+        let whitelist_account = next_account_info(accounts_iter)?;
+
+        let mut whitelist = Self::load_whitelist(program_id, whitelist_account)?;
+        if !admin.is_signer || whitelist.admin != *admin.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if whitelist.entries.contains(&entry) {
+            return Err(ErrorCode::WhitelistEntryAlreadyExists.into());
+        }
+        if whitelist.entries.len() == WHITELIST_SIZE {
+            return Err(ErrorCode::WhitelistFull.into());
+        }
+
+        whitelist.entries.push(entry);
+        WhitelistState::pack(whitelist, &mut whitelist_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_whitelist_delete(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        entry: Pubkey,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let admin = next_account_info(accounts_iter)?;
+        let whitelist_account = next_account_info(accounts_iter)?;
+
+        let mut whitelist = Self::load_whitelist(program_id, whitelist_account)?;
+        if !admin.is_signer || whitelist.admin != *admin.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if !whitelist.entries.contains(&entry) {
+            return Err(ErrorCode::InvalidWhitelistEntry.into());
+        }
+
+        whitelist.entries.retain(|e| *e != entry);
+        WhitelistState::pack(whitelist, &mut whitelist_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    // Relays `data` to a whitelisted program signed by the vesting signer.
+    // `withdraw_amount` is the most the program may take out of the vault for
+    // `WhitelistWithdraw`, and `None` for `WhitelistDeposit`.
+    fn process_whitelist_relay(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        withdraw_amount: Option<u64>,
+        data: Vec<u8>,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let beneficiary = next_account_info(accounts_iter)?;
+        let vesting_account = next_account_info(accounts_iter)?;
+        let vault = next_account_info(accounts_iter)?;
+        let vesting_signer = next_account_info(accounts_iter)?;
+        let whitelist_account = next_account_info(accounts_iter)?;
+        let whitelisted_program = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+        let relay_accounts = accounts_iter.as_slice();
+
+        if !beneficiary.is_signer {
+            msg!("Beneficiary must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *vesting_account.owner != *program_id {
+            msg!("Vesting program must own the vesting account");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let whitelist = Self::load_whitelist(program_id, whitelist_account)?;
+        if !whitelist.entries.contains(whitelisted_program.key) {
+            return Err(ErrorCode::InvalidWhitelistEntry.into());
+        }
+
+        let mut vesting =
+            VestingState::unpack(&vesting_account.data.borrow()[..VestingState::LEN])?;
+        if vesting.beneficiary != *beneficiary.key {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if vesting.vault_kind == VaultKind::Native {
+            return Err(ErrorCode::NativeVaultUnsupported.into());
+        }
+        if vesting.vault != *vault.key {
+            msg!("Vault does not match the vesting account");
+            return Err(ProgramError::InvalidArgument);
+        }
+        if vesting.paused || Self::is_globally_paused(program_id, config_account)? {
+            return Err(ErrorCode::Paused.into());
+        }
+
+        let signer_seeds: &[&[u8]] = &[vesting_account.key.as_ref(), &[vesting.nonce]];
+        let vesting_signer_pda = Pubkey::create_program_address(signer_seeds, program_id)
+            .map_err(|_| ErrorCode::InvalidProgramAddress)?;
+        if vesting_signer_pda != *vesting_signer.key {
+            return Err(ErrorCode::InvalidProgramAddress.into());
+        }
+
+        // Tokens held for a pending withdrawal must stay in the vault.
+        if let Some(amount) = withdraw_amount {
+            let balance = vesting
+                .outstanding
+                .checked_sub(vesting.whitelist_owned)
+                .and_then(|balance| balance.checked_sub(vesting.pending_withdrawal))
+                .ok_or(ProgramError::InvalidAccountData)?;
+            if amount > balance {
+                return Err(ErrorCode::InsufficientWithdrawalBalance.into());
+            }
+        }
+
+        let relay_ix = Instruction {
+            program_id: *whitelisted_program.key,
+            accounts: relay_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: *account.key,
+                    is_signer: account.is_signer || account.key == vesting_signer.key,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data,
+        };
+
+        let before = unpack_token_account(vault)?.amount;
+        invoke_signed(&relay_ix, accounts, &[signer_seeds])?;
+        let after = unpack_token_account(vault)?.amount;
+
+        match withdraw_amount {
+            Some(amount) => {
+                let withdrawn = before
+                    .checked_sub(after)
+                    .ok_or(ErrorCode::WhitelistWithdrawLimit)?;
+                if withdrawn > amount {
+                    return Err(ErrorCode::WhitelistWithdrawLimit.into());
+                }
+                vesting.whitelist_owned = vesting
+                    .whitelist_owned
+                    .checked_add(withdrawn)
+                    .ok_or(ErrorCode::WhitelistWithdrawLimit)?;
+            }
+            None => {
+                let deposited = after
+                    .checked_sub(before)
+                    .ok_or(ErrorCode::WhitelistWithdrawLimit)?;
+                vesting.whitelist_owned = vesting
+                    .whitelist_owned
+                    .checked_sub(deposited)
+                    .ok_or(ErrorCode::WhitelistDepositOverflow)?;
+            }
+        }
+
+        VestingState::pack(
+            vesting,
+            &mut vesting_account.data.borrow_mut()[..VestingState::LEN],
+        )?;
+
+        Ok(())
+    }
+
+    fn process_init_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        admin: Pubkey,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let payer = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+        let system_program = next_account_info(accounts_iter)?;
+        let program_data = next_account_info(accounts_iter)?;
+
+        if !payer.is_signer {
+            msg!("Upgrade authority must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        check_upgrade_authority(program_id, payer, program_data)?;
+
+        let (config_address, bump) = find_config_address(program_id);
+        if config_address != *config_account.key {
+            msg!("Incorrect config address");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let rent = Rent::get()?;
+
+        invoke_signed(
+            &create_account(
+                payer.key,
+                config_account.key,
+                rent.minimum_balance(ConfigState::LEN),
+                ConfigState::LEN as u64,
+                program_id,
+            ),
+            &[
+                payer.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[CONFIG_SEED, &[bump]]],
+        )?;
+
+        let config = ConfigState {
+            is_initialized: true,
+            admin,
+            bump,
+            paused: false,
+        };
+
+        ConfigState::pack(config, &mut config_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_set_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        paused: bool,
+    ) -> Result<(), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+
+        let signer = next_account_info(accounts_iter)?;
+        let target = next_account_info(accounts_iter)?;
+
+        if !signer.is_signer {
+            msg!("Authority or admin must be a signer");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *target.key == find_config_address(program_id).0 {
+            let mut config = Self::load_config(program_id, target)?;
+            if config.admin != *signer.key {
+                return Err(ErrorCode::Unauthorized.into());
+            }
+
+            config.paused = paused;
+            ConfigState::pack(config, &mut target.data.borrow_mut())?;
+        } else {
+            if *target.owner != *program_id {
+                msg!("Vesting program must own the vesting account");
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let mut vesting = VestingState::unpack(&target.data.borrow()[..VestingState::LEN])?;
+            if vesting.authority != *signer.key {
+                return Err(ErrorCode::Unauthorized.into());
+            }
+
+            vesting.paused = paused;
+            VestingState::pack(vesting, &mut target.data.borrow_mut()[..VestingState::LEN])?;
+        }
+
+        Ok(())
+    }
+
+    fn load_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<ConfigState, ProgramError> {
+        if *config_account.owner != *program_id {
+            msg!("Vesting program must own the config");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let config = ConfigState::unpack(&config_account.data.borrow())?;
+        let config_address =
+            Pubkey::create_program_address(&[CONFIG_SEED, &[config.bump]], program_id)?;
+        if config_address != *config_account.key {
+            msg!("Incorrect config address");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Ok(config)
+    }
+
+    // The global switch is off until the config has been created.
+    fn is_globally_paused(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<bool, ProgramError> {
+        if config_account.data_is_empty() {
+            if find_config_address(program_id).0 != *config_account.key {
+                msg!("Incorrect config address");
+                return Err(ProgramError::InvalidArgument);
+            }
+            return Ok(false);
+        }
+
+        Ok(Self::load_config(program_id, config_account)?.paused)
+    }
+
+    fn load_whitelist(
+        program_id: &Pubkey,
+        whitelist_account: &AccountInfo,
+    ) -> Result<WhitelistState, ProgramError> {
+        if *whitelist_account.owner != *program_id {
+            msg!("Vesting program must own the whitelist");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let whitelist = WhitelistState::unpack(&whitelist_account.data.borrow())?;
+        let whitelist_address =
+            Pubkey::create_program_address(&[WHITELIST_SEED, &[whitelist.bump]], program_id)?;
+        if whitelist_address != *whitelist_account.key {
+            msg!("Incorrect whitelist address");
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Ok(whitelist)
+    }
+}
+
+// Reads the vesting state and the tranches it unlocks. Milestone schedules
+// are read as their attested milestones, see `calculator::milestone_tranches`.
+fn unpack_vesting(data: &[u8]) -> Result<(VestingState, Vec<Tranche>), ProgramError> {
+    let vesting = VestingState::unpack(&data[..VestingState::LEN])?;
+    let tranches = match vesting.schedule_mode {
+        ScheduleMode::Milestones => {
+            calculator::milestone_tranches(&unpack_milestones(&data[VestingState::LEN..])?)
+        }
+        _ => unpack_tranches(&data[VestingState::LEN..])?,
+    };
+
+    Ok((vesting, tranches))
+}
+
+// Reads a metadata account of any layout version, see
+// `MetadataState::versioned_len`.
+fn unpack_metadata(metadata_account: &AccountInfo) -> Result<MetadataState, ProgramError> {
+    let data = metadata_account.data.borrow();
+    let version = MetadataState::version(&data).ok_or(ErrorCode::UnsupportedVersion)?;
+    let len = MetadataState::versioned_len(version)
+        .filter(|len| data.len() >= *len)
+        .ok_or(ErrorCode::UnsupportedVersion)?;
+
+    let metadata = MetadataState::unpack_versioned(version, &data[..len])?;
+    if !metadata.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(metadata)
+}
+
+// The metadata the grant was created with, or `None` if that is not an
+// account of the metadata program.
+fn grant_metadata(
+    vesting: &VestingState,
+    metadata_account: &AccountInfo,
+) -> Result<Option<MetadataState>, ProgramError> {
+    check_grant_metadata(vesting, metadata_account)?;
+
+    if *metadata_account.owner != vesting_metadata::id() {
+        return Ok(None);
+    }
+    unpack_metadata(metadata_account).map(Some)
+}
+
+fn check_grant_metadata(vesting: &VestingState, metadata_account: &AccountInfo) -> ProgramResult {
+    if vesting.metadata != *metadata_account.key {
+        msg!("Metadata account does not match the vesting account");
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+// Moves the grant counters of the plan by CPI, with `Commit` if `commit` and
+// `Release` otherwise. Accounts not owned by the metadata program are not
+// plans and count nothing.
+fn count_grants<'a>(
+    program_id: &Pubkey,
+    metadata_account: &AccountInfo<'a>,
+    metadata_program: &AccountInfo<'a>,
+    registrar: &AccountInfo<'a>,
+    vestings: u64,
+    amount: u64,
+    commit: bool,
+) -> ProgramResult {
+    if *metadata_account.owner != vesting_metadata::id() {
+        return Ok(());
+    }
+    if *metadata_program.key != vesting_metadata::id() {
+        msg!("Incorrect vesting metadata program ID");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let (registrar_address, bump) = find_registrar_address(program_id);
+    if registrar_address != *registrar.key {
+        return Err(ErrorCode::InvalidProgramAddress.into());
+    }
+
+    let instruction = if commit {
+        metadata_instruction::commit
+    } else {
+        metadata_instruction::release
+    };
+    invoke_signed(
+        &instruction(
+            metadata_program.key,
+            registrar.key,
+            metadata_account.key,
+            vestings,
+            amount,
+        )?,
+        &[
+            registrar.clone(),
+            metadata_account.clone(),
+            metadata_program.clone(),
+        ],
+        &[&[REGISTRAR_SEED, &[bump]]],
+    )
+}
+
+// Refuses new grants against a plan whose lifetime has passed or that was
+// replaced by a newer version. Accounts not owned by the metadata program are
+// not plans and never expire.
+fn check_plan_active(metadata_account: &AccountInfo, current_ts: u64) -> ProgramResult {
+    if *metadata_account.owner != vesting_metadata::id() {
+        return Ok(());
+    }
+
+    let metadata = unpack_metadata(metadata_account)?;
+    if metadata
+        .expiry_ts()
+        .is_some_and(|expiry_ts| current_ts >= expiry_ts)
+    {
+        return Err(ErrorCode::PlanExpired.into());
+    }
+    if metadata.is_superseded() {
+        return Err(ErrorCode::PlanSuperseded.into());
+    }
+    Ok(())
+}
+
+// Brings `accrued_rewards` up to `current_ts` on the reward terms of
+// `metadata`. Grants without metadata earn nothing, their accrual timestamp
+// just moves up.
+fn accrue_rewards(
+    vesting: &mut VestingState,
+    tranches: &[Tranche],
+    metadata: Option<&MetadataState>,
+    current_ts: u64,
+) -> ProgramResult {
+    match metadata {
+        Some(metadata) => rewards::accrue(vesting, tranches, metadata, current_ts)
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+        None => vesting.last_accrual_ts = std::cmp::max(vesting.last_accrual_ts, current_ts),
+    }
+    Ok(())
+}
+
+// Token vaults may belong to either the token program or Token-2022, native
+// vaults are funded through the system program.
+fn check_token_program(token_program: &AccountInfo, vault_kind: VaultKind) -> ProgramResult {
+    match vault_kind {
+        VaultKind::Token
+            if *token_program.key != spl_token::id()
+                && *token_program.key != spl_token_2022::id() =>
+        {
+            msg!("Incorrect spl-token ID");
+            Err(ProgramError::IncorrectProgramId)
+        }
+        VaultKind::Native if *token_program.key != system_program::id() => {
+            msg!("Native vesting accounts expect the system program");
+            Err(ProgramError::IncorrectProgramId)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Moves the deposit into a token vault owned by the vesting signer and
+// returns the mint and the owner of the funding token account.
+fn deposit_tokens<'a>(
+    authority: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    vesting_signer: &Pubkey,
+    mint: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+) -> Result<(Pubkey, Pubkey), ProgramError> {
+    check_new_vault(vault, vesting_signer, mint.key)?;
+
+    let token_account_data = unpack_token_account(token_account)?;
+    if token_account_data.mint != *mint.key {
+        msg!("Token account, vault and mint do not match");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // The transfer fee is paid on top, so that `amount` reaches the vault.
+    let deposit = amount_before_fee(mint, amount)?;
+    if token_account_data.amount < deposit {
+        msg!("Token account has insufficient funds.");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    let received = transfer_tokens(
+        token_program,
+        token_account,
+        mint,
+        vault,
+        authority,
+        &[],
+        deposit,
+    )?;
+    if received != amount {
+        return Err(ErrorCode::UnexpectedTransferFee.into());
+    }
+
+    Ok((*mint.key, token_account_data.owner))
+}
+
+// Checks that `vault` is an empty token account of `mint`, held by the vault
+// signer and with nobody else able to move or close it.
+fn check_new_vault(vault: &AccountInfo, vesting_signer: &Pubkey, mint: &Pubkey) -> ProgramResult {
+    let vault_data = unpack_token_account(vault)?;
+    if vault_data.owner != *vesting_signer {
+        return Err(ErrorCode::InvalidVaultOwner.into());
+    }
+    if vault_data.amount != 0 {
+        return Err(ErrorCode::InvalidVaultAmount.into());
+    }
+    if vault_data.delegate.is_some() {
+        msg!("Vesting vault should not have a delegate authority");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if vault_data.close_authority.is_some() {
+        msg!("Vesting vault should not have a close authority");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if vault_data.mint != *mint {
+        msg!("Vault mint does not match");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    Ok(())
+}
+
+// Moves the deposit as lamports from the authority into the vesting account,
+// on top of the rent it already holds.
+fn deposit_lamports<'a>(
+    authority: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    vesting_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    if token_account.key != authority.key || *mint.key != native_mint::id() {
+        msg!("Native vesting is funded by the authority under the native mint");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    invoke(
+        &transfer_lamports(authority.key, vesting_account.key, amount),
+        &[
+            authority.clone(),
+            vesting_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    let rent = Rent::get()?.minimum_balance(vesting_account.data_len());
+    if vesting_account.lamports().saturating_sub(rent) < amount {
+        msg!("Vesting account must be rent exempt before the deposit");
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    Ok(())
+}
+
+// Pays `amount` lamports out of a native vesting account, which has to stay
+// rent exempt.
+fn release_lamports(
+    vesting_account: &AccountInfo,
+    destination: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    let remaining = vesting_account
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    if remaining < Rent::get()?.minimum_balance(vesting_account.data_len()) {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    **destination.lamports.borrow_mut() = destination
+        .lamports()
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **vesting_account.lamports.borrow_mut() = remaining;
+
+    Ok(())
+}
+
+// Reads the base token account of either token program, skipping extensions.
+fn unpack_token_account(account: &AccountInfo) -> Result<Account, ProgramError> {
+    if *account.owner != spl_token::id() && *account.owner != spl_token_2022::id() {
+        msg!("Token account must be owned by a token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(StateWithExtensions::<Account>::unpack(&account.data.borrow())?.base)
+}
+
+// How much has to be sent for `amount` to arrive after the mint's transfer
+// fee in the current epoch.
+fn amount_before_fee(mint: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+    let data = mint.data.borrow();
+    let mint = StateWithExtensions::<Mint>::unpack(&data)?;
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(config) => gross_up(config, Clock::get()?.epoch, amount),
+        Err(_) => Ok(amount),
+    }
+}
+
+fn gross_up(config: &TransferFeeConfig, epoch: u64, amount: u64) -> Result<u64, ProgramError> {
+    config
+        .calculate_inverse_epoch_fee(epoch, amount)
+        .and_then(|fee| amount.checked_add(fee))
+        .ok_or(ProgramError::ArithmeticOverflow)
+}
+
+// Sends `amount` with `transfer_checked` and returns how much reached the
+// destination, which is less than `amount` for mints with a transfer fee.
+fn transfer_tokens<'a>(
+    token_program: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<u64, ProgramError> {
+    let decimals = StateWithExtensions::<Mint>::unpack(&mint.data.borrow())?
+        .base
+        .decimals;
+    let before = unpack_token_account(destination)?.amount;
+
+    invoke_signed(
+        &transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?,
+        &[
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            authority.clone(),
+            token_program.clone(),
+        ],
+        signer_seeds,
+    )?;
+
+    let after = unpack_token_account(destination)?.amount;
+    after
+        .checked_sub(before)
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+// Checks shared by every way of creating a vesting account. `schedule` only
+// needs its schedule fields and `start_balance` set.
+fn validate_schedule(schedule: &VestingState, current_ts: u64) -> ProgramResult {
+    if schedule.schedule_mode == ScheduleMode::Linear
+        && schedule.period_count > schedule.end_ts.saturating_sub(schedule.start_ts)
+    {
+        return Err(ErrorCode::InvalidPeriod.into());
+    }
+    if schedule.schedule_mode != ScheduleMode::Milestones && schedule.end_ts <= current_ts {
+        return Err(ErrorCode::InvalidTimestamp.into());
+    }
+    if schedule.period_count == 0 {
+        return Err(ErrorCode::InvalidPeriod.into());
+    }
+    if schedule.start_balance == 0 {
+        return Err(ErrorCode::InvalidDepositAmount.into());
+    }
+    if schedule.start_ts > schedule.end_ts
+        || schedule.cliff_ts > schedule.end_ts
+        || schedule.cliff_amount > schedule.start_balance
+    {
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+    // Calendar periods must end exactly at `end_ts`.
+    if schedule.schedule_mode == ScheduleMode::Linear
+        && schedule.period_unit != PeriodUnit::Seconds
+        && calculator::period_end(schedule, schedule.period_count) != Some(schedule.end_ts)
+    {
+        msg!("End timestamp must be period_count calendar periods after the start");
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+    if schedule.cliff_amount > 0 && schedule.cliff_ts == 0 {
+        msg!("A cliff amount requires a cliff timestamp");
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+
+    Ok(())
+}
+
+// Whether the uninitialized vesting account at `address` may be created for
+// `beneficiary` and `mint`. Accounts from `Init` record both, and `Init`
+// checked their address. Associated vesting accounts record neither, so
+// their address is derived again.
+fn vesting_matches(
+    program_id: &Pubkey,
+    address: &Pubkey,
+    vesting: &VestingState,
+    beneficiary: &Pubkey,
+    mint: &Pubkey,
+) -> bool {
+    if vesting.beneficiary == Pubkey::default() {
+        let (associated_address, _) = get_associated_vesting_address_and_bump_seed(
+            beneficiary,
+            mint,
+            program_id,
+            &associated::id(),
+        );
+        return *address == associated_address;
+    }
+    vesting.beneficiary == *beneficiary && vesting.mint == *mint
+}
+
+// Program-wide accounts pick their admin when they are created, so only the
+// upgrade authority of the program may create them.
+fn check_upgrade_authority(
+    program_id: &Pubkey,
+    authority: &AccountInfo,
+    program_data: &AccountInfo,
+) -> ProgramResult {
+    if *program_data.key != find_program_data_address(program_id)
+        || *program_data.owner != bpf_loader_upgradeable::id()
+    {
+        msg!("Incorrect program data account");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let state = limited_deserialize(
+        &program_data.data.borrow(),
+        UpgradeableLoaderState::size_of_programdata_metadata() as u64,
+    );
+    match state {
+        Ok(UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address: Some(upgrade_authority),
+            ..
+        }) if upgrade_authority == *authority.key => Ok(()),
+        _ => {
+            msg!("Signer is not the upgrade authority of the program");
+            Err(ErrorCode::Unauthorized.into())
+        }
+    }
+}
+
+// Resizes `account` to `space` bytes and tops up its rent from `payer`.
+// `reserved` lamports held on top of the rent do not count towards it.
+fn resize_account<'a>(
+    account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    reserved: u64,
+) -> ProgramResult {
+    if account.data_len() != space {
+        account.realloc(space, true)?;
+    }
+
+    let required_lamports = Rent::get()?
+        .minimum_balance(space)
+        .saturating_add(reserved)
+        .saturating_sub(account.lamports());
+    if required_lamports > 0 {
+        invoke(
+            &transfer_lamports(payer.key, account.key, required_lamports),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+
+    Ok(())
+}
+
+// Moves every lamport of a vesting account to `receiver` and zeroes its data.
+fn close_vesting_account(vesting_account: &AccountInfo, receiver: &AccountInfo) -> ProgramResult {
+    let lamports = vesting_account.lamports();
+    **receiver.lamports.borrow_mut() = receiver
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **vesting_account.lamports.borrow_mut() = 0;
+    vesting_account.data.borrow_mut().fill(0);
+
+    Ok(())
+}
+
+// Tranche timestamps must be strictly increasing and the amounts must add up
+// to the deposit.
+fn validate_tranches(tranches: &[Tranche], amount: u64) -> Result<(), ProgramError> {
+    if tranches
+        .windows(2)
+        .any(|pair| pair[0].timestamp >= pair[1].timestamp)
+    {
+        msg!("Tranche timestamps must be strictly increasing");
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+
+    let total = tranches
+        .iter()
+        .try_fold(0u64, |total, tranche| total.checked_add(tranche.amount))
+        .ok_or(ErrorCode::InvalidSchedule)?;
+    if total != amount {
+        msg!("Tranche amounts must add up to the deposit");
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+
+    Ok(())
+}
+
+// Milestones must add up to the deposit, name an attestor and start pending.
+fn validate_milestones(milestones: &[Milestone], amount: u64) -> Result<(), ProgramError> {
+    if milestones
+        .iter()
+        .any(|milestone| milestone.attestor == Pubkey::default() || milestone.completed_ts != 0)
+    {
+        msg!("Milestones must have an attestor and cannot be completed yet");
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+
+    let total = milestones
+        .iter()
+        .try_fold(0u64, |total, milestone| total.checked_add(milestone.amount))
+        .ok_or(ErrorCode::InvalidSchedule)?;
+    if total != amount {
+        msg!("Milestone amounts must add up to the deposit");
+        return Err(ErrorCode::InvalidSchedule.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::instruction;
+    use solana_program::{
+        entrypoint::{deserialize, BPF_ALIGN_OF_U128, MAX_PERMITTED_DATA_INCREASE, SUCCESS},
+        program_stubs::{self, SyscallStubs},
+        system_instruction::SystemInstruction,
+    };
+    use spl_token_2022::{extension::transfer_fee::TransferFee, state::AccountState};
+    use std::{cell::Cell, sync::Once};
+
+    fn tranche(timestamp: u64, amount: u64) -> Tranche {
+        Tranche { timestamp, amount }
+    }
+
+    thread_local! {
+        static NOW: Cell<i64> = const { Cell::new(0) };
+    }
+
+    // Serves `Clock::get` from `NOW` and `Rent::get` with the default rent,
+    // so that processors run outside the runtime. Tests run on their own
+    // threads, each with its own clock.
+    struct TestSyscalls;
+
+    impl SyscallStubs for TestSyscalls {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                unix_timestamp: NOW.with(Cell::get),
+                ..Clock::default()
+            };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            SUCCESS
+        }
+
+        // Runs the metadata program, spl-token and the system transfers and
+        // account creations in place, other programs are not run.
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            _signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            let accounts: Vec<_> = instruction
+                .accounts
+                .iter()
+                .map(|meta| {
+                    let mut account = account_infos
+                        .iter()
+                        .find(|account| *account.key == meta.pubkey)
+                        .expect("missing account")
+                        .clone();
+                    account.is_signer = meta.is_signer;
+                    account
+                })
+                .collect();
+            let program_id = instruction.program_id;
+            if program_id == vesting_metadata::id() {
+                vesting_metadata::processor::Processor::process(
+                    &program_id,
+                    &accounts,
+                    &instruction.data,
+                )
+            } else if program_id == spl_token::id() {
+                spl_token::processor::Processor::process(&program_id, &accounts, &instruction.data)
+            } else if program_id == system_program::id() {
+                match limited_deserialize(&instruction.data, 1_232)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?
+                {
+                    SystemInstruction::Transfer { lamports } => {
+                        move_lamports(&accounts[0], &accounts[1], lamports)
+                    }
+                    SystemInstruction::CreateAccount {
+                        lamports,
+                        space,
+                        owner,
+                    } => {
+                        move_lamports(&accounts[0], &accounts[1], lamports)?;
+                        accounts[1].realloc(space as usize, true)?;
+                        accounts[1].assign(&owner);
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                }
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+        **from.lamports.borrow_mut() = from
+            .lamports()
+            .checked_sub(lamports)
+            .ok_or(ProgramError::InsufficientFunds)?;
+        **to.lamports.borrow_mut() += lamports;
+        Ok(())
+    }
+
+    fn set_now(timestamp: u64) {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscalls));
+        });
+        NOW.with(|now| now.set(timestamp as i64));
+    }
+
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, lamports: u64, data: Vec<u8>) -> Self {
+            Self {
+                key,
+                owner,
+                lamports,
+                data,
+            }
+        }
+
+        // A system account holding `lamports`.
+        fn wallet(key: Pubkey, lamports: u64) -> Self {
+            Self::new(key, system_program::id(), lamports, vec![])
+        }
+
+        fn vesting(key: Pubkey, vesting: VestingState, lamports: u64) -> Self {
+            let mut data = vec![0; VestingState::LEN];
+            VestingState::pack(vesting, &mut data).unwrap();
+            Self::new(key, crate::id(), lamports, data)
+        }
+
+        fn tranches(&self) -> Vec<Tranche> {
+            unpack_tranches(&self.data[VestingState::LEN..]).unwrap()
+        }
+
+        fn state(&self) -> VestingState {
+            VestingState::unpack(&self.data[..VestingState::LEN]).unwrap()
+        }
+
+        // A version of a plan, owned by the metadata program.
+        fn plan(key: Pubkey, plan: &MetadataState) -> Self {
+            let mut data = vec![0; MetadataState::LEN];
+            plan.pack_into_slice(&mut data);
+            Self::new(key, vesting_metadata::id(), 0, data)
+        }
+
+        fn plan_state(&self) -> MetadataState {
+            MetadataState::unpack(&self.data).unwrap()
+        }
+
+        fn mint(key: Pubkey) -> Self {
+            let mut data = vec![0; Mint::LEN];
+            let mint = Mint {
+                decimals: 6,
+                is_initialized: true,
+                ..Mint::default()
+            };
+            Mint::pack(mint, &mut data).unwrap();
+            Self::new(key, spl_token::id(), 0, data)
+        }
+
+        // An spl-token account of `owner` holding `amount` of `mint`.
+        fn token(key: Pubkey, mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+            let mut data = vec![0; Account::LEN];
+            let account = Account {
+                mint,
+                owner,
+                amount,
+                state: AccountState::Initialized,
+                ..Account::default()
+            };
+            Account::pack(account, &mut data).unwrap();
+            Self::new(key, spl_token::id(), 0, data)
+        }
+
+        fn token_amount(&self) -> u64 {
+            Account::unpack(&self.data).unwrap().amount
+        }
+    }
+
+    // Runs `instruction` against `accounts` the way the runtime does: they
+    // are serialized in the order of its account metas and signed as they
+    // say, so that reallocations and invoked programs work on them, then
+    // read back.
+    fn process(instruction: &Instruction, accounts: &mut [TestAccount]) -> ProgramResult {
+        let metas = &instruction.accounts;
+        let mut input = (metas.len() as u64).to_le_bytes().to_vec();
+        // The accounts serialized and where their owner starts.
+        let mut serialized = Vec::new();
+        for (position, meta) in metas.iter().enumerate() {
+            if let Some(first) = metas[..position]
+                .iter()
+                .position(|other| other.pubkey == meta.pubkey)
+            {
+                input.push(first as u8);
+                input.extend_from_slice(&[0; 7]);
+                continue;
+            }
+            let index = accounts
+                .iter()
+                .position(|account| account.key == meta.pubkey)
+                .unwrap_or_else(|| panic!("missing account {}", meta.pubkey));
+            let account = &accounts[index];
+            let is_signer = metas
+                .iter()
+                .any(|other| other.pubkey == meta.pubkey && other.is_signer);
+
+            input.extend_from_slice(&[u8::MAX, is_signer as u8, 1, 0]);
+            input.extend_from_slice(&(account.data.len() as u32).to_le_bytes());
+            input.extend_from_slice(account.key.as_ref());
+            serialized.push((index, input.len()));
+            input.extend_from_slice(account.owner.as_ref());
+            input.extend_from_slice(&account.lamports.to_le_bytes());
+            input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+            input.extend_from_slice(&account.data);
+            input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+            input.resize(input.len().next_multiple_of(BPF_ALIGN_OF_U128), 0);
+            input.extend_from_slice(&0u64.to_le_bytes());
+        }
+        input.extend_from_slice(&(instruction.data.len() as u64).to_le_bytes());
+        input.extend_from_slice(&instruction.data);
+        input.extend_from_slice(instruction.program_id.as_ref());
+
+        // Lamports are read in place, so the buffer must be aligned for them.
+        let mut buffer = vec![0u64; input.len().div_ceil(8)];
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, buffer.len() * 8)
+        };
+        bytes[..input.len()].copy_from_slice(&input);
+
+        let result = {
+            let (program_id, infos, data) = unsafe { deserialize(bytes.as_mut_ptr()) };
+            Processor::process(program_id, &infos, data)
+        };
+
+        for (index, owner) in serialized {
+            let read_u64 = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+            let account = &mut accounts[index];
+            account.owner = Pubkey::new_from_array(bytes[owner..owner + 32].try_into().unwrap());
+            account.lamports = read_u64(owner + 32);
+            let len = read_u64(owner + 40) as usize;
+            account.data = bytes[owner + 48..owner + 48 + len].to_vec();
+        }
+        result
+    }
+
+    // A linear SOL grant held by the vesting account at `key`, which is its
+    // own vault, without metadata.
+    fn native_vesting(key: &Pubkey) -> VestingState {
+        VestingState {
+            is_initialized: true,
+            authority: Pubkey::new_unique(),
+            beneficiary: Pubkey::new_unique(),
+            vault: *key,
+            mint: spl_token::native_mint::id(),
+            grantor: Pubkey::new_unique(),
+            metadata: Pubkey::new_unique(),
+            rent_receiver: Pubkey::new_unique(),
+            outstanding: 1_000_000,
+            start_balance: 1_000_000,
+            start_ts: 1_000,
+            end_ts: 2_000,
+            period_count: 1,
+            vault_kind: VaultKind::Native,
+            nonce: find_vesting_signer_address(&crate::id(), key).1,
+            ..VestingState::default()
+        }
+    }
+
+    // A grant like `native_vesting` held in a token vault of the vesting
+    // signer, vesting in ten periods.
+    fn token_vesting(key: &Pubkey) -> VestingState {
+        VestingState {
+            vault: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            period_count: 10,
+            vault_kind: VaultKind::Token,
+            ..native_vesting(key)
+        }
+    }
+
+    // The vesting account with its `tranches` and the vault of a token
+    // grant, and its signer.
+    fn token_accounts(
+        key: Pubkey,
+        vesting: VestingState,
+        tranches: &[Tranche],
+    ) -> Vec<TestAccount> {
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let mut vesting_account = TestAccount::vesting(key, vesting, 0);
+        vesting_account
+            .data
+            .resize(VestingState::LEN + tranches.len() * Tranche::LEN, 0);
+        pack_tranches(tranches, &mut vesting_account.data[VestingState::LEN..]);
+        vesting_account.lamports = Rent::default().minimum_balance(vesting_account.data.len());
+        let mut vault =
+            TestAccount::token(vesting.vault, vesting.mint, signer, vesting.outstanding);
+        vault.lamports = Rent::default().minimum_balance(Account::LEN);
+        vec![vesting_account, vault, TestAccount::wallet(signer, 0)]
+    }
+
+    // The rent of a native vesting account plus its outstanding balance.
+    fn native_lamports(vesting: &VestingState) -> u64 {
+        Rent::default().minimum_balance(VestingState::LEN) + vesting.outstanding
+    }
+
+    // The accounts of a grant that do not hold its balance: its metadata, a
+    // plan of the metadata program if `plan` is set, its mint, and the
+    // programs and registrar instructions read.
+    fn shared_accounts(vesting: &VestingState, plan: Option<&MetadataState>) -> Vec<TestAccount> {
+        let metadata = match plan {
+            Some(plan) => TestAccount::plan(vesting.metadata, plan),
+            None => TestAccount::wallet(vesting.metadata, 0),
+        };
+        let mint = match vesting.vault_kind {
+            VaultKind::Native => TestAccount::wallet(vesting.mint, 0),
+            VaultKind::Token => TestAccount::mint(vesting.mint),
+        };
+        vec![
+            metadata,
+            mint,
+            TestAccount::wallet(system_program::id(), 0),
+            TestAccount::new(spl_token::id(), Pubkey::default(), 0, vec![]),
+            TestAccount::new(vesting_metadata::id(), Pubkey::default(), 0, vec![]),
+            TestAccount::wallet(find_registrar_address(&crate::id()).0, 0),
+        ]
+    }
+
+    #[test]
+    fn test_close_refunds_rent_receiver() {
+        set_now(1_700_000_000);
+        let key = Pubkey::new_unique();
+        let vesting = VestingState {
+            outstanding: 0,
+            ..native_vesting(&key)
+        };
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let other = Pubkey::new_unique();
+
+        let close = |rent_receiver: &Pubkey| {
+            instruction::close(
+                &crate::id(),
+                &vesting.beneficiary,
+                &key,
+                &key,
+                &signer,
+                rent_receiver,
+                &system_program::id(),
+                &vesting.metadata,
+            )
+            .unwrap()
+        };
+        let unclaimed = VestingState {
+            accrued_rewards: 5,
+            ..vesting
+        };
+        let mut accounts = vec![
+            TestAccount::vesting(key, unclaimed, 2_000_000),
+            TestAccount::wallet(vesting.beneficiary, 0),
+            TestAccount::wallet(signer, 0),
+            TestAccount::wallet(vesting.rent_receiver, 0),
+            TestAccount::wallet(other, 0),
+        ];
+        accounts.extend(shared_accounts(&vesting, None));
+
+        assert_eq!(
+            process(&close(&vesting.rent_receiver), &mut accounts),
+            Err(ErrorCode::UnclaimedRewards.into())
+        );
+        VestingState::pack(vesting, &mut accounts[0].data).unwrap();
+
+        // The rent goes back to whoever the grant recorded, nobody else.
+        assert_eq!(
+            process(&close(&other), &mut accounts),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            process(&close(&vesting.rent_receiver), &mut accounts),
+            Ok(())
+        );
+        assert_eq!(accounts[0].lamports, 0);
+        assert!(accounts[0].data.iter().all(|byte| *byte == 0));
+        assert_eq!(accounts[3].lamports, 2_000_000);
+        assert_eq!(accounts[1].lamports, 0);
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        set_now(1_700_000_000);
+        let key = Pubkey::new_unique();
+        let vesting = native_vesting(&key);
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let admin = Pubkey::new_unique();
+        let (config_key, bump) = find_config_address(&crate::id());
+        let mut config_data = vec![0; ConfigState::LEN];
+        let config = ConfigState {
+            is_initialized: true,
+            admin,
+            bump,
+            paused: false,
+        };
+        ConfigState::pack(config, &mut config_data).unwrap();
+
+        let mut accounts = vec![
+            TestAccount::vesting(key, vesting, native_lamports(&vesting)),
+            TestAccount::new(config_key, crate::id(), 0, config_data),
+            TestAccount::wallet(vesting.authority, 0),
+            TestAccount::wallet(vesting.beneficiary, 0),
+            TestAccount::wallet(admin, 0),
+            TestAccount::wallet(signer, 0),
+        ];
+        accounts.extend(shared_accounts(&vesting, None));
+
+        let pause = |signer: &Pubkey, target: &Pubkey| {
+            instruction::pause(&crate::id(), signer, target).unwrap()
+        };
+        let resume = |signer: &Pubkey, target: &Pubkey| {
+            instruction::resume(&crate::id(), signer, target).unwrap()
+        };
+        let withdraw = instruction::withdraw(
+            &crate::id(),
+            &key,
+            &key,
+            &signer,
+            &vesting.beneficiary,
+            &vesting.beneficiary,
+            &vesting.metadata,
+            &vesting.mint,
+            &system_program::id(),
+            &config_key,
+            100,
+        )
+        .unwrap();
+
+        // The admin holds the global switch, the authority its own grant.
+        for (signer, target) in [(&vesting.authority, &config_key), (&admin, &key)] {
+            assert_eq!(
+                process(&pause(signer, target), &mut accounts),
+                Err(ErrorCode::Unauthorized.into())
+            );
+        }
+
+        assert_eq!(process(&pause(&admin, &config_key), &mut accounts), Ok(()));
+        assert_eq!(
+            process(&withdraw, &mut accounts),
+            Err(ErrorCode::Paused.into())
+        );
+        assert_eq!(process(&resume(&admin, &config_key), &mut accounts), Ok(()));
+
+        assert_eq!(
+            process(&pause(&vesting.authority, &key), &mut accounts),
+            Ok(())
+        );
+        assert_eq!(
+            process(&withdraw, &mut accounts),
+            Err(ErrorCode::Paused.into())
+        );
+        assert_eq!(
+            process(&resume(&admin, &key), &mut accounts),
+            Err(ErrorCode::Unauthorized.into())
+        );
+        assert_eq!(
+            process(&resume(&vesting.authority, &key), &mut accounts),
+            Ok(())
+        );
+
+        assert_eq!(process(&withdraw, &mut accounts), Ok(()));
+        assert_eq!(accounts[3].lamports, 100);
+    }
+
+    #[test]
+    fn test_gross_up_transfer_fee() {
+        let fee = |epoch: u64, maximum_fee: u64, basis_points: u16| TransferFee {
+            epoch: epoch.into(),
+            maximum_fee: maximum_fee.into(),
+            transfer_fee_basis_points: basis_points.into(),
+        };
+        let config = TransferFeeConfig {
+            older_transfer_fee: fee(0, u64::MAX, 100),
+            newer_transfer_fee: fee(10, 50, 250),
+            ..TransferFeeConfig::default()
+        };
+
+        // 1% before epoch 10, so 10_000 is sent for 9_900 to arrive.
+        let deposit = gross_up(&config, 9, 9_900).unwrap();
+        assert_eq!(deposit, 10_000);
+        assert_eq!(
+            deposit - config.calculate_epoch_fee(9, deposit).unwrap(),
+            9_900
+        );
+
+        // From epoch 10 the fee is capped at 50.
+        assert_eq!(gross_up(&config, 10, 9_900).unwrap(), 9_950);
+        assert_eq!(gross_up(&config, 10, 0).unwrap(), 0);
+        assert!(gross_up(&config, 9, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_registrar_program() {
+        // The metadata program only counts grants signed for by this program.
+        assert_eq!(vesting_metadata::vesting_program::id(), crate::id());
+    }
+
+    #[test]
+    fn test_vesting_matches() {
+        let program_id = crate::id();
+        let (beneficiary, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let other = Pubkey::new_unique();
+
+        let associated = VestingState::default();
+        let associated_address = get_associated_vesting_address(&beneficiary, &mint);
+        assert!(vesting_matches(
+            &program_id,
+            &associated_address,
+            &associated,
+            &beneficiary,
+            &mint
+        ));
+        // Someone else's associated account, or any other blank account.
+        assert!(!vesting_matches(
+            &program_id,
+            &associated_address,
+            &associated,
+            &other,
+            &mint
+        ));
+        assert!(!vesting_matches(
+            &program_id,
+            &associated_address,
+            &associated,
+            &beneficiary,
+            &other
+        ));
+        assert!(!vesting_matches(
+            &program_id,
+            &other,
+            &associated,
+            &beneficiary,
+            &mint
+        ));
+
+        let (address, _) = find_vesting_address(&program_id, &mint, &beneficiary, 0);
+        let from_init = VestingState {
+            beneficiary,
+            mint,
+            ..VestingState::default()
+        };
+        assert!(vesting_matches(
+            &program_id,
+            &address,
+            &from_init,
+            &beneficiary,
+            &mint
+        ));
+        assert!(!vesting_matches(
+            &program_id,
+            &address,
+            &from_init,
+            &other,
+            &mint
+        ));
+    }
+
+    #[test]
+    fn test_check_upgrade_authority() {
+        let program_id = crate::id();
+        let program_data_key = find_program_data_address(&program_id);
+        let loader = bpf_loader_upgradeable::id();
+        let upgrade_authority = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+
+        // ProgramData tag, deployment slot, then the optional authority.
+        let mut data = vec![0u8; UpgradeableLoaderState::size_of_programdata_metadata()];
+        data[..4].copy_from_slice(&3u32.to_le_bytes());
+        data[12] = 1;
+        data[13..].copy_from_slice(upgrade_authority.as_ref());
+        let mut frozen = data.clone();
+        frozen[12] = 0;
+
+        let check = |authority: &Pubkey, key: &Pubkey, owner: &Pubkey, data: &[u8]| {
+            let (mut authority_lamports, mut lamports) = (0, 0);
+            let mut authority_data = [];
+            let mut data = data.to_vec();
+            let authority = AccountInfo::new(
+                authority,
+                true,
+                true,
+                &mut authority_lamports,
+                &mut authority_data,
+                &other,
+                false,
+                0,
+            );
+            let program_data =
+                AccountInfo::new(key, false, false, &mut lamports, &mut data, owner, false, 0);
+            check_upgrade_authority(&program_id, &authority, &program_data)
+        };
+
+        assert!(check(&upgrade_authority, &program_data_key, &loader, &data).is_ok());
+        assert_eq!(
+            check(&other, &program_data_key, &loader, &data),
+            Err(ErrorCode::Unauthorized.into())
+        );
+        assert_eq!(
+            check(&upgrade_authority, &program_data_key, &loader, &frozen),
+            Err(ErrorCode::Unauthorized.into())
+        );
+        assert_eq!(
+            check(&upgrade_authority, &other, &loader, &data),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            check(&upgrade_authority, &program_data_key, &other, &data),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_validate_milestones() {
+        let milestone = |amount, attestor| Milestone {
+            timestamp: 0,
+            amount,
+            attestor,
+            completed_ts: 0,
+        };
+        let attestor = Pubkey::new_unique();
+
+        assert!(
+            validate_milestones(&[milestone(10, attestor), milestone(20, attestor)], 30).is_ok()
+        );
+        assert!(validate_milestones(&[milestone(10, attestor)], 30).is_err());
+        assert!(validate_milestones(&[milestone(30, Pubkey::default())], 30).is_err());
+
+        let mut completed = milestone(30, attestor);
+        completed.completed_ts = 1;
+        assert!(validate_milestones(&[completed], 30).is_err());
+    }
+
+    #[test]
+    fn test_validate_tranches() {
+        let tranches = [tranche(100, 10), tranche(200, 20), tranche(300, 30)];
+        assert!(validate_tranches(&tranches, 60).is_ok());
+        assert_eq!(
+            validate_tranches(&tranches, 59).unwrap_err(),
+            ErrorCode::InvalidSchedule.into()
+        );
+    }
+
+    #[test]
+    fn test_validate_tranches_not_increasing() {
+        let tranches = [tranche(100, 10), tranche(100, 20)];
+        assert_eq!(
+            validate_tranches(&tranches, 30).unwrap_err(),
+            ErrorCode::InvalidSchedule.into()
+        );
+    }
+
+    #[test]
+    fn test_validate_tranches_overflow() {
+        let tranches = [tranche(100, u64::MAX), tranche(200, 1)];
+        assert_eq!(
+            validate_tranches(&tranches, u64::MAX).unwrap_err(),
+            ErrorCode::InvalidSchedule.into()
+        );
+    }
+
+    #[test]
+    fn test_timelocked_withdrawal() {
+        let now = 1_700_000_000;
+        set_now(now);
+        let key = Pubkey::new_unique();
+        let vesting = native_vesting(&key);
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let config = find_config_address(&crate::id()).0;
+        let plan = MetadataState {
+            is_initialized: true,
+            withdrawal_timelock: 86_400,
+            ..MetadataState::default()
+        };
+
+        let mut accounts = vec![
+            TestAccount::vesting(key, vesting, native_lamports(&vesting)),
+            TestAccount::wallet(vesting.authority, 0),
+            TestAccount::wallet(vesting.beneficiary, 0),
+            TestAccount::wallet(signer, 0),
+            TestAccount::wallet(config, 0),
+        ];
+        accounts.extend(shared_accounts(&vesting, Some(&plan)));
+
+        let request = |amount| {
+            instruction::request_withdrawal(
+                &crate::id(),
+                &vesting.beneficiary,
+                &key,
+                &vesting.metadata,
+                &config,
+                amount,
+            )
+            .unwrap()
+        };
+        let cancel =
+            |signer: &Pubkey| instruction::cancel_withdrawal(&crate::id(), signer, &key).unwrap();
+        let complete = instruction::complete_withdrawal(
+            &crate::id(),
+            &key,
+            &key,
+            &signer,
+            &vesting.beneficiary,
+            &vesting.beneficiary,
+            &vesting.metadata,
+            &vesting.mint,
+            &system_program::id(),
+            &config,
+        )
+        .unwrap();
+        let withdraw = instruction::withdraw(
+            &crate::id(),
+            &key,
+            &key,
+            &signer,
+            &vesting.beneficiary,
+            &vesting.beneficiary,
+            &vesting.metadata,
+            &vesting.mint,
+            &system_program::id(),
+            &config,
+            100,
+        )
+        .unwrap();
+
+        assert_eq!(
+            process(&withdraw, &mut accounts),
+            Err(ErrorCode::WithdrawalRequestRequired.into())
+        );
+        assert_eq!(
+            process(&complete, &mut accounts),
+            Err(ErrorCode::NoPendingWithdrawal.into())
+        );
+        assert_eq!(
+            process(&request(vesting.outstanding + 1), &mut accounts),
+            Err(ErrorCode::InsufficientWithdrawalBalance.into())
+        );
+
+        assert_eq!(process(&request(100), &mut accounts), Ok(()));
+        assert_eq!(accounts[0].state().pending_withdrawal, 100);
+        assert_eq!(accounts[0].state().withdrawal_unlock_ts, now + 86_400);
+        assert_eq!(
+            process(&request(100), &mut accounts),
+            Err(ErrorCode::WithdrawalPending.into())
+        );
+        assert_eq!(
+            process(&complete, &mut accounts),
+            Err(ErrorCode::WithdrawalTimelocked.into())
+        );
+
+        // Only the authority can cancel, for instance after a compromise of
+        // the beneficiary key.
+        assert_eq!(
+            process(&cancel(&vesting.beneficiary), &mut accounts),
+            Err(ErrorCode::Unauthorized.into())
+        );
+        assert_eq!(process(&cancel(&vesting.authority), &mut accounts), Ok(()));
+        assert_eq!(accounts[0].state().pending_withdrawal, 0);
+        assert_eq!(
+            process(&cancel(&vesting.authority), &mut accounts),
+            Err(ErrorCode::NoPendingWithdrawal.into())
+        );
+
+        assert_eq!(process(&request(300), &mut accounts), Ok(()));
+        set_now(now + 86_399);
+        assert_eq!(
+            process(&complete, &mut accounts),
+            Err(ErrorCode::WithdrawalTimelocked.into())
+        );
+        set_now(now + 86_400);
+        assert_eq!(process(&complete, &mut accounts), Ok(()));
+
+        let completed = accounts[0].state();
+        assert_eq!(completed.pending_withdrawal, 0);
+        assert_eq!(completed.withdrawal_unlock_ts, 0);
+        assert_eq!(completed.outstanding, vesting.outstanding - 300);
+        assert_eq!(accounts[2].lamports, 300);
+    }
+
+    #[test]
+    fn test_create_vesting_batch() {
+        let now = 500;
+        set_now(now);
+        let template = token_vesting(&Pubkey::new_unique());
+        let authority = template.authority;
+        let token_account = Pubkey::new_unique();
+        let plan = MetadataState {
+            is_initialized: true,
+            ..MetadataState::default()
+        };
+        let entries: Vec<BatchEntry> = [100_000, 250_000, 400_000]
+            .into_iter()
+            .map(|amount| BatchEntry {
+                beneficiary: Pubkey::new_unique(),
+                amount,
+            })
+            .collect();
+        let grants: Vec<(Pubkey, Pubkey)> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    get_associated_vesting_address(&entry.beneficiary, &template.mint),
+                    Pubkey::new_unique(),
+                )
+            })
+            .collect();
+
+        let create = |entries: Vec<BatchEntry>| {
+            instruction::create_vesting_batch(
+                &crate::id(),
+                &authority,
+                &token_account,
+                &template.metadata,
+                &template.mint,
+                &spl_token::id(),
+                &grants,
+                1_000,
+                2_000,
+                10,
+                PeriodUnit::Seconds,
+                1_500,
+                2_000,
+                true,
+                entries,
+            )
+            .unwrap()
+        };
+        let mut accounts = vec![
+            TestAccount::wallet(authority, 0),
+            TestAccount::token(token_account, template.mint, authority, 1_000_000),
+            TestAccount::wallet(find_vesting_signer_address(&crate::id(), &grants[0].0).0, 0),
+        ];
+        for (vesting, vault) in &grants {
+            let signer = find_vesting_signer_address(&crate::id(), vesting).0;
+            accounts.push(TestAccount::new(
+                *vesting,
+                crate::id(),
+                Rent::default().minimum_balance(VestingState::LEN),
+                vec![0; VestingState::LEN],
+            ));
+            accounts.push(TestAccount::token(*vault, template.mint, signer, 0));
+        }
+        accounts.extend(shared_accounts(&template, Some(&plan)));
+
+        // Each vesting account is derived for its own entry, and a mismatch
+        // fails the batch before any tokens move.
+        let mut swapped = entries.clone();
+        swapped.swap(0, 1);
+        assert_eq!(
+            process(&create(swapped), &mut accounts),
+            Err(ErrorCode::InvalidProgramAddress.into())
+        );
+        assert_eq!(accounts[1].token_amount(), 1_000_000);
+
+        assert_eq!(process(&create(entries.clone()), &mut accounts), Ok(()));
+        assert_eq!(accounts[1].token_amount(), 250_000);
+        for (index, (entry, (vesting, vault))) in entries.iter().zip(&grants).enumerate() {
+            let nonce = find_vesting_signer_address(&crate::id(), vesting).1;
+            assert_eq!(accounts[4 + 2 * index].token_amount(), entry.amount);
+            assert_eq!(
+                accounts[3 + 2 * index].state(),
+                VestingState {
+                    is_initialized: true,
+                    authority,
+                    beneficiary: entry.beneficiary,
+                    vault: *vault,
+                    mint: template.mint,
+                    grantor: authority,
+                    metadata: template.metadata,
+                    rent_receiver: authority,
+                    outstanding: entry.amount,
+                    start_balance: entry.amount,
+                    created_ts: now,
+                    start_ts: 1_000,
+                    end_ts: 2_000,
+                    period_count: 10,
+                    cliff_ts: 1_500,
+                    cliff_amount: entry.amount / 5,
+                    revocable: true,
+                    vault_kind: VaultKind::Token,
+                    nonce,
+                    last_accrual_ts: now,
+                    ..VestingState::default()
+                }
+            );
+        }
+        let plan = accounts[9].plan_state();
+        assert_eq!(plan.active_vestings, 3);
+        assert_eq!(plan.total_committed, 750_000);
+
+        // The accounts are taken, so the same batch cannot be created twice.
+        assert_eq!(
+            process(&create(entries), &mut accounts),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+    }
+
+    #[test]
+    fn test_early_withdraw() {
+        set_now(1_300);
+        let key = Pubkey::new_unique();
+        let vesting = token_vesting(&key);
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let config = find_config_address(&crate::id()).0;
+        let token_account = Pubkey::new_unique();
+        let treasury = Pubkey::new_unique();
+        let plan = MetadataState {
+            is_initialized: true,
+            early_withdrawal_fee: 1_000,
+            treasury,
+            active_vestings: 1,
+            total_committed: vesting.start_balance,
+            ..MetadataState::default()
+        };
+
+        let early_withdraw = |treasury: &Pubkey, amount| {
+            instruction::early_withdraw(
+                &crate::id(),
+                &key,
+                &vesting.vault,
+                &signer,
+                &vesting.beneficiary,
+                &token_account,
+                &vesting.metadata,
+                treasury,
+                &vesting.mint,
+                &spl_token::id(),
+                &config,
+                &system_program::id(),
+                amount,
+            )
+            .unwrap()
+        };
+        let other = Pubkey::new_unique();
+        let mut accounts = token_accounts(key, vesting, &[]);
+        accounts.extend([
+            TestAccount::wallet(vesting.beneficiary, 10_000_000),
+            TestAccount::token(token_account, vesting.mint, vesting.beneficiary, 0),
+            TestAccount::token(treasury, vesting.mint, Pubkey::new_unique(), 0),
+            TestAccount::token(other, vesting.mint, Pubkey::new_unique(), 0),
+            TestAccount::wallet(config, 0),
+        ]);
+        accounts.extend(shared_accounts(&vesting, Some(&plan)));
+
+        // The fee only goes to the treasury of the plan, not to its vault
+        // or any other account.
+        assert_eq!(
+            process(&early_withdraw(&other, 350_000), &mut accounts),
+            Err(ProgramError::InvalidArgument)
+        );
+        // Three of the ten periods have vested, only the other seven can be
+        // withdrawn early.
+        assert_eq!(
+            process(&early_withdraw(&treasury, 700_001), &mut accounts),
+            Err(ErrorCode::InsufficientWithdrawalBalance.into())
+        );
+
+        assert_eq!(
+            process(&early_withdraw(&treasury, 350_000), &mut accounts),
+            Ok(())
+        );
+        assert_eq!(accounts[1].token_amount(), 650_000);
+        assert_eq!(accounts[4].token_amount(), 315_000);
+        assert_eq!(accounts[5].token_amount(), 35_000);
+        assert_eq!(accounts[6].token_amount(), 0);
+
+        // The linear schedule became tranches, the vested ones untouched and
+        // the rest halved.
+        let withdrawn = accounts[0].state();
+        assert_eq!(withdrawn.schedule_mode, ScheduleMode::Tranches);
+        assert_eq!(withdrawn.outstanding, 650_000);
+        assert_eq!(withdrawn.start_balance, 650_000);
+        let expected: Vec<Tranche> = (0..10)
+            .map(|index| {
+                let amount = if index < 3 { 100_000 } else { 50_000 };
+                tranche(1_100 + 100 * index, amount)
+            })
+            .collect();
+        assert_eq!(accounts[0].tranches(), expected);
+        assert_eq!(
+            accounts[0].lamports,
+            Rent::default().minimum_balance(VestingState::LEN + 10 * Tranche::LEN)
+        );
+
+        // The plan no longer commits what was withdrawn.
+        let plan = accounts[8].plan_state();
+        assert_eq!(plan.active_vestings, 1);
+        assert_eq!(plan.total_committed, 650_000);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let key = Pubkey::new_unique();
+        let vesting = native_vesting(&key);
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let config = find_config_address(&crate::id()).0;
+        // The plan stops accepting grants at 1_000_000 and can be swept
+        // 500_000 seconds later.
+        let plan = MetadataState {
+            is_initialized: true,
+            lifetime: 1_000_000,
+            sweepable: true,
+            sweep_grace_period: 500_000,
+            ..MetadataState::default()
+        };
+        let accounts_for = |plan: Option<&MetadataState>| {
+            let mut accounts = vec![
+                TestAccount::vesting(key, vesting, native_lamports(&vesting)),
+                TestAccount::wallet(vesting.grantor, 0),
+                TestAccount::wallet(signer, 0),
+                TestAccount::wallet(config, 0),
+            ];
+            accounts.extend(shared_accounts(&vesting, plan));
+            accounts
+        };
+        let sweep = |grantor: &Pubkey| {
+            instruction::sweep(
+                &crate::id(),
+                &key,
+                &key,
+                &signer,
+                &vesting.metadata,
+                grantor,
+                &vesting.mint,
+                &system_program::id(),
+                &config,
+            )
+            .unwrap()
+        };
+
+        // Grants without a plan, or of a plan that did not opt in, are
+        // never swept.
+        set_now(2_000_000);
+        for plan in [
+            None,
+            Some(MetadataState {
+                sweepable: false,
+                ..plan
+            }),
+        ] {
+            let mut accounts = accounts_for(plan.as_ref());
+            assert_eq!(
+                process(&sweep(&vesting.grantor), &mut accounts),
+                Err(ErrorCode::SweepUnavailable.into())
+            );
+        }
+
+        let mut accounts = accounts_for(Some(&plan));
+        set_now(1_499_999);
+        assert_eq!(
+            process(&sweep(&vesting.grantor), &mut accounts),
+            Err(ErrorCode::SweepUnavailable.into())
+        );
+
+        set_now(1_500_000);
+        let mut stranger = accounts_for(Some(&plan));
+        stranger[1].key = Pubkey::new_unique();
+        assert_eq!(
+            process(&sweep(&stranger[1].key), &mut stranger),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(process(&sweep(&vesting.grantor), &mut accounts), Ok(()));
+        assert_eq!(accounts[0].state().outstanding, 0);
+        assert_eq!(accounts[1].lamports, vesting.outstanding);
+        assert_eq!(
+            process(&sweep(&vesting.grantor), &mut accounts),
+            Err(ErrorCode::InsufficientWithdrawalBalance.into())
+        );
+    }
+
+    #[test]
+    fn test_count_grants_checks_metadata_program() {
+        let program_id = crate::id();
+        let metadata_key = Pubkey::new_unique();
+        let registrar_key = find_registrar_address(&program_id).0;
+        let impostor = Pubkey::new_unique();
+
+        let count = |metadata_owner: &Pubkey, metadata_program_key: &Pubkey| {
+            let (mut lamports, mut program_lamports, mut registrar_lamports) = (0, 0, 0);
+            let (mut data, mut program_data, mut registrar_data) = ([], [], []);
+            let metadata = AccountInfo::new(
+                &metadata_key,
+                false,
+                true,
+                &mut lamports,
+                &mut data,
+                metadata_owner,
+                false,
+                0,
+            );
+            let metadata_program = AccountInfo::new(
+                metadata_program_key,
+                false,
+                false,
+                &mut program_lamports,
+                &mut program_data,
+                &impostor,
+                true,
+                0,
+            );
+            let registrar = AccountInfo::new(
+                &registrar_key,
+                false,
+                false,
+                &mut registrar_lamports,
+                &mut registrar_data,
+                &impostor,
+                false,
+                0,
+            );
+            count_grants(
+                &program_id,
+                &metadata,
+                &metadata_program,
+                &registrar,
+                1,
+                100,
+                true,
+            )
+        };
+
+        // Plans are only counted by the real metadata program, which could
+        // otherwise be swapped for one that ignores the counters.
+        assert_eq!(
+            count(&vesting_metadata::id(), &impostor),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        // Accounts that are not plans count nothing.
+        assert_eq!(count(&impostor, &impostor), Ok(()));
+    }
+
+    #[test]
+    fn test_adopt_plan_version() {
+        set_now(1_700_000_000);
+        let key = Pubkey::new_unique();
+        let vesting = native_vesting(&key);
+        let (root_key, latest_key) = (vesting.metadata, Pubkey::new_unique());
+        let root = MetadataState {
+            is_initialized: true,
+            apr: 500,
+            active_vestings: 1,
+            total_committed: vesting.start_balance,
+            successor: latest_key,
+            ..MetadataState::default()
+        };
+        let latest = MetadataState {
+            apr: 600,
+            active_vestings: 0,
+            total_committed: 0,
+            root: root_key,
+            revision: 1,
+            previous: root_key,
+            successor: Pubkey::default(),
+            ..root.clone()
+        };
+
+        let adopt = |beneficiary: &Pubkey, new_metadata: &Pubkey| {
+            instruction::adopt_plan_version(
+                &crate::id(),
+                beneficiary,
+                &key,
+                &root_key,
+                new_metadata,
+            )
+            .unwrap()
+        };
+        let accounts_for = |vesting: VestingState, new_metadata: MetadataState| {
+            let mut accounts = vec![
+                TestAccount::vesting(key, vesting, native_lamports(&vesting)),
+                TestAccount::plan(latest_key, &new_metadata),
+                TestAccount::wallet(vesting.beneficiary, 0),
+            ];
+            accounts.extend(shared_accounts(&vesting, Some(&root)));
+            accounts
+        };
+        let counters = |account: &TestAccount| {
+            let metadata = account.plan_state();
+            (metadata.active_vestings, metadata.total_committed)
+        };
+
+        // Only the beneficiary moves the grant to new terms.
+        let stranger = Pubkey::new_unique();
+        let mut accounts = accounts_for(vesting, latest.clone());
+        accounts.push(TestAccount::wallet(stranger, 0));
+        assert_eq!(
+            process(&adopt(&stranger, &latest_key), &mut accounts),
+            Err(ErrorCode::Unauthorized.into())
+        );
+
+        // Versions of other plans and older revisions are not updates.
+        for new_metadata in [
+            MetadataState {
+                root: Pubkey::new_unique(),
+                ..latest.clone()
+            },
+            MetadataState {
+                revision: 0,
+                ..latest.clone()
+            },
+        ] {
+            let mut accounts = accounts_for(vesting, new_metadata);
+            assert_eq!(
+                process(&adopt(&vesting.beneficiary, &latest_key), &mut accounts),
+                Err(ErrorCode::InvalidPlanVersion.into())
+            );
+        }
+
+        // Nor can a grant skip to a version that was updated again.
+        let superseded = MetadataState {
+            successor: Pubkey::new_unique(),
+            ..latest.clone()
+        };
+        let mut accounts = accounts_for(vesting, superseded);
+        assert_eq!(
+            process(&adopt(&vesting.beneficiary, &latest_key), &mut accounts),
+            Err(ErrorCode::PlanSuperseded.into())
+        );
+
+        let mut accounts = accounts_for(vesting, latest.clone());
+        assert_eq!(
+            process(&adopt(&vesting.beneficiary, &latest_key), &mut accounts),
+            Ok(())
+        );
+        assert_eq!(accounts[0].state().metadata, latest_key);
+        assert_eq!(counters(&accounts[3]), (0, 0));
+        assert_eq!(counters(&accounts[1]), (1, vesting.start_balance));
+    }
+
+    #[test]
+    fn test_revoke() {
+        set_now(1_500);
+        let key = Pubkey::new_unique();
+        let vesting = VestingState {
+            revocable: true,
+            ..token_vesting(&key)
+        };
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let grantor_token_account = Pubkey::new_unique();
+        let plan = MetadataState {
+            is_initialized: true,
+            active_vestings: 1,
+            total_committed: vesting.start_balance,
+            ..MetadataState::default()
+        };
+        let revoke = |authority: &Pubkey| {
+            instruction::revoke(
+                &crate::id(),
+                authority,
+                &key,
+                &vesting.vault,
+                &signer,
+                &grantor_token_account,
+                &vesting.mint,
+                &spl_token::id(),
+                &vesting.metadata,
+            )
+            .unwrap()
+        };
+        let accounts_for = |vesting: VestingState| {
+            let mut accounts = token_accounts(key, vesting, &[]);
+            accounts.push(TestAccount::wallet(vesting.authority, 0));
+            accounts.push(TestAccount::wallet(vesting.beneficiary, 0));
+            accounts.push(TestAccount::token(
+                grantor_token_account,
+                vesting.mint,
+                vesting.grantor,
+                0,
+            ));
+            accounts.extend(shared_accounts(&vesting, Some(&plan)));
+            accounts
+        };
+
+        let mut accounts = accounts_for(VestingState {
+            revocable: false,
+            ..vesting
+        });
+        assert_eq!(
+            process(&revoke(&vesting.authority), &mut accounts),
+            Err(ErrorCode::Irrevocable.into())
+        );
+
+        let mut accounts = accounts_for(vesting);
+        assert_eq!(
+            process(&revoke(&vesting.beneficiary), &mut accounts),
+            Err(ErrorCode::Unauthorized.into())
+        );
+
+        // Half the periods have passed, the other half goes back.
+        assert_eq!(process(&revoke(&vesting.authority), &mut accounts), Ok(()));
+        let revoked = accounts[0].state();
+        assert!(revoked.revoked);
+        assert_eq!(revoked.outstanding, 500_000);
+        assert_eq!(revoked.start_balance, 500_000);
+        assert_eq!(accounts[1].token_amount(), 500_000);
+        assert_eq!(accounts[5].token_amount(), 500_000);
+        let counters = accounts[6].plan_state();
+        assert_eq!(
+            (counters.active_vestings, counters.total_committed),
+            (1, 500_000)
+        );
+
+        // What is left is vested, later periods change nothing.
+        set_now(1_900);
+        assert_eq!(
+            calculator::available_for_withdrawal(&revoked, &[], 1_900),
+            revoked.outstanding
+        );
+        assert_eq!(
+            process(&revoke(&vesting.authority), &mut accounts),
+            Err(ErrorCode::AlreadyRevoked.into())
+        );
+    }
+
+    #[test]
+    fn test_set_beneficiary() {
+        set_now(1_200);
+        let key = Pubkey::new_unique();
+        let tranches = [tranche(1_500, 400_000), tranche(2_000, 600_000)];
+        let vesting = VestingState {
+            schedule_mode: ScheduleMode::Tranches,
+            start_ts: 1_500,
+            period_count: 2,
+            pending_withdrawal: 100,
+            withdrawal_unlock_ts: 5_000,
+            ..token_vesting(&key)
+        };
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let new_beneficiary = Pubkey::new_unique();
+        let new_key = get_associated_vesting_address(&new_beneficiary, &vesting.mint);
+        let new_signer = find_vesting_signer_address(&crate::id(), &new_key).0;
+        let new_vault = Pubkey::new_unique();
+        let stray_key = Pubkey::new_unique();
+
+        let set_beneficiary = |new_vesting: &Pubkey| {
+            instruction::set_beneficiary(
+                &crate::id(),
+                &vesting.authority,
+                &key,
+                &vesting.vault,
+                &signer,
+                &new_beneficiary,
+                new_vesting,
+                &new_vault,
+                &vesting.mint,
+                &spl_token::id(),
+                &system_program::id(),
+                &vesting.rent_receiver,
+            )
+            .unwrap()
+        };
+        let accounts_for = |new_vesting: TestAccount| {
+            let mut accounts = token_accounts(key, vesting, &tranches);
+            accounts.extend([
+                TestAccount::wallet(vesting.authority, 10_000_000),
+                TestAccount::wallet(new_beneficiary, 0),
+                new_vesting,
+                TestAccount::token(new_vault, vesting.mint, new_signer, 0),
+                TestAccount::wallet(vesting.rent_receiver, 0),
+            ]);
+            accounts.extend(shared_accounts(&vesting, None));
+            accounts
+        };
+        let blank = |key| TestAccount::new(key, crate::id(), 0, vec![0; VestingState::LEN]);
+
+        let mut accounts = accounts_for(blank(stray_key));
+        assert_eq!(
+            process(&set_beneficiary(&stray_key), &mut accounts),
+            Err(ErrorCode::InvalidProgramAddress.into())
+        );
+
+        let taken = VestingState {
+            beneficiary: new_beneficiary,
+            ..vesting
+        };
+        let mut accounts = accounts_for(TestAccount::vesting(new_key, taken, 0));
+        assert_eq!(
+            process(&set_beneficiary(&new_key), &mut accounts),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+
+        let mut accounts = accounts_for(blank(new_key));
+        let old_rent = accounts[0].lamports + accounts[1].lamports;
+        assert_eq!(process(&set_beneficiary(&new_key), &mut accounts), Ok(()));
+
+        // The old vault and vesting account are gone, their rent refunded.
+        assert_eq!((accounts[0].lamports, accounts[1].lamports), (0, 0));
+        assert!(accounts[0].data.iter().all(|byte| *byte == 0));
+        assert_eq!(accounts[7].lamports, old_rent);
+
+        // The grant moved over whole, without the pending withdrawal.
+        let moved = accounts[5].state();
+        assert_eq!(accounts[6].token_amount(), vesting.outstanding);
+        assert_eq!(
+            moved,
+            VestingState {
+                beneficiary: new_beneficiary,
+                vault: new_vault,
+                nonce: find_vesting_signer_address(&crate::id(), &new_key).1,
+                pending_withdrawal: 0,
+                withdrawal_unlock_ts: 0,
+                ..vesting
+            }
+        );
+        assert_eq!(accounts[5].tranches(), tranches);
+        assert_eq!(
+            accounts[5].lamports,
+            Rent::default().minimum_balance(accounts[5].data.len())
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        set_now(1_300);
+        let key = Pubkey::new_unique();
+        let vesting = token_vesting(&key);
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let new_beneficiary = Pubkey::new_unique();
+        let new_key = get_associated_vesting_address(&new_beneficiary, &vesting.mint);
+        let (new_signer, new_nonce) = find_vesting_signer_address(&crate::id(), &new_key);
+        let new_vault = Pubkey::new_unique();
+        let plan = MetadataState {
+            is_initialized: true,
+            active_vestings: 1,
+            total_committed: vesting.start_balance,
+            ..MetadataState::default()
+        };
+
+        let split = |amount| {
+            instruction::split(
+                &crate::id(),
+                &vesting.authority,
+                &key,
+                &vesting.vault,
+                &signer,
+                &new_beneficiary,
+                &new_key,
+                &new_vault,
+                &vesting.mint,
+                &spl_token::id(),
+                &system_program::id(),
+                &vesting.metadata,
+                amount,
+            )
+            .unwrap()
+        };
+        let accounts_for = || {
+            let mut accounts = token_accounts(key, vesting, &[]);
+            accounts.extend([
+                TestAccount::wallet(vesting.authority, 10_000_000),
+                TestAccount::wallet(new_beneficiary, 0),
+                TestAccount::new(
+                    new_key,
+                    crate::id(),
+                    Rent::default().minimum_balance(VestingState::LEN),
+                    vec![0; VestingState::LEN],
+                ),
+                TestAccount::token(new_vault, vesting.mint, new_signer, 0),
+            ]);
+            accounts.extend(shared_accounts(&vesting, Some(&plan)));
+            accounts
+        };
+
+        let mut accounts = accounts_for();
+        assert_eq!(
+            process(&split(vesting.outstanding), &mut accounts),
+            Err(ErrorCode::InvalidSplitAmount.into())
+        );
+
+        // 300_000 divides into the ten periods, both halves stay linear.
+        assert_eq!(process(&split(300_000), &mut accounts), Ok(()));
+        assert_eq!(accounts[1].token_amount(), 700_000);
+        assert_eq!(accounts[6].token_amount(), 300_000);
+        assert_eq!(
+            accounts[0].state(),
+            VestingState {
+                outstanding: 700_000,
+                start_balance: 700_000,
+                ..vesting
+            }
+        );
+        assert_eq!(
+            accounts[5].state(),
+            VestingState {
+                beneficiary: new_beneficiary,
+                vault: new_vault,
+                rent_receiver: vesting.authority,
+                outstanding: 300_000,
+                start_balance: 300_000,
+                nonce: new_nonce,
+                ..vesting
+            }
+        );
+        assert_eq!(accounts[5].data.len(), VestingState::LEN);
+        assert_eq!(accounts[7].plan_state().active_vestings, 2);
+        assert_eq!(
+            accounts[7].plan_state().total_committed,
+            vesting.start_balance
+        );
+
+        // 333_333 does not, so both halves become tranches that still add up
+        // to the original schedule.
+        let mut accounts = accounts_for();
+        assert_eq!(process(&split(333_333), &mut accounts), Ok(()));
+        assert_eq!(accounts[1].token_amount(), 666_667);
+        assert_eq!(accounts[6].token_amount(), 333_333);
+
+        let (remaining, new) = (accounts[0].state(), accounts[5].state());
+        let (remaining_tranches, new_tranches) = (accounts[0].tranches(), accounts[5].tranches());
+        assert_eq!(remaining.schedule_mode, ScheduleMode::Tranches);
+        assert_eq!(new.schedule_mode, ScheduleMode::Tranches);
+        assert_eq!(remaining_tranches.len(), 10);
+        assert_eq!(new_tranches.len(), 10);
+        for (index, (remaining, new)) in remaining_tranches.iter().zip(&new_tranches).enumerate() {
+            assert_eq!(remaining.timestamp, 1_100 + 100 * index as u64);
+            assert_eq!(remaining.timestamp, new.timestamp);
+            assert_eq!(remaining.amount + new.amount, 100_000);
+        }
+        assert_eq!((remaining.outstanding, new.outstanding), (666_667, 333_333));
+        for account in [&accounts[0], &accounts[5]] {
+            assert_eq!(
+                account.lamports,
+                Rent::default().minimum_balance(VestingState::LEN + 10 * Tranche::LEN)
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        set_now(900);
+        let key = Pubkey::new_unique();
+        let vesting = token_vesting(&key);
+        let source_key = Pubkey::new_unique();
+        let (source_signer, source_nonce) = find_vesting_signer_address(&crate::id(), &source_key);
+        let source_tranches = [tranche(1_500, 200_000), tranche(2_500, 300_000)];
+        let source = VestingState {
+            vault: Pubkey::new_unique(),
+            rent_receiver: Pubkey::new_unique(),
+            outstanding: 500_000,
+            start_balance: 500_000,
+            schedule_mode: ScheduleMode::Tranches,
+            start_ts: 1_500,
+            end_ts: 2_500,
+            period_count: 2,
+            nonce: source_nonce,
+            ..vesting
+        };
+        let plan = MetadataState {
+            is_initialized: true,
+            active_vestings: 2,
+            total_committed: 1_500_000,
+            ..MetadataState::default()
+        };
+
+        let merge = |sources: &[(Pubkey, Pubkey, Pubkey, Pubkey)]| {
+            instruction::merge(
+                &crate::id(),
+                &vesting.authority,
+                &key,
+                &vesting.vault,
+                &vesting.mint,
+                &spl_token::id(),
+                &system_program::id(),
+                &vesting.metadata,
+                sources,
+            )
+            .unwrap()
+        };
+        let sources = [(
+            source_key,
+            source.vault,
+            source_signer,
+            source.rent_receiver,
+        )];
+        let accounts_for = |source: VestingState| {
+            let mut accounts = token_accounts(key, vesting, &[]);
+            accounts.push(TestAccount::wallet(vesting.authority, 10_000_000));
+            accounts.extend(token_accounts(source_key, source, &source_tranches));
+            accounts.push(TestAccount::wallet(source.rent_receiver, 0));
+            accounts.extend(shared_accounts(&vesting, Some(&plan)));
+            accounts
+        };
+
+        let mut accounts = accounts_for(source);
+        assert_eq!(
+            process(&merge(&[]), &mut accounts),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut accounts = accounts_for(VestingState {
+            beneficiary: Pubkey::new_unique(),
+            ..source
+        });
+        assert_eq!(
+            process(&merge(&sources), &mut accounts),
+            Err(ErrorCode::IncompatibleMerge.into())
+        );
+
+        let mut accounts = accounts_for(source);
+        let source_rent = accounts[4].lamports + accounts[5].lamports;
+        assert_eq!(process(&merge(&sources), &mut accounts), Ok(()));
+
+        // The source vault was emptied into the destination and closed along
+        // with the source vesting account.
+        assert_eq!(accounts[1].token_amount(), 1_500_000);
+        assert_eq!((accounts[4].lamports, accounts[5].lamports), (0, 0));
+        assert!(accounts[4].data.iter().all(|byte| *byte == 0));
+        assert_eq!(accounts[7].lamports, source_rent);
+
+        // The ten linear periods and the source tranches make one schedule.
+        let mut expected: Vec<Tranche> = (1..=10)
+            .map(|period| tranche(1_000 + 100 * period, 100_000))
+            .collect();
+        expected[4].amount += 200_000;
+        expected.push(tranche(2_500, 300_000));
+        assert_eq!(accounts[0].tranches(), expected);
+        assert_eq!(
+            accounts[0].state(),
+            VestingState {
+                outstanding: 1_500_000,
+                start_balance: 1_500_000,
+                schedule_mode: ScheduleMode::Tranches,
+                start_ts: 1_100,
+                end_ts: 2_500,
+                period_count: 11,
+                last_accrual_ts: 900,
+                ..vesting
+            }
+        );
+        assert_eq!(
+            accounts[0].lamports,
+            Rent::default().minimum_balance(VestingState::LEN + 11 * Tranche::LEN)
+        );
+
+        // One grant is left, committing the same amount.
+        assert_eq!(accounts[8].plan_state().active_vestings, 1);
+        assert_eq!(accounts[8].plan_state().total_committed, 1_500_000);
+    }
+
+    #[test]
+    fn test_whitelist_relay() {
+        set_now(1_500);
+        let key = Pubkey::new_unique();
+        let vesting = token_vesting(&key);
+        let signer = find_vesting_signer_address(&crate::id(), &key).0;
+        let whitelisted_program = Pubkey::new_unique();
+        let (whitelist_key, whitelist_bump) = find_whitelist_address(&crate::id());
+        let mut whitelist_data = vec![0; WhitelistState::LEN];
+        let whitelist = WhitelistState {
+            is_initialized: true,
+            admin: Pubkey::new_unique(),
+            bump: whitelist_bump,
+            entries: vec![whitelisted_program],
+        };
+        WhitelistState::pack(whitelist, &mut whitelist_data).unwrap();
+        let (config_key, config_bump) = find_config_address(&crate::id());
+        let config_account = |paused| {
+            let mut data = vec![0; ConfigState::LEN];
+            let config = ConfigState {
+                is_initialized: true,
+                admin: Pubkey::new_unique(),
+                bump: config_bump,
+                paused,
+            };
+            ConfigState::pack(config, &mut data).unwrap();
+            TestAccount::new(config_key, crate::id(), 0, data)
+        };
+
+        let relays = [
+            instruction::whitelist_withdraw(
+                &crate::id(),
+                &vesting.beneficiary,
+                &key,
+                &vesting.vault,
+                &signer,
+                &whitelist_key,
+                &whitelisted_program,
+                &config_key,
+                vec![],
+                100,
+                vec![],
+            )
+            .unwrap(),
+            instruction::whitelist_deposit(
+                &crate::id(),
+                &vesting.beneficiary,
+                &key,
+                &vesting.vault,
+                &signer,
+                &whitelist_key,
+                &whitelisted_program,
+                &config_key,
+                vec![],
+                vec![],
+            )
+            .unwrap(),
+        ];
+        let accounts_for = |vesting: VestingState, paused| {
+            let mut accounts = token_accounts(key, vesting, &[]);
+            accounts.extend([
+                TestAccount::wallet(vesting.beneficiary, 0),
+                TestAccount::new(whitelist_key, crate::id(), 0, whitelist_data.clone()),
+                TestAccount::new(whitelisted_program, Pubkey::default(), 0, vec![]),
+                config_account(paused),
+            ]);
+            accounts
+        };
+
+        for relay in &relays {
+            let mut accounts = accounts_for(vesting, true);
+            assert_eq!(process(relay, &mut accounts), Err(ErrorCode::Paused.into()));
+
+            let paused = VestingState {
+                paused: true,
+                ..vesting
+            };
+            let mut accounts = accounts_for(paused, false);
+            assert_eq!(process(relay, &mut accounts), Err(ErrorCode::Paused.into()));
+
+            let mut accounts = accounts_for(vesting, false);
+            assert_eq!(process(relay, &mut accounts), Ok(()));
+        }
+
+        // A pending withdrawal keeps its tokens out of reach of the relay.
+        let pending = |pending_withdrawal| VestingState {
+            pending_withdrawal,
+            withdrawal_unlock_ts: 5_000,
+            ..vesting
+        };
+        let mut accounts = accounts_for(pending(vesting.outstanding - 99), false);
+        assert_eq!(
+            process(&relays[0], &mut accounts),
+            Err(ErrorCode::InsufficientWithdrawalBalance.into())
+        );
+        let mut accounts = accounts_for(pending(vesting.outstanding - 100), false);
+        assert_eq!(process(&relays[0], &mut accounts), Ok(()));
+    }
+
+    // The fields of `vesting` the first release stored, in its layout and
+    // with the room to spare its `Init` allocated.
+    fn legacy_vesting_data(vesting: &VestingState) -> Vec<u8> {
+        let mut data = vec![vesting.is_initialized as u8];
+        for key in [
+            vesting.authority,
+            vesting.beneficiary,
+            vesting.vault,
+            vesting.mint,
+            vesting.grantor,
+            vesting.metadata,
+        ] {
+            data.extend_from_slice(key.as_ref());
+        }
+        for value in [
+            vesting.outstanding,
+            vesting.start_balance,
+            vesting.created_ts,
+            vesting.start_ts,
+            vesting.end_ts,
+            vesting.period_count,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.push(vesting.nonce);
+        data.resize(266, 0);
+        data
+    }
+
+    #[test]
+    fn test_migrate() {
+        set_now(1_500);
+        let key = Pubkey::new_unique();
+        let vesting = token_vesting(&key);
+        let payer = Pubkey::new_unique();
+        let plan = MetadataState {
+            is_initialized: true,
+            active_vestings: 2,
+            total_committed: 3_000_000,
+            ..MetadataState::default()
+        };
+        let migrate = |metadata: &Pubkey| {
+            instruction::migrate(&crate::id(), &payer, &key, &system_program::id(), metadata)
+                .unwrap()
+        };
+
+        let data = legacy_vesting_data(&vesting);
+        let mut accounts = vec![
+            TestAccount::new(
+                key,
+                crate::id(),
+                Rent::default().minimum_balance(data.len()),
+                data,
+            ),
+            TestAccount::wallet(payer, 10_000_000),
+        ];
+        accounts.extend(shared_accounts(&vesting, Some(&plan)));
+
+        let stray = Pubkey::new_unique();
+        accounts.push(TestAccount::plan(stray, &plan));
+        assert_eq!(
+            process(&migrate(&stray), &mut accounts),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // The grant is rewritten in place and joins the plan counters.
+        assert_eq!(process(&migrate(&vesting.metadata), &mut accounts), Ok(()));
+        assert_eq!(
+            accounts[0].state(),
+            VestingState {
+                rent_receiver: vesting.authority,
+                ..vesting
+            }
+        );
+        assert_eq!(accounts[0].data.len(), VestingState::LEN);
+        assert_eq!(
+            accounts[0].lamports,
+            Rent::default().minimum_balance(VestingState::LEN)
+        );
+        let counters = |accounts: &[TestAccount]| {
+            let plan = accounts[2].plan_state();
+            (plan.active_vestings, plan.total_committed)
+        };
+        assert_eq!(counters(&accounts), (3, 4_000_000));
+
+        // Migrating again changes nothing.
+        assert_eq!(process(&migrate(&vesting.metadata), &mut accounts), Ok(()));
+        assert_eq!(counters(&accounts), (3, 4_000_000));
+    }
 }
@@ -0,0 +1,232 @@
+//! Reward accrual for vesting accounts whose metadata pays an APR.
+//!
+//! Rewards are paid per second at `apr` basis points a year, where a year is
+//! the calendar year in UTC that the second falls in, so a second of a leap
+//! year earns 365/366 of a second of any other year. Every year of the
+//! accrual window is weighed exactly and only the total is rounded down.
+
+use vesting_metadata::state::{MetadataState, RewardBasis};
+
+use crate::{
+    calculator::vested_seconds,
+    civil::{self, SECS_PER_DAY},
+    state::{Tranche, VestingState, MAX_BPS},
+};
+
+// Weight of a second of a common and of a leap year, over a common
+// denominator of `365 * 366` days.
+const COMMON_YEAR_WEIGHT: u128 = 366;
+const LEAP_YEAR_WEIGHT: u128 = 365;
+const YEAR_DENOMINATOR: u128 = 365 * 366 * SECS_PER_DAY as u128;
+
+/// The window rewards are paid for if `vesting` accrues at `current_ts`.
+/// Accrual starts at `created_ts` and stops `duration` seconds later, or
+/// never if `duration` is zero.
+pub fn accrual_window(
+    vesting: &VestingState,
+    metadata: &MetadataState,
+    current_ts: u64,
+) -> (u64, u64) {
+    let from_ts = std::cmp::max(vesting.last_accrual_ts, vesting.created_ts);
+    let to_ts = match metadata.duration {
+        0 => current_ts,
+        duration => std::cmp::min(current_ts, vesting.created_ts.saturating_add(duration)),
+    };
+    (from_ts, std::cmp::max(from_ts, to_ts))
+}
+
+/// Rewards earned over `[from_ts, to_ts)` at `apr` basis points a year.
+/// `tranches` is read as in `calculator::available_for_withdrawal`.
+pub fn rewards_earned(
+    vesting: &VestingState,
+    tranches: &[Tranche],
+    basis: RewardBasis,
+    apr: u64,
+    from_ts: u64,
+    to_ts: u64,
+) -> Option<u64> {
+    let mut weighted = 0u128;
+    let mut ts = from_ts;
+    while ts < to_ts {
+        let (year, _, _) = civil::civil_from_days((ts / SECS_PER_DAY) as i64);
+        let next_year = civil::days_from_civil(year + 1, 1, 1) as u64 * SECS_PER_DAY;
+        let end = std::cmp::min(next_year, to_ts);
+
+        let principal = principal_seconds(vesting, tranches, basis, ts, end)?;
+        let weight = if civil::is_leap(year) {
+            LEAP_YEAR_WEIGHT
+        } else {
+            COMMON_YEAR_WEIGHT
+        };
+        weighted = weighted.checked_add(principal.checked_mul(weight)?)?;
+        ts = end;
+    }
+
+    weighted
+        .checked_mul(apr as u128)?
+        .checked_div(YEAR_DENOMINATOR * MAX_BPS as u128)?
+        .try_into()
+        .ok()
+}
+
+/// Adds the rewards earned since the last accrual to `accrued_rewards` and
+/// moves `last_accrual_ts` up to `current_ts`.
+pub fn accrue(
+    vesting: &mut VestingState,
+    tranches: &[Tranche],
+    metadata: &MetadataState,
+    current_ts: u64,
+) -> Option<()> {
+    let (from_ts, to_ts) = accrual_window(vesting, metadata, current_ts);
+    let earned = rewards_earned(
+        vesting,
+        tranches,
+        metadata.reward_basis,
+        metadata.apr,
+        from_ts,
+        to_ts,
+    )?;
+
+    vesting.accrued_rewards = vesting.accrued_rewards.checked_add(earned)?;
+    vesting.last_accrual_ts = std::cmp::max(from_ts, current_ts);
+    Some(())
+}
+
+// The balance rewards are paid on, summed over every second of the window.
+fn principal_seconds(
+    vesting: &VestingState,
+    tranches: &[Tranche],
+    basis: RewardBasis,
+    from_ts: u64,
+    to_ts: u64,
+) -> Option<u128> {
+    let seconds = (to_ts - from_ts) as u128;
+    match basis {
+        RewardBasis::Outstanding => (vesting.outstanding as u128).checked_mul(seconds),
+        RewardBasis::Unvested => (vesting.start_balance as u128)
+            .checked_mul(seconds)?
+            .checked_sub(vested_seconds(vesting, tranches, from_ts, to_ts)?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{calculator::available_for_withdrawal, state::PeriodUnit};
+
+    // 2023-01-01T00:00:00Z, 2024-01-01T00:00:00Z and 2025-01-01T00:00:00Z.
+    const YEAR_2023: u64 = 1_672_531_200;
+    const YEAR_2024: u64 = 1_704_067_200;
+    const YEAR_2025: u64 = 1_735_689_600;
+
+    fn vesting(created_ts: u64) -> VestingState {
+        VestingState {
+            is_initialized: true,
+            outstanding: 1_000_000,
+            start_balance: 1_000_000,
+            created_ts,
+            start_ts: created_ts,
+            end_ts: YEAR_2025,
+            period_count: 1,
+            ..VestingState::default()
+        }
+    }
+
+    fn earned(vesting: &VestingState, from_ts: u64, to_ts: u64) -> Option<u64> {
+        rewards_earned(
+            vesting,
+            &[],
+            RewardBasis::Outstanding,
+            1_000,
+            from_ts,
+            to_ts,
+        )
+    }
+
+    #[test]
+    fn test_full_years() {
+        // A leap year pays the same as any other, its seconds are worth less.
+        assert_eq!(
+            earned(&vesting(YEAR_2023), YEAR_2023, YEAR_2024),
+            Some(100_000)
+        );
+        assert_eq!(
+            earned(&vesting(YEAR_2023), YEAR_2024, YEAR_2025),
+            Some(100_000)
+        );
+        assert_eq!(
+            earned(&vesting(YEAR_2023), YEAR_2023, YEAR_2025),
+            Some(200_000)
+        );
+    }
+
+    #[test]
+    fn test_partial_periods() {
+        let vesting = vesting(YEAR_2023);
+
+        // 182 of 366 days.
+        assert_eq!(
+            earned(&vesting, YEAR_2024, YEAR_2024 + 182 * SECS_PER_DAY),
+            Some(49_726)
+        );
+        // 31 of 365 days and 31 of 366 days, rounded once.
+        assert_eq!(
+            earned(
+                &vesting,
+                YEAR_2024 - 31 * SECS_PER_DAY,
+                YEAR_2024 + 31 * SECS_PER_DAY
+            ),
+            Some(16_963)
+        );
+        assert_eq!(earned(&vesting, YEAR_2024, YEAR_2024 + 1), Some(0));
+    }
+
+    #[test]
+    fn test_unvested_basis() {
+        // Monthly over 2023, steps at midnight so a daily sum is exact.
+        let vesting = VestingState {
+            end_ts: YEAR_2024,
+            period_count: 12,
+            period_unit: PeriodUnit::Months,
+            ..vesting(YEAR_2023)
+        };
+        let unvested_days: u128 = (YEAR_2023..YEAR_2024)
+            .step_by(SECS_PER_DAY as usize)
+            .map(|ts| (1_000_000 - available_for_withdrawal(&vesting, &[], ts)) as u128)
+            .sum();
+        let expected = unvested_days * 1_000 / (365 * MAX_BPS as u128);
+
+        assert_eq!(
+            rewards_earned(
+                &vesting,
+                &[],
+                RewardBasis::Unvested,
+                1_000,
+                YEAR_2023,
+                YEAR_2025
+            ),
+            Some(expected as u64)
+        );
+        assert!(expected < 100_000 / 2 + 100_000 / 12);
+    }
+
+    #[test]
+    fn test_accrue() {
+        let metadata = MetadataState {
+            apr: 1_000,
+            duration: YEAR_2025 - YEAR_2024,
+            ..MetadataState::default()
+        };
+        let mut vesting = vesting(YEAR_2024);
+
+        accrue(&mut vesting, &[], &metadata, YEAR_2024 + 182 * SECS_PER_DAY).unwrap();
+        assert_eq!(vesting.accrued_rewards, 49_726);
+        assert_eq!(vesting.last_accrual_ts, YEAR_2024 + 182 * SECS_PER_DAY);
+
+        // Nothing accrues past `duration`.
+        accrue(&mut vesting, &[], &metadata, YEAR_2025 + SECS_PER_DAY).unwrap();
+        assert_eq!(vesting.accrued_rewards, 99_999);
+        accrue(&mut vesting, &[], &metadata, YEAR_2025 + 2 * SECS_PER_DAY).unwrap();
+        assert_eq!(vesting.accrued_rewards, 99_999);
+    }
+}
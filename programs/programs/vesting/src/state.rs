@@ -1,27 +1,154 @@
 use solana_program::{
-    account_info::AccountInfo,
+    bpf_loader_upgradeable,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
 
-const PK_LEN: usize = 32;
+use std::convert::TryInto;
 
-const IS_INIT: usize = 0;
-const AUTH: usize = 1;
+pub(crate) const PK_LEN: usize = 32;
+
+const DISC: usize = 0;
+const VERSION: usize = DISC + 1;
+const IS_INIT: usize = VERSION + 1;
+const AUTH: usize = IS_INIT + 1;
 const BENE: usize = AUTH + PK_LEN;
 const VAULT: usize = BENE + PK_LEN;
 const MINT: usize = VAULT + PK_LEN;
 const GRANTOR: usize = MINT + PK_LEN;
 const META: usize = GRANTOR + PK_LEN;
-const OUTSTANDING: usize = META + PK_LEN;
+const RENT_RCV: usize = META + PK_LEN;
+const OUTSTANDING: usize = RENT_RCV + PK_LEN;
 const SB: usize = OUTSTANDING + 8;
 const C_TS: usize = SB + 8;
 const S_TS: usize = C_TS + 8;
 const E_TS: usize = S_TS + 8;
 const PC: usize = E_TS + 8;
-const NCE: usize = PC + 8;
+const CLIFF_TS: usize = PC + 8;
+const CLIFF_AMT: usize = CLIFF_TS + 8;
+const WL_OWNED: usize = CLIFF_AMT + 8;
+const MODE: usize = WL_OWNED + 8;
+const UNIT: usize = MODE + 1;
+const REVOCABLE: usize = UNIT + 1;
+const REVOKED: usize = REVOCABLE + 1;
+const PAUSED: usize = REVOKED + 1;
+const KIND: usize = PAUSED + 1;
+const NCE: usize = KIND + 1;
+const RWD_TS: usize = NCE + 1;
+const RWD_ACC: usize = RWD_TS + 8;
+const PND_AMT: usize = RWD_ACC + 8;
+const PND_TS: usize = PND_AMT + 8;
+
+// The unversioned layout of accounts created before `VESTING_VERSION`.
+const V0_IS_INIT: usize = 0;
+const V0_AUTH: usize = V0_IS_INIT + 1;
+const V0_BENE: usize = V0_AUTH + PK_LEN;
+const V0_VAULT: usize = V0_BENE + PK_LEN;
+const V0_MINT: usize = V0_VAULT + PK_LEN;
+const V0_GRANTOR: usize = V0_MINT + PK_LEN;
+const V0_META: usize = V0_GRANTOR + PK_LEN;
+const V0_OUTSTANDING: usize = V0_META + PK_LEN;
+const V0_SB: usize = V0_OUTSTANDING + 8;
+const V0_C_TS: usize = V0_SB + 8;
+const V0_S_TS: usize = V0_C_TS + 8;
+const V0_E_TS: usize = V0_S_TS + 8;
+const V0_PC: usize = V0_E_TS + 8;
+const V0_NCE: usize = V0_PC + 8;
+const V0_LEN: usize = V0_NCE + 1;
+
+const TR_TS: usize = 0;
+const TR_AMT: usize = TR_TS + 8;
+
+const MS_TS: usize = 0;
+const MS_AMT: usize = MS_TS + 8;
+const MS_ATTESTOR: usize = MS_AMT + 8;
+const MS_DONE_TS: usize = MS_ATTESTOR + PK_LEN;
+
+const WL_INIT: usize = 0;
+const WL_ADMIN: usize = WL_INIT + 1;
+const WL_BUMP: usize = WL_ADMIN + PK_LEN;
+const WL_COUNT: usize = WL_BUMP + 1;
+const WL_ENTRIES: usize = WL_COUNT + 1;
+
+const CFG_INIT: usize = 0;
+const CFG_ADMIN: usize = CFG_INIT + 1;
+const CFG_BUMP: usize = CFG_ADMIN + PK_LEN;
+const CFG_PAUSED: usize = CFG_BUMP + 1;
 
+/// Seed of the program-wide `WhitelistState` account.
+pub const WHITELIST_SEED: &[u8] = b"whitelist";
+/// The maximum number of programs that can be whitelisted.
+pub const WHITELIST_SIZE: usize = 10;
+/// The maximum number of tranches a merged vesting account can hold.
+pub const MAX_MERGED_TRANCHES: usize = 256;
+/// The maximum number of vesting accounts a `CreateVestingBatch` creates.
+pub const MAX_BATCH_SIZE: usize = 16;
+/// Basis points in a whole.
+pub const MAX_BPS: u64 = 10_000;
+/// Seed of the program-wide `ConfigState` account.
+pub const CONFIG_SEED: &[u8] = b"config";
+/// First seed of the canonical vesting account addresses.
+pub const VESTING_SEED: &[u8] = b"vesting";
+/// First byte of every `VestingState`.
+pub const VESTING_DISCRIMINATOR: u8 = b'V';
+/// The `VestingState` layout written by this program. Older accounts are
+/// brought up to date with `Migrate`.
+pub const VESTING_VERSION: u8 = 1;
+/// First seed of the authority over the reward vault of a metadata account.
+pub const REWARD_SEED: &[u8] = b"rewards";
+
+/// How the deposit of a vesting account is released.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ScheduleMode {
+    /// Unlocks linearly from `start_ts` to `end_ts` over `period_count` steps.
+    #[default]
+    Linear,
+    /// Unlocks the list of tranches stored after the `VestingState` header.
+    Tranches,
+    /// Unlocks the milestones stored after the `VestingState` header once
+    /// their attestor confirms them.
+    Milestones,
+}
+
+/// The length of each period of a `ScheduleMode::Linear` schedule.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PeriodUnit {
+    /// `end_ts - start_ts` split into `period_count` periods of equal seconds.
+    #[default]
+    Seconds,
+    Days,
+    /// Calendar months, each period ends on the same day of the month as
+    /// `start_ts`, or the last day of shorter months.
+    Months,
+    /// Calendar years, Feb 29 starts end on Feb 28 in common years.
+    Years,
+}
+
+/// Where the deposit of a vesting account is held.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum VaultKind {
+    /// An SPL token account owned by the vesting signer.
+    #[default]
+    Token,
+    /// Lamports held by the vesting account itself, above its rent-exempt
+    /// minimum. `vault` is the vesting account and `mint` the native mint.
+    Native,
+}
+
+impl PeriodUnit {
+    pub fn from_u8(unit: u8) -> Option<Self> {
+        match unit {
+            0 => Some(Self::Seconds),
+            1 => Some(Self::Days),
+            2 => Some(Self::Months),
+            3 => Some(Self::Years),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct VestingState {
     pub is_initialized: bool,
     /// The account with the permission to change state
@@ -34,7 +161,12 @@ pub struct VestingState {
     pub mint: Pubkey,
     /// The owner of the token account funding this account.
     pub grantor: Pubkey,
+    /// The plan version the grant was signed under. Updating a plan creates
+    /// a new version, grants only move to it with `AdoptPlanVersion`.
     pub metadata: Pubkey,
+    /// Receives the rent of the vesting account and its vault on `Close`.
+    /// Set to the payer of `Init`, or the authority otherwise.
+    pub rent_receiver: Pubkey,
     /// The outstanding SCY deposit backing this vesting account. All withdrawls will deducted this
     /// balance.
     pub outstanding: u64,
@@ -48,8 +180,39 @@ pub struct VestingState {
     pub end_ts: u64,
     /// The number of times vesting will occur.
     pub period_count: u64,
-    /// Signer nonce.
+    /// The unix timestamp before which nothing is vested. Zero if there is no cliff.
+    pub cliff_ts: u64,
+    /// The amount released in one step once `cliff_ts` is reached. The remainder of
+    /// `start_balance` vests linearly between `start_ts` and `end_ts`.
+    pub cliff_amount: u64,
+    /// The amount of tokens currently held by whitelisted programs on behalf of this account.
+    pub whitelist_owned: u64,
+    /// How the deposit is released, see `ScheduleMode`.
+    pub schedule_mode: ScheduleMode,
+    /// The length of each linear period, see `PeriodUnit`.
+    pub period_unit: PeriodUnit,
+    /// Whether the authority may revoke this grant and claw back unvested tokens.
+    pub revocable: bool,
+    /// Set once the grant is revoked. Everything left in `outstanding` is then vested.
+    pub revoked: bool,
+    /// Set by `Pause`. Withdrawals are rejected until `Resume`, vesting keeps accruing.
+    pub paused: bool,
+    /// Whether the deposit is held in a token vault or as lamports, see `VaultKind`.
+    pub vault_kind: VaultKind,
+    /// Canonical bump of the vault signer, see `find_vesting_signer_address`.
     pub nonce: u8,
+    /// The unix timestamp up to which rewards have been added to
+    /// `accrued_rewards`. Zero for accounts migrated from before rewards,
+    /// which accrue from `created_ts`.
+    pub last_accrual_ts: u64,
+    /// Rewards accrued but not claimed yet.
+    pub accrued_rewards: u64,
+    /// Amount of the withdrawal requested by `RequestWithdrawal`, zero if
+    /// none is pending.
+    pub pending_withdrawal: u64,
+    /// The unix timestamp from which the pending withdrawal can be
+    /// completed.
+    pub withdrawal_unlock_ts: u64,
 }
 
 impl IsInitialized for VestingState {
@@ -58,33 +221,118 @@ impl IsInitialized for VestingState {
     }
 }
 
+impl VestingState {
+    /// Header size of each layout version. Version 0 is the unversioned
+    /// layout of the first release, which only held linear token grants.
+    pub fn versioned_len(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(V0_LEN),
+            VESTING_VERSION => Some(Self::LEN),
+            _ => None,
+        }
+    }
+
+    /// The layout version of a serialized `VestingState`. Unversioned
+    /// accounts start with their `is_initialized` flag and are version 0.
+    pub fn version(src: &[u8]) -> Option<u8> {
+        match src.first()? {
+            0 | 1 => Some(0),
+            &VESTING_DISCRIMINATOR => src.get(VERSION).copied(),
+            _ => None,
+        }
+    }
+
+    /// Reads a header of any layout version, see `versioned_len`. Fields
+    /// the layout lacks take their defaults, with the authority that paid
+    /// for the account as its rent receiver.
+    pub fn unpack_versioned(version: u8, src: &[u8]) -> Result<Self, ProgramError> {
+        if Self::versioned_len(version) != Some(src.len()) {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if version == VESTING_VERSION {
+            return Self::unpack_from_slice(src);
+        }
+
+        let is_initialized = match src[V0_IS_INIT] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let authority = Pubkey::new_from_array(src[V0_AUTH..V0_BENE].try_into().unwrap());
+
+        Ok(Self {
+            is_initialized,
+            authority,
+            beneficiary: Pubkey::new_from_array(src[V0_BENE..V0_VAULT].try_into().unwrap()),
+            vault: Pubkey::new_from_array(src[V0_VAULT..V0_MINT].try_into().unwrap()),
+            mint: Pubkey::new_from_array(src[V0_MINT..V0_GRANTOR].try_into().unwrap()),
+            grantor: Pubkey::new_from_array(src[V0_GRANTOR..V0_META].try_into().unwrap()),
+            metadata: Pubkey::new_from_array(src[V0_META..V0_OUTSTANDING].try_into().unwrap()),
+            rent_receiver: authority,
+            outstanding: u64::from_le_bytes(src[V0_OUTSTANDING..V0_SB].try_into().unwrap()),
+            start_balance: u64::from_le_bytes(src[V0_SB..V0_C_TS].try_into().unwrap()),
+            created_ts: u64::from_le_bytes(src[V0_C_TS..V0_S_TS].try_into().unwrap()),
+            start_ts: u64::from_le_bytes(src[V0_S_TS..V0_E_TS].try_into().unwrap()),
+            end_ts: u64::from_le_bytes(src[V0_E_TS..V0_PC].try_into().unwrap()),
+            period_count: u64::from_le_bytes(src[V0_PC..V0_NCE].try_into().unwrap()),
+            nonce: src[V0_NCE],
+            ..Self::default()
+        })
+    }
+}
+
 impl Sealed for VestingState {}
 
 impl Pack for VestingState {
-    const LEN: usize = 1 + (PK_LEN * 7) + 40 + 1;
+    const LEN: usize = PND_TS + 8;
 
-    pub fn pack_into_slice(&self, dst: &mut [u8]) {
-        dst[IS_INIT] = self.is_initalized as u8;
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[DISC] = VESTING_DISCRIMINATOR;
+        dst[VERSION] = VESTING_VERSION;
+        dst[IS_INIT] = self.is_initialized as u8;
         dst[AUTH..BENE].copy_from_slice(self.authority.as_ref());
         dst[BENE..VAULT].copy_from_slice(self.beneficiary.as_ref());
         dst[VAULT..MINT].copy_from_slice(self.vault.as_ref());
         dst[MINT..GRANTOR].copy_from_slice(self.mint.as_ref());
         dst[GRANTOR..META].copy_from_slice(self.grantor.as_ref());
-        dst[META..OUTSTANDING].copy_from_slice(self.metadata.as_ref());
-        dst[OUTSTANDING..SB].copy_from_slice(self.outstanding.to_le_bytes());
-        dst[SB..C_TS].copy_from_slice(self.start_balance.to_le_bytes());
-        dst[C_TS..S_TS].copy_from_slice(self.created_ts.to_le_bytes());
-        dst[S_TS..E_TS].copy_from_slice(self.start_ts.to_le_bytes());
-        dst[E_TS..PC].copy_from_slice(self.end_ts.to_le_bytes());
-        dst[PC..NCE].copy_from_slice(self.period_count.to_le_bytes());
-        dst[NCE..].copy_from_slice(self.nonce.to_le_bytes());
+        dst[META..RENT_RCV].copy_from_slice(self.metadata.as_ref());
+        dst[RENT_RCV..OUTSTANDING].copy_from_slice(self.rent_receiver.as_ref());
+        dst[OUTSTANDING..SB].copy_from_slice(&self.outstanding.to_le_bytes());
+        dst[SB..C_TS].copy_from_slice(&self.start_balance.to_le_bytes());
+        dst[C_TS..S_TS].copy_from_slice(&self.created_ts.to_le_bytes());
+        dst[S_TS..E_TS].copy_from_slice(&self.start_ts.to_le_bytes());
+        dst[E_TS..PC].copy_from_slice(&self.end_ts.to_le_bytes());
+        dst[PC..CLIFF_TS].copy_from_slice(&self.period_count.to_le_bytes());
+        dst[CLIFF_TS..CLIFF_AMT].copy_from_slice(&self.cliff_ts.to_le_bytes());
+        dst[CLIFF_AMT..WL_OWNED].copy_from_slice(&self.cliff_amount.to_le_bytes());
+        dst[WL_OWNED..MODE].copy_from_slice(&self.whitelist_owned.to_le_bytes());
+        dst[MODE] = self.schedule_mode as u8;
+        dst[UNIT] = self.period_unit as u8;
+        dst[REVOCABLE] = self.revocable as u8;
+        dst[REVOKED] = self.revoked as u8;
+        dst[PAUSED] = self.paused as u8;
+        dst[KIND] = self.vault_kind as u8;
+        dst[NCE] = self.nonce;
+        dst[RWD_TS..RWD_ACC].copy_from_slice(&self.last_accrual_ts.to_le_bytes());
+        dst[RWD_ACC..PND_AMT].copy_from_slice(&self.accrued_rewards.to_le_bytes());
+        dst[PND_AMT..PND_TS].copy_from_slice(&self.pending_withdrawal.to_le_bytes());
+        dst[PND_TS..].copy_from_slice(&self.withdrawal_unlock_ts.to_le_bytes());
     }
 
-    pub fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        // Freshly allocated accounts, e.g. associated vesting accounts, are
+        // all zeroes until they are created.
+        if src.iter().all(|byte| *byte == 0) {
+            return Ok(Self::default());
+        }
+        if src[DISC] != VESTING_DISCRIMINATOR || src[VERSION] != VESTING_VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
         let is_initialized = match src[IS_INIT] {
-            0 = false,
-            1 = true,
-            _ => unreachable!(),
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
         };
 
         let authority = Pubkey::new_from_array(src[AUTH..BENE].try_into().unwrap());
@@ -92,13 +340,49 @@ impl Pack for VestingState {
         let vault = Pubkey::new_from_array(src[VAULT..MINT].try_into().unwrap());
         let mint = Pubkey::new_from_array(src[MINT..GRANTOR].try_into().unwrap());
         let grantor = Pubkey::new_from_array(src[GRANTOR..META].try_into().unwrap());
-        let metadata = Pubkey::new_from_array(src[META..OUTSTANDING].try_into().unwrap());
-        let outstanding = u64::from_le_bytes(src[OUTSTANDING..SB]).try_into().unwrap());
-        let starting_balance = u64::from_le_bytes(src[SB..C_TS]).try_into().unwrap());
+        let metadata = Pubkey::new_from_array(src[META..RENT_RCV].try_into().unwrap());
+        let rent_receiver = Pubkey::new_from_array(src[RENT_RCV..OUTSTANDING].try_into().unwrap());
+        let outstanding = u64::from_le_bytes(src[OUTSTANDING..SB].try_into().unwrap());
+        let start_balance = u64::from_le_bytes(src[SB..C_TS].try_into().unwrap());
         let created_ts = u64::from_le_bytes(src[C_TS..S_TS].try_into().unwrap());
         let start_ts = u64::from_le_bytes(src[S_TS..E_TS].try_into().unwrap());
-        let end_ts = u64::from_le_bytes(src[E_TS..NCE].try_into().unwrap());
-        let nonce = u8::from_le_bytes(src[NCE..].try_into().unwrap());
+        let end_ts = u64::from_le_bytes(src[E_TS..PC].try_into().unwrap());
+        let period_count = u64::from_le_bytes(src[PC..CLIFF_TS].try_into().unwrap());
+        let cliff_ts = u64::from_le_bytes(src[CLIFF_TS..CLIFF_AMT].try_into().unwrap());
+        let cliff_amount = u64::from_le_bytes(src[CLIFF_AMT..WL_OWNED].try_into().unwrap());
+        let whitelist_owned = u64::from_le_bytes(src[WL_OWNED..MODE].try_into().unwrap());
+        let schedule_mode = match src[MODE] {
+            0 => ScheduleMode::Linear,
+            1 => ScheduleMode::Tranches,
+            2 => ScheduleMode::Milestones,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let period_unit = PeriodUnit::from_u8(src[UNIT]).ok_or(ProgramError::InvalidAccountData)?;
+        let revocable = match src[REVOCABLE] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let revoked = match src[REVOKED] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let paused = match src[PAUSED] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let vault_kind = match src[KIND] {
+            0 => VaultKind::Token,
+            1 => VaultKind::Native,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let nonce = src[NCE];
+        let last_accrual_ts = u64::from_le_bytes(src[RWD_TS..RWD_ACC].try_into().unwrap());
+        let accrued_rewards = u64::from_le_bytes(src[RWD_ACC..PND_AMT].try_into().unwrap());
+        let pending_withdrawal = u64::from_le_bytes(src[PND_AMT..PND_TS].try_into().unwrap());
+        let withdrawal_unlock_ts = u64::from_le_bytes(src[PND_TS..].try_into().unwrap());
 
         Ok(Self {
             is_initialized,
@@ -108,11 +392,632 @@ impl Pack for VestingState {
             mint,
             grantor,
             metadata,
+            rent_receiver,
             outstanding,
+            start_balance,
             created_ts,
             start_ts,
             end_ts,
+            period_count,
+            cliff_ts,
+            cliff_amount,
+            whitelist_owned,
+            schedule_mode,
+            period_unit,
+            revocable,
+            revoked,
+            paused,
+            vault_kind,
             nonce,
+            last_accrual_ts,
+            accrued_rewards,
+            pending_withdrawal,
+            withdrawal_unlock_ts,
+        })
+    }
+}
+
+/// A single release of a `ScheduleMode::Tranches` schedule.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Tranche {
+    /// The unix timestamp at which this tranche vests.
+    pub timestamp: u64,
+    /// The amount released at `timestamp`.
+    pub amount: u64,
+}
+
+impl Sealed for Tranche {}
+
+impl Pack for Tranche {
+    const LEN: usize = TR_AMT + 8;
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[TR_TS..TR_AMT].copy_from_slice(&self.timestamp.to_le_bytes());
+        dst[TR_AMT..Self::LEN].copy_from_slice(&self.amount.to_le_bytes());
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let timestamp = u64::from_le_bytes(src[TR_TS..TR_AMT].try_into().unwrap());
+        let amount = u64::from_le_bytes(src[TR_AMT..Self::LEN].try_into().unwrap());
+
+        Ok(Self { timestamp, amount })
+    }
+}
+
+/// Unpacks the tranches stored after the `VestingState` header.
+pub fn unpack_tranches(src: &[u8]) -> Result<Vec<Tranche>, ProgramError> {
+    if !src.len().is_multiple_of(Tranche::LEN) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    src.chunks_exact(Tranche::LEN)
+        .map(Tranche::unpack_from_slice)
+        .collect()
+}
+
+/// A deliverable of a `ScheduleMode::Milestones` schedule.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Milestone {
+    /// The unix timestamp before which this milestone does not vest, even once
+    /// attested. Zero for no time floor.
+    pub timestamp: u64,
+    /// The amount released by this milestone.
+    pub amount: u64,
+    /// The account that confirms completion with `AttestMilestone`.
+    pub attestor: Pubkey,
+    /// The unix timestamp of the attestation, zero while pending.
+    pub completed_ts: u64,
+}
+
+impl Sealed for Milestone {}
+
+impl Pack for Milestone {
+    const LEN: usize = MS_DONE_TS + 8;
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[MS_TS..MS_AMT].copy_from_slice(&self.timestamp.to_le_bytes());
+        dst[MS_AMT..MS_ATTESTOR].copy_from_slice(&self.amount.to_le_bytes());
+        dst[MS_ATTESTOR..MS_DONE_TS].copy_from_slice(self.attestor.as_ref());
+        dst[MS_DONE_TS..Self::LEN].copy_from_slice(&self.completed_ts.to_le_bytes());
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let timestamp = u64::from_le_bytes(src[MS_TS..MS_AMT].try_into().unwrap());
+        let amount = u64::from_le_bytes(src[MS_AMT..MS_ATTESTOR].try_into().unwrap());
+        let attestor = Pubkey::new_from_array(src[MS_ATTESTOR..MS_DONE_TS].try_into().unwrap());
+        let completed_ts = u64::from_le_bytes(src[MS_DONE_TS..Self::LEN].try_into().unwrap());
+
+        Ok(Self {
+            timestamp,
+            amount,
+            attestor,
+            completed_ts,
+        })
+    }
+}
+
+/// Unpacks the milestones stored after the `VestingState` header.
+pub fn unpack_milestones(src: &[u8]) -> Result<Vec<Milestone>, ProgramError> {
+    if !src.len().is_multiple_of(Milestone::LEN) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    src.chunks_exact(Milestone::LEN)
+        .map(Milestone::unpack_from_slice)
+        .collect()
+}
+
+/// Packs `milestones` back to back into `dst`.
+pub fn pack_milestones(milestones: &[Milestone], dst: &mut [u8]) {
+    for (milestone, chunk) in milestones.iter().zip(dst.chunks_exact_mut(Milestone::LEN)) {
+        milestone.pack_into_slice(chunk);
+    }
+}
+
+/// Packs `tranches` back to back into `dst`.
+pub fn pack_tranches(tranches: &[Tranche], dst: &mut [u8]) {
+    for (tranche, chunk) in tranches.iter().zip(dst.chunks_exact_mut(Tranche::LEN)) {
+        tranche.pack_into_slice(chunk);
+    }
+}
+
+/// Returns the address and bump of the account that signs for the reward
+/// vault of the plan whose first version is `metadata`, see
+/// `MetadataState::plan_root`. Reward vaults must be owned by it.
+pub fn find_reward_authority_address(program_id: &Pubkey, metadata: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REWARD_SEED, metadata.as_ref()], program_id)
+}
+
+/// Returns the address and bump of the program-wide whitelist account.
+pub fn find_whitelist_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[WHITELIST_SEED], program_id)
+}
+
+/// Returns the `ProgramData` account of the upgradeable loader that records
+/// the upgrade authority of `program_id`.
+pub fn find_program_data_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+/// Returns the address and bump of the program-wide config account.
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+/// Returns the address and bump of the `index`th vesting account of
+/// `beneficiary` for `mint`, as created by `Init`.
+pub fn find_vesting_address(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    beneficiary: &Pubkey,
+    index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            VESTING_SEED,
+            mint.as_ref(),
+            beneficiary.as_ref(),
+            &index.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Returns the signer that owns the vault of `vesting` and its canonical bump,
+/// which is stored in `VestingState::nonce`.
+pub fn find_vesting_signer_address(program_id: &Pubkey, vesting: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[vesting.as_ref()], program_id)
+}
+
+/// Program-wide settings, owned by the global admin.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ConfigState {
+    pub is_initialized: bool,
+    /// The account allowed to pause and resume every vesting account.
+    pub admin: Pubkey,
+    /// Bump of the config address, see `find_config_address`.
+    pub bump: u8,
+    /// Rejects withdrawals from every vesting account while set.
+    pub paused: bool,
+}
+
+impl IsInitialized for ConfigState {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Sealed for ConfigState {}
+
+impl Pack for ConfigState {
+    const LEN: usize = CFG_PAUSED + 1;
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[CFG_INIT] = self.is_initialized as u8;
+        dst[CFG_ADMIN..CFG_BUMP].copy_from_slice(self.admin.as_ref());
+        dst[CFG_BUMP] = self.bump;
+        dst[CFG_PAUSED] = self.paused as u8;
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let is_initialized = match src[CFG_INIT] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        let admin = Pubkey::new_from_array(src[CFG_ADMIN..CFG_BUMP].try_into().unwrap());
+        let bump = src[CFG_BUMP];
+        let paused = match src[CFG_PAUSED] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        Ok(Self {
+            is_initialized,
+            admin,
+            bump,
+            paused,
         })
     }
 }
+
+/// Programs that locked tokens may be relayed to, e.g. for staking while vesting.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WhitelistState {
+    pub is_initialized: bool,
+    /// The account allowed to add and remove entries.
+    pub admin: Pubkey,
+    /// Bump of the whitelist address, see `find_whitelist_address`.
+    pub bump: u8,
+    /// Whitelisted program ids, at most `WHITELIST_SIZE`.
+    pub entries: Vec<Pubkey>,
+}
+
+impl IsInitialized for WhitelistState {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Sealed for WhitelistState {}
+
+impl Pack for WhitelistState {
+    const LEN: usize = WL_ENTRIES + WHITELIST_SIZE * PK_LEN;
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        dst[WL_INIT] = self.is_initialized as u8;
+        dst[WL_ADMIN..WL_BUMP].copy_from_slice(self.admin.as_ref());
+        dst[WL_BUMP] = self.bump;
+        dst[WL_COUNT] = self.entries.len() as u8;
+
+        let entries = &mut dst[WL_ENTRIES..Self::LEN];
+        entries.fill(0);
+        for (entry, chunk) in self.entries.iter().zip(entries.chunks_exact_mut(PK_LEN)) {
+            chunk.copy_from_slice(entry.as_ref());
+        }
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let is_initialized = match src[WL_INIT] {
+            0 => false,
+            1 => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+
+        let admin = Pubkey::new_from_array(src[WL_ADMIN..WL_BUMP].try_into().unwrap());
+        let bump = src[WL_BUMP];
+        let count = src[WL_COUNT] as usize;
+        if count > WHITELIST_SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let entries = src[WL_ENTRIES..Self::LEN]
+            .chunks_exact(PK_LEN)
+            .take(count)
+            .map(|chunk| Pubkey::new_from_array(chunk.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            is_initialized,
+            admin,
+            bump,
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vesting_state() -> VestingState {
+        VestingState {
+            is_initialized: true,
+            authority: Pubkey::new_unique(),
+            beneficiary: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            grantor: Pubkey::new_unique(),
+            metadata: Pubkey::new_unique(),
+            rent_receiver: Pubkey::new_unique(),
+            outstanding: 750,
+            start_balance: 1_000,
+            created_ts: 1_600_000_000,
+            start_ts: 1_600_000_000,
+            end_ts: 1_700_000_000,
+            period_count: 36,
+            cliff_ts: 1_631_536_000,
+            cliff_amount: 250,
+            whitelist_owned: 0,
+            schedule_mode: ScheduleMode::Linear,
+            period_unit: PeriodUnit::Months,
+            revocable: true,
+            revoked: false,
+            paused: false,
+            vault_kind: VaultKind::Token,
+            nonce: 254,
+            last_accrual_ts: 1_650_000_000,
+            accrued_rewards: 12,
+            pending_withdrawal: 300,
+            withdrawal_unlock_ts: 1_650_086_400,
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let state = vesting_state();
+        let mut buf = vec![0u8; VestingState::LEN];
+        state.pack_into_slice(&mut buf);
+
+        assert_eq!(VestingState::unpack(&buf).unwrap(), state);
+    }
+
+    #[test]
+    fn test_cliff_layout() {
+        let state = vesting_state();
+        let mut buf = vec![0u8; VestingState::LEN];
+        state.pack_into_slice(&mut buf);
+
+        assert_eq!(&buf[PC..CLIFF_TS], &state.period_count.to_le_bytes());
+        assert_eq!(&buf[CLIFF_TS..CLIFF_AMT], &state.cliff_ts.to_le_bytes());
+        assert_eq!(&buf[CLIFF_AMT..WL_OWNED], &state.cliff_amount.to_le_bytes());
+        assert_eq!(buf[NCE], state.nonce);
+        assert_eq!(&buf[RWD_ACC..PND_AMT], &state.accrued_rewards.to_le_bytes());
+        assert_eq!(&buf[PND_TS..], &state.withdrawal_unlock_ts.to_le_bytes());
+    }
+
+    #[test]
+    fn test_schedule_mode_layout() {
+        let mut state = vesting_state();
+        state.schedule_mode = ScheduleMode::Tranches;
+        let mut buf = vec![0u8; VestingState::LEN];
+        state.pack_into_slice(&mut buf);
+
+        assert_eq!(buf[MODE], 1);
+        assert_eq!(VestingState::unpack(&buf).unwrap(), state);
+
+        buf[MODE] = 3;
+        assert_eq!(
+            VestingState::unpack(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn test_vault_kind_layout() {
+        let mut state = vesting_state();
+        state.vault_kind = VaultKind::Native;
+        let mut buf = vec![0u8; VestingState::LEN];
+        state.pack_into_slice(&mut buf);
+
+        assert_eq!(buf[KIND], 1);
+        assert_eq!(VestingState::unpack(&buf).unwrap(), state);
+
+        buf[KIND] = 2;
+        assert_eq!(
+            VestingState::unpack(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn test_pack_unpack_tranches() {
+        let tranches = vec![
+            Tranche {
+                timestamp: 1_600_000_000,
+                amount: 100,
+            },
+            Tranche {
+                timestamp: 1_602_592_000,
+                amount: 200,
+            },
+        ];
+        let state = vesting_state();
+        let mut buf = vec![0u8; VestingState::LEN + 2 * Tranche::LEN];
+        state.pack_into_slice(&mut buf[..VestingState::LEN]);
+        pack_tranches(&tranches, &mut buf[VestingState::LEN..]);
+
+        assert_eq!(
+            VestingState::unpack(&buf[..VestingState::LEN]).unwrap(),
+            state
+        );
+        assert_eq!(
+            unpack_tranches(&buf[VestingState::LEN..]).unwrap(),
+            tranches
+        );
+        assert!(unpack_tranches(&buf[VestingState::LEN + 1..]).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_milestones() {
+        let milestones = vec![
+            Milestone {
+                timestamp: 0,
+                amount: 100,
+                attestor: Pubkey::new_unique(),
+                completed_ts: 1_600_000_000,
+            },
+            Milestone {
+                timestamp: 1_700_000_000,
+                amount: 200,
+                attestor: Pubkey::new_unique(),
+                completed_ts: 0,
+            },
+        ];
+        let mut state = vesting_state();
+        state.schedule_mode = ScheduleMode::Milestones;
+        let mut buf = vec![0u8; VestingState::LEN + 2 * Milestone::LEN];
+        state.pack_into_slice(&mut buf[..VestingState::LEN]);
+        pack_milestones(&milestones, &mut buf[VestingState::LEN..]);
+
+        assert_eq!(buf[MODE], 2);
+        assert_eq!(
+            VestingState::unpack(&buf[..VestingState::LEN]).unwrap(),
+            state
+        );
+        assert_eq!(
+            unpack_milestones(&buf[VestingState::LEN..]).unwrap(),
+            milestones
+        );
+        assert!(unpack_milestones(&buf[VestingState::LEN + 1..]).is_err());
+    }
+
+    #[test]
+    fn test_pack_unpack_whitelist() {
+        let whitelist = WhitelistState {
+            is_initialized: true,
+            admin: Pubkey::new_unique(),
+            bump: 255,
+            entries: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+        };
+        let mut buf = vec![0u8; WhitelistState::LEN];
+        whitelist.pack_into_slice(&mut buf);
+
+        assert_eq!(buf[WL_COUNT], 2);
+        assert_eq!(WhitelistState::unpack(&buf).unwrap(), whitelist);
+
+        buf[WL_COUNT] = WHITELIST_SIZE as u8 + 1;
+        assert_eq!(
+            WhitelistState::unpack(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn test_pack_unpack_config() {
+        let config = ConfigState {
+            is_initialized: true,
+            admin: Pubkey::new_unique(),
+            bump: 253,
+            paused: true,
+        };
+        let mut buf = vec![0u8; ConfigState::LEN];
+        config.pack_into_slice(&mut buf);
+
+        assert_eq!(ConfigState::unpack(&buf).unwrap(), config);
+
+        buf[CFG_PAUSED] = 2;
+        assert_eq!(
+            ConfigState::unpack(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn test_find_vesting_address() {
+        let program_id = crate::id();
+        let mint = Pubkey::new_unique();
+        let beneficiary = Pubkey::new_unique();
+
+        let (address, bump) = find_vesting_address(&program_id, &mint, &beneficiary, 0);
+        assert_eq!(
+            Pubkey::create_program_address(
+                &[
+                    VESTING_SEED,
+                    mint.as_ref(),
+                    beneficiary.as_ref(),
+                    &0u64.to_le_bytes(),
+                    &[bump],
+                ],
+                &program_id,
+            )
+            .unwrap(),
+            address
+        );
+        assert_ne!(
+            find_vesting_address(&program_id, &mint, &beneficiary, 1).0,
+            address
+        );
+        assert_ne!(
+            find_vesting_address(&program_id, &beneficiary, &mint, 0).0,
+            address
+        );
+
+        let (signer, nonce) = find_vesting_signer_address(&program_id, &address);
+        assert_eq!(
+            Pubkey::create_program_address(&[address.as_ref(), &[nonce]], &program_id).unwrap(),
+            signer
+        );
+    }
+
+    #[test]
+    fn test_version_header() {
+        let state = vesting_state();
+        let mut buf = vec![0u8; VestingState::LEN];
+        state.pack_into_slice(&mut buf);
+
+        assert_eq!(buf[DISC], VESTING_DISCRIMINATOR);
+        assert_eq!(VestingState::version(&buf), Some(VESTING_VERSION));
+
+        buf[VERSION] = VESTING_VERSION + 1;
+        assert_eq!(VestingState::version(&buf), Some(VESTING_VERSION + 1));
+        assert_eq!(
+            VestingState::unpack(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+
+        buf[DISC] = b'M';
+        assert_eq!(VestingState::version(&buf), None);
+        assert!(VestingState::unpack(&buf).is_err());
+
+        let blank = vec![0u8; VestingState::LEN];
+        assert_eq!(
+            VestingState::unpack_unchecked(&blank).unwrap(),
+            VestingState::default()
+        );
+    }
+
+    #[test]
+    fn test_unpack_versioned() {
+        let state = vesting_state();
+        let mut buf = vec![0u8; VestingState::LEN];
+        state.pack_into_slice(&mut buf);
+
+        // The first release packed these fields in this order.
+        let mut legacy = vec![state.is_initialized as u8];
+        for key in [
+            state.authority,
+            state.beneficiary,
+            state.vault,
+            state.mint,
+            state.grantor,
+            state.metadata,
+        ] {
+            legacy.extend_from_slice(key.as_ref());
+        }
+        for value in [
+            state.outstanding,
+            state.start_balance,
+            state.created_ts,
+            state.start_ts,
+            state.end_ts,
+            state.period_count,
+        ] {
+            legacy.extend_from_slice(&value.to_le_bytes());
+        }
+        legacy.push(state.nonce);
+        assert_eq!(legacy.len(), 242);
+
+        assert_eq!(VestingState::version(&legacy), Some(0));
+        assert_eq!(
+            VestingState::unpack_versioned(0, &legacy).unwrap(),
+            VestingState {
+                is_initialized: true,
+                authority: state.authority,
+                beneficiary: state.beneficiary,
+                vault: state.vault,
+                mint: state.mint,
+                grantor: state.grantor,
+                metadata: state.metadata,
+                rent_receiver: state.authority,
+                outstanding: state.outstanding,
+                start_balance: state.start_balance,
+                created_ts: state.created_ts,
+                start_ts: state.start_ts,
+                end_ts: state.end_ts,
+                period_count: state.period_count,
+                nonce: state.nonce,
+                ..VestingState::default()
+            }
+        );
+
+        assert_eq!(
+            VestingState::unpack_versioned(VESTING_VERSION, &buf).unwrap(),
+            state
+        );
+        assert!(VestingState::unpack_versioned(0, &buf).is_err());
+        assert!(VestingState::unpack_versioned(0, &legacy[..241]).is_err());
+        assert!(VestingState::unpack_versioned(VESTING_VERSION + 1, &buf).is_err());
+    }
+
+    #[test]
+    fn test_unpack_wrong_len() {
+        let buf = vec![0u8; VestingState::LEN - 1];
+        assert_eq!(
+            VestingState::unpack_unchecked(&buf).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+}